@@ -4,8 +4,11 @@ use std::str::FromStr;
 
 use anyhow::anyhow;
 use anyhow::Result;
+use clap::builder::PossibleValue;
 use clap::ValueEnum;
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
+use kmeans_color_gpu::image::copied_pixel;
+use kmeans_color_gpu::swatch::{load_swatch, SwatchFormat};
 use kmeans_color_gpu::RGBA8;
 use regex::Regex;
 
@@ -21,9 +24,8 @@ pub struct Cli {
 pub enum Commands {
     /// Quantized the image then output the reduced palette.
     Palette {
-        /// Color count of the generated palette
-        #[clap(short, long="colorcount", value_parser = validate_k)]
-        color_count: u32,
+        #[clap(flatten)]
+        color_count: ColorCountArgs,
         /// Input image file
         #[clap(short, long, value_parser = validate_filenames)]
         input: PathBuf,
@@ -33,9 +35,34 @@ pub enum Commands {
         /// Algorithm to use for palette reduction
         #[clap(value_enum, short, long, default_value_t=Algorithm::Kmeans)]
         algo: Algorithm,
+        #[clap(flatten)]
+        kmeans: KmeansArgs,
+        /// Black and white image of the same dimensions as the input: only the pixels under its white part are used to extract the palette
+        #[clap(long, value_parser = validate_filenames)]
+        mask: Option<PathBuf>,
+        #[clap(flatten)]
+        sampling: SamplingArgs,
         /// Each color will be represented by a square of <SIZE x SIZE>. Between 1 and 60
         #[clap(short, long, default_value_t = 40, value_parser = clap::value_parser!(u32).range(1..=60))]
         size: u32,
+        /// File format of the palette
+        #[clap(value_enum, short, long, default_value_t = PaletteFormat::Png)]
+        format: PaletteFormat,
+        /// Print the share of the image covered by each color, and make the width of each color in the png palette match it
+        #[clap(long)]
+        coverage: bool,
+        /// How the alpha channel is handled. Fully transparent pixels are left out of the palette
+        #[clap(value_enum, long = "alpha", default_value_t = AlphaMode::Preserve)]
+        alpha_mode: AlphaMode,
+        /// Reserve a fully transparent palette entry for transparent pixels
+        #[clap(long)]
+        reserve_transparent: bool,
+        /// Color space used to compare colors
+        #[clap(value_enum, long = "colorspace", default_value_t = ColorSpace::Lab)]
+        color_space: ColorSpace,
+        /// Formula used to measure the distance between two colors, cie94 in lab and euclidean in the other color spaces if missing
+        #[clap(value_enum, long = "metric")]
+        distance_metric: Option<DistanceMetric>,
     },
     /// Find colors in image that are closest to the replacements, and swap them.
     Find {
@@ -45,18 +72,50 @@ pub enum Commands {
         /// Optional output image file
         #[clap(short, long, value_parser)]
         output: Option<PathBuf>,
-        /// List of RGB replacement colors formatted as "#RRGGBB,#RRGGBB" or path to a palette image
+        /// List of RGB replacement colors formatted as "#RRGGBB,#RRGGBB", or path to a palette image or a .gpl, .ase, .act, .pal, .txt, .hex or .json palette file
         #[clap(short, long, value_parser = validate_palette)]
         palette: Palette,
+        /// Black and white image of the same dimensions as the input: only the pixels under its white part are swapped
+        #[clap(long, value_parser = validate_filenames)]
+        mask: Option<PathBuf>,
+        /// What happens to the pixels left out by --mask
+        #[clap(value_enum, long, default_value_t = MaskedPixels::Keep)]
+        masked_pixels: MaskedPixels,
         /// Mix function to apply on the result
         #[clap(value_enum, short, long, default_value_t=ReduceMode::Replace)]
         mode: ReduceMode,
+        /// Threshold matrix used by the dither mode
+        #[clap(value_enum, long = "matrix", default_value_t = DitherMatrix::Bayer4)]
+        dither_matrix: DitherMatrix,
+        /// Threshold texture, like blue noise, used by the dither mode instead of the matrix
+        #[clap(long = "blue-noise", value_parser = validate_filenames, conflicts_with = "dither_matrix")]
+        blue_noise: Option<PathBuf>,
+        /// Strength of the dither and error diffusion modes, between 0 and 1
+        #[clap(long, default_value_t = 1.0, value_parser = validate_strength)]
+        strength: f32,
+        /// Spread of the dither mode in the color space, computed from the palette if missing. Not used by error diffusion
+        #[clap(long, value_parser = validate_threshold)]
+        threshold: Option<f32>,
+        /// How the alpha channel is handled. Fully transparent pixels are left out of the palette
+        #[clap(value_enum, long = "alpha", default_value_t = AlphaMode::Preserve)]
+        alpha_mode: AlphaMode,
+        /// Reserve a fully transparent palette entry for transparent pixels
+        #[clap(long)]
+        reserve_transparent: bool,
+        /// Color space used to compare colors
+        #[clap(value_enum, long = "colorspace", default_value_t = ColorSpace::Lab)]
+        color_space: ColorSpace,
+        /// Formula used to measure the distance between two colors, cie94 in lab and euclidean in the other color spaces if missing
+        #[clap(value_enum, long = "metric")]
+        distance_metric: Option<DistanceMetric>,
+        /// Save a palette based png, or a gif if the output ends with .gif
+        #[clap(long)]
+        indexed: bool,
     },
     /// Quantized the image then replaces it's resulting color.
     Reduce {
-        /// Color count of the generated palette
-        #[clap(short, long="colorcount", value_parser = validate_k)]
-        color_count: u32,
+        #[clap(flatten)]
+        color_count: ColorCountArgs,
         /// Input image file
         #[clap(short, long, value_parser = validate_filenames)]
         input: PathBuf,
@@ -66,9 +125,55 @@ pub enum Commands {
         /// Algorithm to use for palette reduction
         #[clap(value_enum, short, long, default_value_t=Algorithm::Kmeans)]
         algo: Algorithm,
+        #[clap(flatten)]
+        kmeans: KmeansArgs,
+        /// Black and white image of the same dimensions as the input: only the pixels under its white part are used to extract the palette
+        #[clap(long, value_parser = validate_filenames)]
+        mask: Option<PathBuf>,
+        /// What happens to the pixels left out by --mask
+        #[clap(value_enum, long, default_value_t = MaskedPixels::Keep)]
+        masked_pixels: MaskedPixels,
+        #[clap(flatten)]
+        sampling: SamplingArgs,
         /// Mix function to apply on the result
         #[clap(value_enum, short, long, default_value_t=ReduceMode::Replace)]
         mode: ReduceMode,
+        /// Threshold matrix used by the dither mode
+        #[clap(value_enum, long = "matrix", default_value_t = DitherMatrix::Bayer4)]
+        dither_matrix: DitherMatrix,
+        /// Threshold texture, like blue noise, used by the dither mode instead of the matrix
+        #[clap(long = "blue-noise", value_parser = validate_filenames, conflicts_with = "dither_matrix")]
+        blue_noise: Option<PathBuf>,
+        /// Strength of the dither and error diffusion modes, between 0 and 1
+        #[clap(long, default_value_t = 1.0, value_parser = validate_strength)]
+        strength: f32,
+        /// Spread of the dither mode in the color space, computed from the palette if missing. Not used by error diffusion
+        #[clap(long, value_parser = validate_threshold)]
+        threshold: Option<f32>,
+        /// How the alpha channel is handled. Fully transparent pixels are left out of the palette
+        #[clap(value_enum, long = "alpha", default_value_t = AlphaMode::Preserve)]
+        alpha_mode: AlphaMode,
+        /// Reserve a fully transparent palette entry for transparent pixels
+        #[clap(long)]
+        reserve_transparent: bool,
+        /// Color space used to compare colors
+        #[clap(value_enum, long = "colorspace", default_value_t = ColorSpace::Lab)]
+        color_space: ColorSpace,
+        /// Formula used to measure the distance between two colors, cie94 in lab and euclidean in the other color spaces if missing
+        #[clap(value_enum, long = "metric")]
+        distance_metric: Option<DistanceMetric>,
+        /// Save a palette based png, or a gif if the output ends with .gif
+        #[clap(long)]
+        indexed: bool,
+        /// Split the palette into sub-palettes of this many colors, each tile of the image using a single one, like on the NES or the Game Boy Color. Needs a color count
+        #[clap(long, value_parser = validate_k, requires = "sub_palettes", conflicts_with_all = ["mode", "auto"])]
+        tile_colors: Option<u32>,
+        /// How many sub-palettes the tiles pick from, with --tile-colors
+        #[clap(long, value_parser = validate_k, requires = "tile_colors")]
+        sub_palettes: Option<u32>,
+        /// Width and height of the tiles, with --tile-colors
+        #[clap(value_enum, long, default_value_t = TileSize::Eight)]
+        tile_size: TileSize,
     },
 }
 
@@ -105,11 +210,187 @@ impl Display for Extension {
     }
 }
 
+/// A strip of colored squares, or a swatch file, that the library writes.
+#[derive(Clone, Copy)]
+pub enum PaletteFormat {
+    Png,
+    Swatch(SwatchFormat),
+}
+
+impl PaletteFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            PaletteFormat::Png => "png",
+            PaletteFormat::Swatch(format) => format.extension(),
+        }
+    }
+}
+
+impl ValueEnum for PaletteFormat {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[
+            PaletteFormat::Png,
+            PaletteFormat::Swatch(SwatchFormat::Gpl),
+            PaletteFormat::Swatch(SwatchFormat::Ase),
+            PaletteFormat::Swatch(SwatchFormat::Act),
+            PaletteFormat::Swatch(SwatchFormat::Pal),
+            PaletteFormat::Swatch(SwatchFormat::PaintNet),
+            PaletteFormat::Swatch(SwatchFormat::Hex),
+            PaletteFormat::Swatch(SwatchFormat::Css),
+            PaletteFormat::Swatch(SwatchFormat::Json),
+        ]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        let help = match self {
+            PaletteFormat::Png => "Strip of colored squares",
+            PaletteFormat::Swatch(SwatchFormat::Gpl) => "GIMP palette",
+            PaletteFormat::Swatch(SwatchFormat::Ase) => "Adobe Swatch Exchange",
+            PaletteFormat::Swatch(SwatchFormat::Act) => "Adobe Color Table",
+            PaletteFormat::Swatch(SwatchFormat::Pal) => "JASC palette",
+            PaletteFormat::Swatch(SwatchFormat::PaintNet) => "Paint.NET palette",
+            PaletteFormat::Swatch(SwatchFormat::Hex) => "Hex colors, one per line",
+            PaletteFormat::Swatch(SwatchFormat::Css) => "CSS custom properties",
+            PaletteFormat::Swatch(SwatchFormat::Json) => "Lospec style JSON",
+        };
+        Some(PossibleValue::new(self.extension()).help(help))
+    }
+}
+
+/// The color count, or how to pick it.
+#[derive(Args)]
+pub struct ColorCountArgs {
+    /// Color count of the generated palette
+    #[clap(short, long="colorcount", value_parser = validate_k, required_unless_present = "auto")]
+    pub color_count: Option<u32>,
+    /// Pick the color count automatically with k-means, between --min-colors and --max-colors
+    #[clap(value_enum, long, conflicts_with = "color_count")]
+    pub auto: Option<ColorCountSelection>,
+    /// Smallest color count tried by --auto
+    #[clap(long, default_value_t = 2, value_parser = validate_k)]
+    pub min_colors: u32,
+    /// Largest color count tried by --auto
+    #[clap(long, default_value_t = 16, value_parser = validate_k)]
+    pub max_colors: u32,
+    /// Distance under which every pixel should be from its color, for --auto max-delta-e
+    #[clap(long, default_value_t = 10.0, value_parser = validate_threshold)]
+    pub max_delta_e: f32,
+}
+
+/// How k-means learns the palette.
+#[derive(Args)]
+pub struct KmeansArgs {
+    /// Seed picking the first k-means centroid, for reproducible palettes
+    #[clap(long, default_value_t = 0)]
+    pub seed: u64,
+    /// Maximum number of k-means iterations
+    #[clap(long, default_value_t = 128, value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_iterations: u32,
+    /// Number of k-means iterations between two convergence checks
+    #[clap(long, default_value_t = 8, value_parser = clap::value_parser!(u32).range(1..))]
+    pub check_interval: u32,
+    /// Distance under which k-means centroids are considered stable, depends on the color space if missing. Not used by mini-batch k-means
+    #[clap(long, value_parser = validate_tolerance)]
+    pub tolerance: Option<f32>,
+    /// Colors the k-means centroids start from, formatted as "#RRGGBB,#RRGGBB", or path to a palette image or palette file
+    #[clap(long, value_parser = validate_palette)]
+    pub initial_palette: Option<Palette>,
+    /// How the k-means centroids that aren't fixed or initial colors start
+    #[clap(value_enum, long = "init", default_value_t = Initialization::KmeansPlusPlus)]
+    pub initialization: Initialization,
+    /// Colors kept in the palette as is, formatted as "#RRGGBB,#RRGGBB", or path to a palette image or palette file. They count towards the color count
+    #[clap(long = "fixed", value_parser = validate_palette)]
+    pub fixed_palette: Option<Palette>,
+    /// Run mini-batch k-means on batches of this many random pixels from the full resolution image, for very large images
+    #[clap(long, value_parser = clap::value_parser!(u32).range(1..), conflicts_with_all = ["sample_size", "sample_pixels", "full_resolution"])]
+    pub batch_size: Option<u32>,
+    /// Grayscale image of the same dimensions as the input, telling how much each pixel counts for k-means, like a saliency map or a face mask. Black pixels are ignored
+    #[clap(long, value_parser = validate_filenames)]
+    pub weights: Option<PathBuf>,
+}
+
+impl TryFrom<KmeansArgs> for kmeans_color_gpu::KmeansOptions {
+    type Error = anyhow::Error;
+
+    fn try_from(kmeans: KmeansArgs) -> Result<Self> {
+        let weights = kmeans
+            .weights
+            .map(|path| -> Result<_> {
+                let image = image::open(path)?.to_rgba8();
+                Ok(copied_pixel(image.dimensions(), image.as_raw()))
+            })
+            .transpose()?;
+
+        Ok(kmeans_color_gpu::KmeansOptions {
+            seed: kmeans.seed,
+            max_iterations: kmeans.max_iterations,
+            check_interval: kmeans.check_interval,
+            tolerance: kmeans.tolerance,
+            initial_colors: kmeans
+                .initial_palette
+                .map_or(vec![], |palette| palette.colors),
+            initialization: kmeans.initialization.into(),
+            fixed_colors: kmeans
+                .fixed_palette
+                .map_or(vec![], |palette| palette.colors),
+            batch_size: kmeans.batch_size,
+            weights,
+        })
+    }
+}
+
+/// Which pixels the palette is extracted from.
+#[derive(Args)]
+pub struct SamplingArgs {
+    /// Downsample the image so that its longest side fits in this many pixels before extracting the palette
+    #[clap(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub sample_size: Option<u32>,
+    /// Downsample the image to at most this many pixels before extracting the palette
+    #[clap(long, value_parser = clap::value_parser!(u32).range(1..), conflicts_with = "sample_size")]
+    pub sample_pixels: Option<u32>,
+    /// Extract the palette from every pixel of the image
+    #[clap(long, conflicts_with_all = ["sample_size", "sample_pixels"])]
+    pub full_resolution: bool,
+    /// How pixels are combined when downsampling the image
+    #[clap(value_enum, long, default_value_t = ResizeFilter::Bilinear)]
+    pub resize_filter: ResizeFilter,
+}
+
+impl From<SamplingArgs> for kmeans_color_gpu::SamplingOptions {
+    fn from(sampling: SamplingArgs) -> Self {
+        let sample = match (sampling.sample_size, sampling.sample_pixels) {
+            _ if sampling.full_resolution => kmeans_color_gpu::Sampling::Full,
+            (Some(sample_size), _) => kmeans_color_gpu::Sampling::MaxDimension(sample_size),
+            (_, Some(sample_pixels)) => kmeans_color_gpu::Sampling::PixelBudget(sample_pixels),
+            (None, None) => kmeans_color_gpu::Sampling::Auto,
+        };
+
+        kmeans_color_gpu::SamplingOptions {
+            sampling: sample,
+            filter: sampling.resize_filter.into(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ColorCountSelection {
+    /// Where adding colors stops paying off
+    Elbow,
+    /// Where colors are the most distinct from each other
+    Silhouette,
+    /// Smallest count keeping every pixel within --max-delta-e of its color
+    MaxDeltaE,
+}
+
 #[derive(Clone, Copy, ValueEnum)]
 pub enum ReduceMode {
     Replace,
     Dither,
     Meld,
+    FloydSteinberg,
+    Atkinson,
+    JarvisJudiceNinke,
+    Sierra,
 }
 
 impl From<ReduceMode> for kmeans_color_gpu::ReduceMode {
@@ -118,6 +399,114 @@ impl From<ReduceMode> for kmeans_color_gpu::ReduceMode {
             ReduceMode::Replace => kmeans_color_gpu::ReduceMode::Replace,
             ReduceMode::Dither => kmeans_color_gpu::ReduceMode::Dither,
             ReduceMode::Meld => kmeans_color_gpu::ReduceMode::Meld,
+            ReduceMode::FloydSteinberg => kmeans_color_gpu::ReduceMode::FloydSteinberg,
+            ReduceMode::Atkinson => kmeans_color_gpu::ReduceMode::Atkinson,
+            ReduceMode::JarvisJudiceNinke => kmeans_color_gpu::ReduceMode::JarvisJudiceNinke,
+            ReduceMode::Sierra => kmeans_color_gpu::ReduceMode::Sierra,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum DitherMatrix {
+    Bayer2,
+    Bayer4,
+    Bayer8,
+    Bayer16,
+}
+
+impl From<DitherMatrix> for kmeans_color_gpu::DitherMatrix {
+    fn from(dither_matrix: DitherMatrix) -> Self {
+        match dither_matrix {
+            DitherMatrix::Bayer2 => kmeans_color_gpu::DitherMatrix::Bayer2,
+            DitherMatrix::Bayer4 => kmeans_color_gpu::DitherMatrix::Bayer4,
+            DitherMatrix::Bayer8 => kmeans_color_gpu::DitherMatrix::Bayer8,
+            DitherMatrix::Bayer16 => kmeans_color_gpu::DitherMatrix::Bayer16,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ResizeFilter {
+    Bilinear,
+    Area,
+}
+
+impl From<ResizeFilter> for kmeans_color_gpu::ResizeFilter {
+    fn from(resize_filter: ResizeFilter) -> Self {
+        match resize_filter {
+            ResizeFilter::Bilinear => kmeans_color_gpu::ResizeFilter::Bilinear,
+            ResizeFilter::Area => kmeans_color_gpu::ResizeFilter::Area,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum MaskedPixels {
+    /// Left as they are
+    Keep,
+    /// Remapped to the palette of the masked in pixels
+    Remap,
+    /// Remapped to a palette of their own
+    Separate,
+}
+
+impl From<MaskedPixels> for kmeans_color_gpu::MaskedPixels {
+    fn from(masked_pixels: MaskedPixels) -> Self {
+        match masked_pixels {
+            MaskedPixels::Keep => kmeans_color_gpu::MaskedPixels::Keep,
+            MaskedPixels::Remap => kmeans_color_gpu::MaskedPixels::Remap,
+            MaskedPixels::Separate => kmeans_color_gpu::MaskedPixels::Separate,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum TileSize {
+    #[value(name = "8")]
+    Eight,
+    #[value(name = "16")]
+    Sixteen,
+}
+
+impl From<TileSize> for kmeans_color_gpu::TileSize {
+    fn from(tile_size: TileSize) -> Self {
+        match tile_size {
+            TileSize::Eight => kmeans_color_gpu::TileSize::Eight,
+            TileSize::Sixteen => kmeans_color_gpu::TileSize::Sixteen,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Initialization {
+    /// Pixels picked at random, each new one more likely the further it is from the others
+    #[value(name = "kmeans++")]
+    KmeansPlusPlus,
+    /// The palette of the wu algorithm
+    Wu,
+}
+
+impl From<Initialization> for kmeans_color_gpu::Initialization {
+    fn from(initialization: Initialization) -> Self {
+        match initialization {
+            Initialization::KmeansPlusPlus => kmeans_color_gpu::Initialization::KmeansPlusPlus,
+            Initialization::Wu => kmeans_color_gpu::Initialization::Wu,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum AlphaMode {
+    Preserve,
+    Quantize,
+}
+
+impl From<AlphaMode> for kmeans_color_gpu::AlphaMode {
+    fn from(alpha_mode: AlphaMode) -> Self {
+        match alpha_mode {
+            AlphaMode::Preserve => kmeans_color_gpu::AlphaMode::Preserve,
+            AlphaMode::Quantize => kmeans_color_gpu::AlphaMode::Quantize,
         }
     }
 }
@@ -126,6 +515,8 @@ impl From<ReduceMode> for kmeans_color_gpu::ReduceMode {
 pub enum ColorSpace {
     Lab,
     Rgb,
+    Oklab,
+    LinearRgb,
 }
 
 impl From<ColorSpace> for kmeans_color_gpu::ColorSpace {
@@ -133,6 +524,28 @@ impl From<ColorSpace> for kmeans_color_gpu::ColorSpace {
         match color_space {
             ColorSpace::Lab => kmeans_color_gpu::ColorSpace::Lab,
             ColorSpace::Rgb => kmeans_color_gpu::ColorSpace::Rgb,
+            ColorSpace::Oklab => kmeans_color_gpu::ColorSpace::Oklab,
+            ColorSpace::LinearRgb => kmeans_color_gpu::ColorSpace::LinearRgb,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum DistanceMetric {
+    Cie94,
+    Ciede2000,
+    Euclidean,
+    /// Euclidean distance in lab
+    Cie76,
+}
+
+impl From<DistanceMetric> for kmeans_color_gpu::DistanceMetric {
+    fn from(distance_metric: DistanceMetric) -> Self {
+        match distance_metric {
+            DistanceMetric::Cie94 => kmeans_color_gpu::DistanceMetric::Cie94,
+            DistanceMetric::Ciede2000 => kmeans_color_gpu::DistanceMetric::Ciede2000,
+            DistanceMetric::Euclidean => kmeans_color_gpu::DistanceMetric::Euclidean,
+            DistanceMetric::Cie76 => kmeans_color_gpu::DistanceMetric::Cie76,
         }
     }
 }
@@ -141,6 +554,10 @@ impl From<ColorSpace> for kmeans_color_gpu::ColorSpace {
 pub enum Algorithm {
     Kmeans,
     Octree,
+    MedianCut,
+    Wu,
+    #[clap(name = "neuquant")]
+    NeuQuant,
 }
 
 impl From<Algorithm> for kmeans_color_gpu::Algorithm {
@@ -148,6 +565,9 @@ impl From<Algorithm> for kmeans_color_gpu::Algorithm {
         match algo {
             Algorithm::Kmeans => kmeans_color_gpu::Algorithm::Kmeans,
             Algorithm::Octree => kmeans_color_gpu::Algorithm::Octree,
+            Algorithm::MedianCut => kmeans_color_gpu::Algorithm::MedianCut,
+            Algorithm::Wu => kmeans_color_gpu::Algorithm::Wu,
+            Algorithm::NeuQuant => kmeans_color_gpu::Algorithm::NeuQuant,
         }
     }
 }
@@ -170,6 +590,33 @@ fn validate_k(s: &str) -> Result<u32> {
     }
 }
 
+fn validate_strength(s: &str) -> Result<f32> {
+    let strength: f32 = s.parse()?;
+    if (0.0..=1.0).contains(&strength) {
+        Ok(strength)
+    } else {
+        Err(anyhow!("The strength should be between 0 and 1"))
+    }
+}
+
+fn validate_threshold(s: &str) -> Result<f32> {
+    let threshold: f32 = s.parse()?;
+    if threshold >= 0.0 {
+        Ok(threshold)
+    } else {
+        Err(anyhow!("The threshold can't be negative"))
+    }
+}
+
+fn validate_tolerance(s: &str) -> Result<f32> {
+    let tolerance: f32 = s.parse()?;
+    if tolerance >= 0.0 {
+        Ok(tolerance)
+    } else {
+        Err(anyhow!("The tolerance can't be negative"))
+    }
+}
+
 fn validate_filenames(s: &str) -> Result<PathBuf> {
     if s.len() > 4 && (s.ends_with(".png") || s.ends_with(".jpg")) {
         Ok(PathBuf::from(s))
@@ -184,11 +631,20 @@ fn validate_palette(s: &str) -> Result<Palette> {
         parse_colors(s)
     } else {
         let path = PathBuf::from(s);
+        let is_swatch = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(SwatchFormat::from_extension)
+            .is_some();
         if s.len() > 4 && (s.ends_with(".png") || s.ends_with(".jpg")) && path.exists() {
             parse_palette(&path)
+        } else if is_swatch && path.exists() {
+            Ok(Palette {
+                colors: load_swatch(&path)?,
+            })
         } else {
             Err(anyhow!(
-                "The palette should be a path to an image file, or defined as \"#RRGGBB,#RRGGBB,#RRGGBB\""
+                "The palette should be a path to an image or palette file, or defined as \"#RRGGBB,#RRGGBB,#RRGGBB\""
             ))
         }
     }
@@ -250,6 +706,30 @@ mod tests {
         assert!(validate_k("0").is_err());
     }
 
+    #[test]
+    fn test_validate_strength() {
+        assert!(validate_strength("0").is_ok());
+        assert!(validate_strength("0.5").is_ok());
+        assert!(validate_strength("1").is_ok());
+        assert!(validate_strength("1.5").is_err());
+        assert!(validate_strength("-0.1").is_err());
+        assert!(validate_strength("strong").is_err());
+    }
+
+    #[test]
+    fn test_validate_threshold() {
+        assert!(validate_threshold("0").is_ok());
+        assert!(validate_threshold("12.5").is_ok());
+        assert!(validate_threshold("-1").is_err());
+    }
+
+    #[test]
+    fn test_validate_tolerance() {
+        assert!(validate_tolerance("0").is_ok());
+        assert!(validate_tolerance("0.5").is_ok());
+        assert!(validate_tolerance("-0.5").is_err());
+    }
+
     #[test]
     fn test_validate_filename() {
         assert!(validate_filenames("jog.png").is_ok());
@@ -0,0 +1,144 @@
+use std::{borrow::Cow, fs::File, io::BufWriter, path::Path};
+
+use anyhow::{anyhow, Result};
+use kmeans_color_gpu::image::{IndexedImage, Indices};
+
+/// Saves the image with its palette, as a GIF if the path ends with `.gif`, as a PNG otherwise.
+pub fn save_indexed<P>(path: P, image: &IndexedImage) -> Result<()>
+where
+    P: AsRef<Path>,
+{
+    let indices = match image.indices() {
+        Indices::U8(indices) => indices,
+        Indices::U16(_) => {
+            return Err(anyhow!(
+                "Indexed images are limited to 256 colors, the palette has {}",
+                image.palette().len()
+            ))
+        }
+    };
+
+    let path = path.as_ref();
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("gif") => save_gif(path, image, indices),
+        Some("png") | None => save_png(path, image, indices),
+        Some(extension) => Err(anyhow!(
+            "Indexed images can only be saved as png or gif, not {extension}"
+        )),
+    }
+}
+
+fn save_png(path: &Path, image: &IndexedImage, indices: &[u8]) -> Result<()> {
+    let (width, height) = image.dimensions();
+    let palette = image.palette();
+
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), width, height);
+    encoder.set_color(png::ColorType::Indexed);
+
+    let bit_depth = match palette.len() {
+        0..=2 => png::BitDepth::One,
+        3..=4 => png::BitDepth::Two,
+        5..=16 => png::BitDepth::Four,
+        _ => png::BitDepth::Eight,
+    };
+    encoder.set_depth(bit_depth);
+
+    encoder.set_palette(
+        palette
+            .iter()
+            .flat_map(|color| [color.r, color.g, color.b])
+            .collect::<Vec<_>>(),
+    );
+
+    // The tRNS chunk can stop at the last translucent entry, the others being opaque.
+    if let Some(last) = palette.iter().rposition(|color| color.a != 255) {
+        encoder.set_trns(
+            palette[..=last]
+                .iter()
+                .map(|color| color.a)
+                .collect::<Vec<_>>(),
+        );
+    }
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&pack_rows(indices, width as usize, bit_depth as usize))?;
+    writer.finish()?;
+
+    Ok(())
+}
+
+/// Packs the indices using the given bit depth, each row starting on a new byte.
+fn pack_rows(indices: &[u8], width: usize, bit_depth: usize) -> Vec<u8> {
+    if bit_depth == 8 {
+        return indices.to_vec();
+    }
+
+    let pixels_per_byte = 8 / bit_depth;
+    indices
+        .chunks(width)
+        .flat_map(|row| {
+            row.chunks(pixels_per_byte).map(|pixels| {
+                pixels
+                    .iter()
+                    .enumerate()
+                    .fold(0u8, |byte, (position, &index)| {
+                        byte | index << (8 - bit_depth * (position + 1))
+                    })
+            })
+        })
+        .collect()
+}
+
+fn save_gif(path: &Path, image: &IndexedImage, indices: &[u8]) -> Result<()> {
+    let (width, height) = image.dimensions();
+    let width = u16::try_from(width).map_err(|_| anyhow!("Image too wide for a gif"))?;
+    let height = u16::try_from(height).map_err(|_| anyhow!("Image too tall for a gif"))?;
+    let palette = image.palette();
+
+    let global_palette: Vec<u8> = palette
+        .iter()
+        .flat_map(|color| [color.r, color.g, color.b])
+        .collect();
+
+    let mut encoder = gif::Encoder::new(
+        BufWriter::new(File::create(path)?),
+        width,
+        height,
+        &global_palette,
+    )?;
+
+    // Gif only knows about a single, fully transparent, color.
+    let transparent = palette
+        .iter()
+        .position(|color| color.a == 0)
+        .map(|index| index as u8);
+
+    let frame = gif::Frame {
+        width,
+        height,
+        transparent,
+        buffer: Cow::Borrowed(indices),
+        ..Default::default()
+    };
+    encoder.write_frame(&frame)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::pack_rows;
+
+    #[test]
+    fn test_pack_rows() {
+        let indices = [1, 0, 1, 1, 0, 1, 0, 0, 1, 1];
+
+        assert_eq!(pack_rows(&indices, 10, 1), vec![0b1011_0100, 0b1100_0000]);
+        assert_eq!(pack_rows(&indices, 5, 1), vec![0b1011_0000, 0b1001_1000]);
+        assert_eq!(
+            pack_rows(&[3, 1, 2, 0, 1], 5, 2),
+            vec![0b1101_1000, 0b0100_0000]
+        );
+        assert_eq!(pack_rows(&[15, 1, 2], 3, 4), vec![0xF1, 0x20]);
+    }
+}
@@ -1,16 +1,25 @@
-use anyhow::{Ok, Result};
-use args::{Cli, Commands, Extension, Palette};
+use anyhow::{anyhow, Ok, Result};
+use args::{Cli, ColorCountArgs, Commands, DitherMatrix, Extension, Palette, PaletteFormat};
 use clap::Parser;
 use image::{ImageBuffer, Rgba, RgbaImage};
-use kmeans_color_gpu::{image::Image, Algorithm, ImageProcessor, ReduceMode, RGBA8};
+use indexed::save_indexed;
+use kmeans_color_gpu::{
+    image::{borrowed_pixel, copied_pixel, Image},
+    swatch::{hex_color, save_swatch},
+    Algorithm, AlphaOptions, ColorCountReport, ColorCountSelection, DitherOptions, ImageProcessor,
+    MaskOptions, MaskedPixels, Options, PaletteEntry, ReduceMode, ThresholdTexture, TileOptions,
+    RGBA8,
+};
 use pollster::FutureExt;
 use std::{
     borrow::Cow,
+    ops::RangeInclusive,
     path::{Path, PathBuf},
     time::{SystemTime, UNIX_EPOCH},
 };
 
 mod args;
+mod indexed;
 
 fn main() -> Result<()> {
     env_logger::init();
@@ -23,51 +32,258 @@ fn main() -> Result<()> {
             input,
             output,
             algo,
+            kmeans,
+            mask,
+            sampling,
+            size,
+            format,
+            coverage,
+            alpha_mode,
+            reserve_transparent,
+            color_space,
+            distance_metric,
+        } => palette_subcommand2(
+            color_count_mode(color_count)?,
+            input,
+            output,
+            Options {
+                algo: algo.into(),
+                kmeans: kmeans.try_into()?,
+                sampling: sampling.into(),
+                alpha: AlphaOptions {
+                    mode: alpha_mode.into(),
+                    reserve_transparent,
+                },
+                color_space: color_space.into(),
+                distance_metric: distance_metric.map(Into::into),
+                mask: mask_options(mask, Default::default())?,
+                ..Default::default()
+            },
             size,
-        } => palette_subcommand2(color_count, input, output, algo.into(), size).block_on(),
+            format,
+            coverage,
+        )
+        .block_on(),
         Commands::Find {
             input,
             output,
             palette,
+            mask,
+            masked_pixels,
             mode,
-        } => find_subcommand(input, output, palette, mode.into()).block_on(),
+            dither_matrix,
+            blue_noise,
+            strength,
+            threshold,
+            alpha_mode,
+            reserve_transparent,
+            color_space,
+            distance_metric,
+            indexed,
+        } => find_subcommand(
+            input,
+            output,
+            palette,
+            Options {
+                reduce_mode: mode.into(),
+                dither: dither_options(dither_matrix, blue_noise, strength, threshold)?,
+                alpha: AlphaOptions {
+                    mode: alpha_mode.into(),
+                    reserve_transparent,
+                },
+                color_space: color_space.into(),
+                distance_metric: distance_metric.map(Into::into),
+                mask: mask_options(mask, masked_pixels.into())?,
+                ..Default::default()
+            },
+            indexed,
+        )
+        .block_on(),
         Commands::Reduce {
             color_count,
             input,
             output,
             algo,
+            kmeans,
+            mask,
+            masked_pixels,
+            sampling,
             mode,
-        } => reduce_subcommand(color_count, input, output, algo.into(), mode.into()).block_on(),
+            dither_matrix,
+            blue_noise,
+            strength,
+            threshold,
+            alpha_mode,
+            reserve_transparent,
+            color_space,
+            distance_metric,
+            indexed,
+            tile_colors,
+            sub_palettes,
+            tile_size,
+        } => reduce_subcommand(
+            color_count_mode(color_count)?,
+            input,
+            output,
+            Options {
+                algo: algo.into(),
+                kmeans: kmeans.try_into()?,
+                sampling: sampling.into(),
+                reduce_mode: mode.into(),
+                dither: dither_options(dither_matrix, blue_noise, strength, threshold)?,
+                alpha: AlphaOptions {
+                    mode: alpha_mode.into(),
+                    reserve_transparent,
+                },
+                color_space: color_space.into(),
+                distance_metric: distance_metric.map(Into::into),
+                mask: mask_options(mask, masked_pixels.into())?,
+            },
+            indexed,
+            tile_colors
+                .zip(sub_palettes)
+                .map(|(colors_per_tile, sub_palette_count)| TileOptions {
+                    tile_size: tile_size.into(),
+                    colors_per_tile,
+                    sub_palette_count,
+                }),
+        )
+        .block_on(),
     }?;
 
     Ok(())
 }
 
+enum ColorCountMode {
+    Fixed(u32),
+    Auto(RangeInclusive<u32>, ColorCountSelection),
+}
+
+fn color_count_mode(
+    ColorCountArgs {
+        color_count,
+        auto,
+        min_colors,
+        max_colors,
+        max_delta_e,
+    }: ColorCountArgs,
+) -> Result<ColorCountMode> {
+    if let Some(color_count) = color_count {
+        return Ok(ColorCountMode::Fixed(color_count));
+    }
+    if min_colors > max_colors {
+        return Err(anyhow!(
+            "--min-colors ({min_colors}) can't be higher than --max-colors ({max_colors})"
+        ));
+    }
+
+    let selection = match auto {
+        Some(args::ColorCountSelection::Elbow) => ColorCountSelection::Elbow,
+        Some(args::ColorCountSelection::Silhouette) => ColorCountSelection::Silhouette,
+        Some(args::ColorCountSelection::MaxDeltaE) => ColorCountSelection::MaxDeltaE(max_delta_e),
+        None => return Err(anyhow!("Either a color count or --auto is needed")),
+    };
+    Ok(ColorCountMode::Auto(min_colors..=max_colors, selection))
+}
+
+fn auto_needs_kmeans() -> anyhow::Error {
+    anyhow!("The color count can only be picked automatically with k-means")
+}
+
+fn print_color_count_report(report: &ColorCountReport) {
+    for score in &report.scores {
+        println!(
+            "{} colors: mean distance {:.2}, max distance {:.2}, silhouette {:.3}",
+            score.color_count, score.mean_distance, score.max_distance, score.silhouette
+        );
+    }
+    println!("Chosen color count: {}", report.color_count);
+    if !report.target_met {
+        eprintln!(
+            "Warning: no color count keeps every pixel within --max-delta-e, using the largest one"
+        );
+    }
+}
+
 async fn palette_subcommand2(
-    color_count: u32,
+    color_count: ColorCountMode,
     input: PathBuf,
     output: Option<PathBuf>,
-    algo: Algorithm,
+    options: Options,
     size: u32,
+    format: PaletteFormat,
+    coverage: bool,
 ) -> Result<()> {
     let image = image::open(&input)?.to_rgba8();
     let image = to_lib_image(&image);
 
     let image_processor = ImageProcessor::new().await?;
 
-    let result = image_processor.palette(color_count, &image, algo).await?;
+    let (colors, color_count) = match (color_count, options.algo) {
+        (ColorCountMode::Fixed(color_count), Algorithm::Kmeans) => {
+            let (colors, report) = image_processor
+                .palette_kmeans(color_count, &image, &options)
+                .await?;
+            let status = if report.converged {
+                "converged"
+            } else {
+                "didn't converge"
+            };
+            println!("K-means: {status} after {} iterations", report.iterations);
+            (colors, color_count)
+        }
+        (ColorCountMode::Fixed(color_count), _) => {
+            let colors = image_processor
+                .palette(color_count, &image, &options)
+                .await?;
+            (colors, color_count)
+        }
+        (ColorCountMode::Auto(color_counts, selection), Algorithm::Kmeans) => {
+            let (colors, report) = image_processor
+                .palette_auto(color_counts, &selection, &image, &options)
+                .await?;
+            print_color_count_report(&report);
+            (colors, report.color_count)
+        }
+        (ColorCountMode::Auto(..), _) => return Err(auto_needs_kmeans()),
+    };
+    // Measuring the coverage remaps the whole image, only do it when asked.
+    let entries = if coverage {
+        Some(
+            image_processor
+                .palette_coverage(&image, &colors, &options)
+                .await?,
+        )
+    } else {
+        None
+    };
 
-    let path = palette_file_path(color_count, &input, &output, &algo, size)?;
-    save_palette(path, &result, size)?;
+    let path = palette_file_path(color_count, &input, &output, &options.algo, size, &format)?;
+    match format {
+        PaletteFormat::Png => save_palette(path, &colors, entries.as_deref(), size)?,
+        PaletteFormat::Swatch(format) => {
+            let name = input
+                .file_stem()
+                .expect("Expecting .jpg or .png files")
+                .to_string_lossy();
+            save_swatch(path, &colors, &name, &format)?
+        }
+    }
 
-    let colors = result
-        .into_iter()
-        .map(|color| format!("#{:02X}{:02X}{:02X}", color.r, color.g, color.b))
-        .collect::<Vec<_>>()
-        .join(",");
+    let colors = colors.iter().map(hex_color).collect::<Vec<_>>().join(",");
 
     println!("Palette: {colors}");
 
+    if let Some(entries) = entries {
+        let fractions = entries
+            .iter()
+            .map(|entry| format!("{:.1}%", entry.fraction * 100.0))
+            .collect::<Vec<_>>()
+            .join(",");
+
+        println!("Coverage: {fractions}");
+    }
+
     Ok(())
 }
 
@@ -75,14 +291,26 @@ async fn find_subcommand(
     input: PathBuf,
     output: Option<PathBuf>,
     palette: Palette,
-    reduce_mode: ReduceMode,
+    options: Options,
+    indexed: bool,
 ) -> Result<()> {
     let image = image::open(&input)?.to_rgba8();
     let image = to_lib_image(&image);
 
     let image_processor = ImageProcessor::new().await?;
+
+    if indexed {
+        let result = image_processor
+            .find_indexed(&image, &palette.colors, &options)
+            .await?;
+
+        let output_file =
+            find_file_path(&options.reduce_mode, &output, &input, &Some(Extension::Png))?;
+        return save_indexed(output_file, &result);
+    }
+
     let result = image_processor
-        .find(&image, &palette.colors, &reduce_mode)
+        .find(&image, &palette.colors, &options)
         .await?;
 
     let (width, height) = result.dimensions();
@@ -90,7 +318,7 @@ async fn find_subcommand(
     if let Some(output_image) =
         ImageBuffer::<Rgba<u8>, _>::from_raw(width, height, result.into_raw_pixels())
     {
-        let output_file = find_file_path(&reduce_mode, &output, &input, &None)?;
+        let output_file = find_file_path(&options.reduce_mode, &output, &input, &None)?;
         output_image.save(output_file)?;
     }
 
@@ -98,26 +326,120 @@ async fn find_subcommand(
 }
 
 async fn reduce_subcommand(
-    color_count: u32,
+    color_count: ColorCountMode,
     input: PathBuf,
     output: Option<PathBuf>,
-    algo: Algorithm,
-    reduce_mode: ReduceMode,
+    options: Options,
+    indexed: bool,
+    tile_options: Option<TileOptions>,
 ) -> Result<()> {
     let image = image::open(&input)?.to_rgba8();
     let image = to_lib_image(&image);
 
     let image_processor = ImageProcessor::new().await?;
-    let result = image_processor
-        .reduce(color_count, &image, &algo, &reduce_mode)
-        .await?;
+    // The palette picked automatically is the one the image is remapped to.
+    let (color_count, palette) = match (color_count, &options.algo) {
+        (ColorCountMode::Fixed(color_count), _) => (color_count, None),
+        (ColorCountMode::Auto(color_counts, selection), Algorithm::Kmeans) => {
+            if let Some(MaskOptions {
+                masked_pixels: MaskedPixels::Separate,
+                ..
+            }) = options.mask
+            {
+                return Err(anyhow!(
+                    "--auto picks a single palette, masked out pixels can't get their own"
+                ));
+            }
+            let (mut colors, report) = image_processor
+                .palette_auto(color_counts, &selection, &image, &options)
+                .await?;
+            print_color_count_report(&report);
+            // Find reserves the transparent entry again.
+            if options.alpha.reserve_transparent {
+                colors.pop();
+            }
+            (report.color_count, Some(colors))
+        }
+        (ColorCountMode::Auto(..), _) => return Err(auto_needs_kmeans()),
+    };
+    let output_file = reduce_file_path(
+        color_count,
+        &options.algo,
+        &options.reduce_mode,
+        &output,
+        &input,
+    )?;
+
+    if let Some(tile_options) = tile_options {
+        let result = image_processor
+            .reduce_tiles(color_count, &image, &tile_options, &options)
+            .await?;
+
+        for (index, sub_palette) in result.sub_palettes().iter().enumerate() {
+            let colors = sub_palette
+                .iter()
+                .map(hex_color)
+                .collect::<Vec<_>>()
+                .join(",");
+            println!("Sub-palette {index}: {colors}");
+        }
+        println!("Tile sub-palettes:");
+        for row in result
+            .tile_palettes()
+            .chunks(result.tile_columns() as usize)
+        {
+            let row = row
+                .iter()
+                .map(|index| index.to_string())
+                .collect::<Vec<_>>()
+                .join(" ");
+            println!("{row}");
+        }
+
+        if indexed {
+            return save_indexed(output_file, &result.to_indexed());
+        }
+        let reduced = result.to_image();
+        let (width, height) = reduced.dimensions();
+        if let Some(output_image) =
+            ImageBuffer::<Rgba<u8>, _>::from_raw(width, height, reduced.into_raw_pixels())
+        {
+            output_image.save(output_file)?;
+        }
+        return Ok(());
+    }
+
+    if indexed {
+        let result = match &palette {
+            Some(colors) => {
+                image_processor
+                    .find_indexed(&image, colors, &options)
+                    .await?
+            }
+            None => {
+                image_processor
+                    .reduce_indexed(color_count, &image, &options)
+                    .await?
+            }
+        };
+
+        return save_indexed(output_file, &result);
+    }
+
+    let result = match &palette {
+        Some(colors) => image_processor.find(&image, colors, &options).await?,
+        None => {
+            image_processor
+                .reduce(color_count, &image, &options)
+                .await?
+        }
+    };
 
     let (width, height) = result.dimensions();
 
     if let Some(output_image) =
         ImageBuffer::<Rgba<u8>, _>::from_raw(width, height, result.into_raw_pixels())
     {
-        let output_file = reduce_file_path(color_count, &algo, &reduce_mode, &output, &input)?;
         output_image.save(output_file)?;
     }
 
@@ -158,6 +480,7 @@ fn palette_file_path(
     output: &Option<PathBuf>,
     algo: &Algorithm,
     size: u32,
+    format: &PaletteFormat,
 ) -> Result<PathBuf> {
     if let Some(output) = output {
         return Ok(output.clone());
@@ -169,9 +492,12 @@ fn palette_file_path(
         .file_stem()
         .expect("Expecting .jpg or .png files")
         .to_string_lossy();
-    let extension = "png";
+    let extension = format.extension();
 
-    let filename = format!("{stem}-palette-c{k}-{algo}-s{size}.{extension}",);
+    let filename = match format {
+        PaletteFormat::Png => format!("{stem}-palette-c{k}-{algo}-s{size}.{extension}"),
+        _ => format!("{stem}-palette-c{k}-{algo}.{extension}"),
+    };
     let output_path = if let Some(parent) = parent {
         parent.join(filename)
     } else {
@@ -218,17 +544,44 @@ fn find_file_path(
     }
 }
 
-fn save_palette<P>(path: P, palette: &[RGBA8], size: u32) -> Result<()>
+/// Saves the colors as a strip of squares, or of rectangles as wide as their coverage when the
+/// entries are given.
+fn save_palette<P>(
+    path: P,
+    colors: &[RGBA8],
+    entries: Option<&[PaletteEntry]>,
+    size: u32,
+) -> Result<()>
 where
     P: AsRef<Path>,
 {
-    let width = palette.len() as u32 * size;
+    let width = colors.len() as u32 * size;
+
+    // Right edge of each color, either evenly spaced or following the coverage.
+    let edges: Vec<_> = match entries {
+        Some(entries) => {
+            let mut covered = 0.0;
+            entries
+                .iter()
+                .map(|entry| {
+                    covered += entry.fraction;
+                    (covered * width as f32).round() as u32
+                })
+                .collect()
+        }
+        None => (1..=colors.len() as u32)
+            .map(|index| index * size)
+            .collect(),
+    };
 
     let mut image_buffer: RgbaImage = image::ImageBuffer::new(width, size);
 
     for (x, _, pixel) in image_buffer.enumerate_pixels_mut() {
-        let index = (x / size) as usize;
-        let color = palette[index];
+        let index = edges
+            .iter()
+            .position(|&edge| x < edge)
+            .unwrap_or(colors.len() - 1);
+        let color = colors[index];
 
         *pixel = Rgba([color.r, color.g, color.b, color.a]);
     }
@@ -238,6 +591,44 @@ where
     Ok(())
 }
 
+fn dither_options(
+    dither_matrix: DitherMatrix,
+    blue_noise: Option<PathBuf>,
+    strength: f32,
+    threshold: Option<f32>,
+) -> Result<DitherOptions> {
+    let matrix = if let Some(blue_noise) = blue_noise {
+        let image = image::open(blue_noise)?.to_rgba8();
+        kmeans_color_gpu::DitherMatrix::BlueNoise(ThresholdTexture::new(&borrowed_pixel(
+            image.dimensions(),
+            image.as_raw(),
+        ))?)
+    } else {
+        dither_matrix.into()
+    };
+
+    Ok(DitherOptions {
+        matrix,
+        strength,
+        threshold,
+    })
+}
+
+fn load_image(path: Option<PathBuf>) -> Result<Option<Image<Vec<RGBA8>>>> {
+    path.map(|path| {
+        let image = image::open(path)?.to_rgba8();
+        Ok(copied_pixel(image.dimensions(), image.as_raw()))
+    })
+    .transpose()
+}
+
+fn mask_options(mask: Option<PathBuf>, masked_pixels: MaskedPixels) -> Result<Option<MaskOptions>> {
+    Ok(load_image(mask)?.map(|mask| MaskOptions {
+        mask,
+        masked_pixels,
+    }))
+}
+
 fn to_lib_image(image: &RgbaImage) -> Image<&[RGBA8]> {
     Image::new(image.dimensions(), bytemuck::cast_slice(image.as_raw()))
 }
@@ -1,7 +1,7 @@
 use std::{fs::File, path::Path, time::Instant};
 
 use gif::{Frame, Repeat};
-use kmeans_color_gpu::{image::Image, Algorithm, ImageProcessor, ReduceMode};
+use kmeans_color_gpu::{image::Image, ImageProcessor, Options};
 use pollster::FutureExt;
 
 fn main() {
@@ -31,7 +31,7 @@ fn main() {
 
     for c in 2..16 {
         let reduced = image_processor
-            .reduce(c, &image, &Algorithm::Kmeans, &ReduceMode::Replace)
+            .reduce(c, &image, &Options::default())
             .block_on()
             .unwrap();
 
@@ -1,7 +1,7 @@
 use std::{fs::File, path::Path, sync::Arc, thread, time::Instant};
 
 use gif::{Frame, Repeat};
-use kmeans_color_gpu::{image::copied_pixel, Algorithm, ImageProcessor};
+use kmeans_color_gpu::{image::copied_pixel, ImageProcessor, Options};
 use pollster::FutureExt;
 
 fn main() {
@@ -39,12 +39,7 @@ fn main() {
             let image = image.clone();
             thread::spawn(move || {
                 image_processor
-                    .reduce(
-                        color_count,
-                        &image,
-                        &Algorithm::Kmeans,
-                        &kmeans_color_gpu::ReduceMode::Replace,
-                    )
+                    .reduce(color_count, &image, &Options::default())
                     .block_on()
             })
         })
@@ -7,17 +7,20 @@ struct Centroids {
 struct Settings {
     n_seq: u32,
     convergence: f32,
+    // The first centroids are fixed, and never move.
+    fixed_count: u32,
 };
 
 struct ColorAggregator {
     color: vec3<f32>,
-    count: u32,
+    weight: f32,
 };
 
 @group(0) @binding(0) var<storage, read_write> centroids: Centroids;
 @group(0) @binding(1) var color_indices: texture_2d<u32>;
 @group(0) @binding(2) var pixels: texture_2d<f32>;
 @group(0) @binding(3) var<storage, read_write> part_id_buffer : array<atomic<u32>>;
+@group(0) @binding(4) var weights: texture_2d<f32>;
 @group(1) @binding(0) var<storage, read_write> prefix_buffer: array<atomic<u32>>;
 @group(1) @binding(1) var<storage, read_write> flag_buffer: array<atomic<u32>>;
 @group(1) @binding(2) var<storage, read_write> convergence: array<atomic<u32>>;
@@ -50,11 +53,16 @@ fn matches_centroid(k: u32, coords: vec2<u32>) -> bool {
     return k == textureLoad(color_indices, coords, 0).r;
 }
 
+// Importance of the pixel, in the red channel. A 1 x 1 white texture weighs every pixel the same.
+fn pixel_weight(coords: vec2<u32>) -> f32 {
+    return textureLoad(weights, min(coords, textureDimensions(weights) - vec2<u32>(1u)), 0).r;
+}
+
 fn atomicStoreAggregator(index: u32, value: ColorAggregator) {
     atomicStore(&prefix_buffer[index + 0u], bitcast<u32>(value.color.r));
     atomicStore(&prefix_buffer[index + 1u], bitcast<u32>(value.color.g));
     atomicStore(&prefix_buffer[index + 2u], bitcast<u32>(value.color.b));
-    atomicStore(&prefix_buffer[index + 3u], value.count);
+    atomicStore(&prefix_buffer[index + 3u], bitcast<u32>(value.weight));
 }
 
 fn atomicLoadAggregator(index: u32) -> ColorAggregator {
@@ -62,9 +70,9 @@ fn atomicLoadAggregator(index: u32) -> ColorAggregator {
     let r = bitcast<f32>(atomicLoad(&prefix_buffer[index + 0u]));
     let g = bitcast<f32>(atomicLoad(&prefix_buffer[index + 1u]));
     let b = bitcast<f32>(atomicLoad(&prefix_buffer[index + 2u]));
-    let count = atomicLoad(&prefix_buffer[index + 3u]);
+    let weight = bitcast<f32>(atomicLoad(&prefix_buffer[index + 3u]));
     value.color = vec3<f32>(r, g, b);
-    value.count = count;
+    value.weight = weight;
     return value;
 }
 
@@ -93,13 +101,14 @@ fn main(
     let width = u32(dimensions.x);
     let global_x = workgroup_x * workgroup_size + local_id.x;
 
-    var local: ColorAggregator = ColorAggregator(vec3<f32>(0.0), 0u);
+    var local: ColorAggregator = ColorAggregator(vec3<f32>(0.0), 0.0);
     for (var i: u32 = 0u; i < N_SEQ; i = i + 1u) {
         let index = global_x * N_SEQ + i;
         let coords = coords(index, dimensions);
         if (in_bounds(index, dimensions) && matches_centroid(k, coords)) {
-            local.color = local.color + textureLoad(pixels, coords, 0).rgb;
-            local.count = local.count + 1u;
+            let weight = pixel_weight(coords);
+            local.color = local.color + textureLoad(pixels, coords, 0).rgb * weight;
+            local.weight = local.weight + weight;
         }
     }
 
@@ -109,13 +118,13 @@ fn main(
         workgroupBarrier();
         if (local_id.x >= (1u << i)) {
             local.color = local.color + scratch[local_id.x - (1u << i)].color;
-            local.count = local.count + scratch[local_id.x - (1u << i)].count;
+            local.weight = local.weight + scratch[local_id.x - (1u << i)].weight;
         }
         workgroupBarrier();
         scratch[local_id.x] = local;
     }
     
-    var exclusive_prefix = ColorAggregator(vec3<f32>(0.0), 0u);
+    var exclusive_prefix = ColorAggregator(vec3<f32>(0.0), 0.0);
     var flag = FLAG_AGGREGATE_READY;
     
     if (local_id.x == workgroup_size - 1u) {
@@ -148,14 +157,14 @@ fn main(
                 if (local_id.x == workgroup_size - 1u) {
                     let their_prefix = atomicLoadAggregator(loop_back_ix * 8u);
                     exclusive_prefix.color = exclusive_prefix.color + their_prefix.color;
-                    exclusive_prefix.count = exclusive_prefix.count + their_prefix.count;
+                    exclusive_prefix.weight = exclusive_prefix.weight + their_prefix.weight;
                 }
                 break;
             } else if (flag == FLAG_AGGREGATE_READY) {                
                 if (local_id.x == workgroup_size - 1u) {                    
                     let their_aggregate = atomicLoadAggregator(loop_back_ix * 8u + 4u);
                     exclusive_prefix.color = their_aggregate.color + exclusive_prefix.color;
-                    exclusive_prefix.count = their_aggregate.count + exclusive_prefix.count;
+                    exclusive_prefix.weight = their_aggregate.weight + exclusive_prefix.weight;
                 }
                 loop_back_ix = loop_back_ix - 1u;
             }
@@ -166,7 +175,7 @@ fn main(
         if (local_id.x == workgroup_size - 1u) {
             var inclusive_prefix: ColorAggregator;
             inclusive_prefix.color = exclusive_prefix.color + local.color;
-            inclusive_prefix.count = exclusive_prefix.count + local.count;
+            inclusive_prefix.weight = exclusive_prefix.weight + local.weight;
             
             atomicStoreAggregator(workgroup_x * 8u + 0u, inclusive_prefix);
         }
@@ -182,15 +191,18 @@ fn main(
 fn pick() {
     let sum = atomicLoadAggregator(last_group_idx() * 8u + 0u);
     let k = k_index;
-    if(sum.count > 0u) {
-        let new_centroid = vec4<f32>(sum.color / f32(sum.count), 1.0);
+    if (k < settings.fixed_count) {
+        atomicStore(&convergence[k], 1u);
+    } else if(sum.weight > 0.0) {
+        let new_centroid = vec4<f32>(sum.color / sum.weight, 1.0);
         let previous_centroid = centroids.data[k];
 
         centroids.data[k] = new_centroid;
 
-        atomicStore(&convergence[k], u32(distance_cie94(new_centroid.rgb, previous_centroid.rgb) < settings.convergence));
+        atomicStore(&convergence[k], u32(color_distance(new_centroid.rgb, previous_centroid.rgb) < settings.convergence));
     } else {
-        atomicStore(&convergence[k], 0u);
+        // No weighted pixel is left in the cluster, so there is nothing to move towards.
+        atomicStore(&convergence[k], 1u);
     }
 
     if (k == centroids.count - 1u) {
@@ -77,6 +77,7 @@ fn main(
         return;
     }
 
-    let lab = xyz_to_rgb(lab_to_xyz(textureLoad(input_texture, coords, 0)));
-    textureStore(output_texture, coords, lab);
+    let texel = textureLoad(input_texture, coords, 0);
+    let rgb = xyz_to_rgb(lab_to_xyz(texel));
+    textureStore(output_texture, coords, vec4<f32>(rgb.rgb, texel.a));
 }
\ No newline at end of file
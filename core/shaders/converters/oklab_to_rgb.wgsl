@@ -0,0 +1,41 @@
+@group(0) @binding(0) var input_texture : texture_2d<f32>;
+@group(0) @binding(1) var output_texture : texture_storage_2d<rgba8unorm, write>;
+
+// #include ../functions/srgb.wgsl
+
+// Oklab factors, see https://bottosson.github.io/posts/oklab/
+const OKLAB_TO_LMS_MATRIX = mat3x3<f32>(
+    vec3<f32>(1.0, 1.0, 1.0),
+    vec3<f32>(0.3963377774, -0.1055613458, -0.0894841775),
+    vec3<f32>(0.2158037573, -0.0638541728, -1.2914855480)
+);
+
+const LMS_TO_LINEAR_RGB_MATRIX = mat3x3<f32>(
+    vec3<f32>(4.0767416621, -1.2684380046, -0.0041960863),
+    vec3<f32>(-3.3077115913, 2.6097574011, -0.7034186147),
+    vec3<f32>(0.2309699292, -0.3413193965, 1.7076147010)
+);
+
+fn oklab_to_rgb(oklab: vec4<f32>) -> vec4<f32> {
+    let lms_cbrt = OKLAB_TO_LMS_MATRIX * oklab.rgb;
+    let lms = lms_cbrt * lms_cbrt * lms_cbrt;
+
+    return vec4<f32>(linear_to_srgb(LMS_TO_LINEAR_RGB_MATRIX * lms), 1.0);
+}
+
+@compute
+@workgroup_size(16, 16)
+fn main(
+    @builtin(global_invocation_id) global_id : vec3<u32>,
+) {
+    let dimensions = textureDimensions(output_texture);
+    let coords = global_id.xy;
+
+    if(coords.x >= dimensions.x || coords.y >= dimensions.y) {
+        return;
+    }
+
+    let texel = textureLoad(input_texture, coords, 0);
+    let rgb = oklab_to_rgb(texel);
+    textureStore(output_texture, coords, vec4<f32>(rgb.rgb, texel.a));
+}
@@ -1,6 +1,8 @@
 @group(0) @binding(0) var input_texture : texture_2d<f32>;
 @group(0) @binding(1) var output_texture : texture_storage_2d<rgba32float, write>;
 
+// #include ../functions/alpha.wgsl
+
 @compute
 @workgroup_size(16, 16)
 fn main(
@@ -14,5 +16,5 @@ fn main(
     }
 
     let texel = textureLoad(input_texture, coords, 0);
-    textureStore(output_texture, coords, texel);
+    textureStore(output_texture, coords, vec4<f32>(texel.rgb, work_alpha(texel.a)));
 }
\ No newline at end of file
@@ -1,6 +1,8 @@
 @group(0) @binding(0) var input_texture : texture_2d<f32>;
 @group(0) @binding(1) var output_texture : texture_storage_2d<rgba32float, write>;
 
+// #include ../functions/alpha.wgsl
+
 // sRGB factors, see http://www.brucelindbloom.com/
 const RGB_TO_XYZ_MATRIX = mat3x3<f32>(
     vec3<f32>(0.4124564, 0.2126729, 0.0193339),
@@ -75,6 +77,7 @@ fn main(
         return;
     }
 
-    let lab = xyz_to_lab(rgb_to_xyz(textureLoad(input_texture, coords, 0)));
-    textureStore(output_texture, coords, lab);
+    let texel = textureLoad(input_texture, coords, 0);
+    let lab = xyz_to_lab(rgb_to_xyz(texel));
+    textureStore(output_texture, coords, vec4<f32>(lab.xyz, work_alpha(texel.a)));
 }
\ No newline at end of file
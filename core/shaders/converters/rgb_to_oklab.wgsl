@@ -0,0 +1,42 @@
+@group(0) @binding(0) var input_texture : texture_2d<f32>;
+@group(0) @binding(1) var output_texture : texture_storage_2d<rgba32float, write>;
+
+// #include ../functions/alpha.wgsl
+// #include ../functions/srgb.wgsl
+
+// Oklab factors, see https://bottosson.github.io/posts/oklab/
+const LINEAR_RGB_TO_LMS_MATRIX = mat3x3<f32>(
+    vec3<f32>(0.4122214708, 0.2119034982, 0.0883024619),
+    vec3<f32>(0.5363325363, 0.6806995451, 0.2817188376),
+    vec3<f32>(0.0514459929, 0.1073969566, 0.6299787005)
+);
+
+const LMS_TO_OKLAB_MATRIX = mat3x3<f32>(
+    vec3<f32>(0.2104542553, 1.9779984951, 0.0259040371),
+    vec3<f32>(0.7936177850, -2.4285922050, 0.7827717662),
+    vec3<f32>(-0.0040720468, 0.4505937099, -0.8086757660)
+);
+
+fn rgb_to_oklab(rgb: vec4<f32>) -> vec4<f32> {
+    let lms = LINEAR_RGB_TO_LMS_MATRIX * srgb_to_linear(rgb.rgb);
+    let lms_cbrt = sign(lms) * pow(abs(lms), vec3<f32>(1.0 / 3.0));
+
+    return vec4<f32>(LMS_TO_OKLAB_MATRIX * lms_cbrt, 1.0);
+}
+
+@compute
+@workgroup_size(16, 16)
+fn main(
+    @builtin(global_invocation_id) global_id : vec3<u32>,
+) {
+    let dimensions = textureDimensions(output_texture);
+    let coords = global_id.xy;
+
+    if(coords.x >= dimensions.x || coords.y >= dimensions.y) {
+        return;
+    }
+
+    let texel = textureLoad(input_texture, coords, 0);
+    let oklab = rgb_to_oklab(texel);
+    textureStore(output_texture, coords, vec4<f32>(oklab.xyz, work_alpha(texel.a)));
+}
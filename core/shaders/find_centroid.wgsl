@@ -24,15 +24,25 @@ fn main(
         return;
     }
 
-    let pixel : vec3<f32> = textureLoad(pixels, coords.xy, 0).rgb;
+    let texel = textureLoad(pixels, coords.xy, 0);
+    let pixel : vec3<f32> = texel.rgb;
+
+    // The reserved transparent entry, if any, is the last centroid, with an alpha of 0.
+    let has_transparent = centroids.count > 0u && centroids.data[centroids.count - 1u].a == 0.0;
+    if (texel.a == 0.0) {
+        // Transparent pixels are left out of the clustering.
+        textureStore(color_indices, coords, vec4<u32>(select(max_int, centroids.count - 1u, has_transparent), 0u, 0u, 0u));
+        return;
+    }
+    let opaque_count = centroids.count - u32(has_transparent);
 
     var min_distance: f32 = max_f32;
     var found_index: u32 = 0u;
 
-    for(var index: u32 = 0u; index < centroids.count; index = index + 1u){
+    for(var index: u32 = 0u; index < opaque_count; index = index + 1u){
         let centroid_components : vec3<f32> = centroids.data[index].rgb;
 
-        let distance: f32 = distance_cie94(pixel, centroid_components);
+        let distance: f32 = color_distance(pixel, centroid_components);
 
         if (distance < min_distance) {            
             min_distance = distance;
@@ -0,0 +1,11 @@
+// Replaced when the pipeline is created, see `modules::with_alpha_mode`.
+const quantize_alpha: bool = false;
+
+// Alpha stored in the work texture: either kept as is, or snapped to fully opaque or fully
+// transparent.
+fn work_alpha(alpha: f32) -> f32 {
+    if (quantize_alpha) {
+        return step(0.5, alpha);
+    }
+    return alpha;
+}
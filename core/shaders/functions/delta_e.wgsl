@@ -1,3 +1,22 @@
+// Replaced when creating a pipeline, see `DistanceMetric` in the Rust sources.
+const distance_metric: u32 = 1u;
+
+fn color_distance(one: vec3<f32>, second: vec3<f32>) -> f32 {
+    var result: f32;
+    switch distance_metric {
+        case 0u: {
+            result = distance(one, second);
+        }
+        case 2u: {
+            result = distance_cie2000(one, second);
+        }
+        default: {
+            result = distance_cie94(one, second);
+        }
+    }
+    return result;
+}
+
 fn distance_cie94(one: vec3<f32>, second: vec3<f32>) -> f32{
     let K1 = 0.045;
     let K2 = 0.015;
@@ -0,0 +1,13 @@
+// sRGB transfer functions, see https://en.wikipedia.org/wiki/SRGB
+fn srgb_to_linear(rgb: vec3<f32>) -> vec3<f32> {
+    let lower = rgb / 12.92;
+    let higher = pow((rgb + 0.055) / 1.055, vec3<f32>(2.4));
+    return select(higher, lower, rgb <= vec3<f32>(0.04045));
+}
+
+fn linear_to_srgb(rgb: vec3<f32>) -> vec3<f32> {
+    let clamped = max(rgb, vec3<f32>(0.0));
+    let lower = 12.92 * clamped;
+    let higher = 1.055 * pow(clamped, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(higher, lower, clamped <= vec3<f32>(0.0031308));
+}
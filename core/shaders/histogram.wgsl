@@ -0,0 +1,33 @@
+@group(0) @binding(0) var input_texture : texture_2d<f32>;
+// 4 values per bin: the pixel count, then the sum of the bits each channel lost to the binning.
+@group(0) @binding(1) var<storage, read_write> histogram: array<atomic<u32>>;
+
+// #include functions/alpha.wgsl
+
+// Counts the pixels in 32 x 32 x 32 bins, 5 bits per channel. Transparent pixels are skipped.
+@compute
+@workgroup_size(16, 16)
+fn main(
+    @builtin(global_invocation_id) global_id : vec3<u32>,
+) {
+    let dimensions = textureDimensions(input_texture);
+    let coords = global_id.xy;
+
+    if(coords.x >= dimensions.x || coords.y >= dimensions.y) {
+        return;
+    }
+
+    let texel = textureLoad(input_texture, coords, 0);
+    if (work_alpha(texel.a) == 0.0) {
+        return;
+    }
+
+    let color = vec3<u32>(round(texel.rgb * 255.0));
+    let bin = color >> vec3<u32>(3u);
+    let index = ((bin.r << 10u) | (bin.g << 5u) | bin.b) * 4u;
+
+    atomicAdd(&histogram[index], 1u);
+    atomicAdd(&histogram[index + 1u], color.r & 7u);
+    atomicAdd(&histogram[index + 2u], color.g & 7u);
+    atomicAdd(&histogram[index + 3u], color.b & 7u);
+}
@@ -7,6 +7,7 @@ struct Centroids {
 @group(0) @binding(0) var<storage, read> centroids: Centroids;
 @group(0) @binding(1) var pixels: texture_2d<f32>;
 @group(0) @binding(2) var distance_map: texture_storage_2d<r32float, write>;
+@group(0) @binding(3) var weights: texture_2d<f32>;
 @group(1) @binding(0) var<uniform> k_index: u32;
 
 // #include functions/delta_e.wgsl
@@ -23,12 +24,22 @@ fn main(
         return;
     }
 
-    let color = textureLoad(pixels, coords, 0).rgb;
+    let texel = textureLoad(pixels, coords, 0);
+    // A 1 x 1 white texture weighs every pixel the same.
+    let weight = textureLoad(weights, min(coords, textureDimensions(weights) - vec2<u32>(1u)), 0).r;
+    if (texel.a == 0.0 || weight == 0.0) {
+        // Transparent and ignored pixels should never be picked as centroids, so they lose every tie.
+        textureStore(distance_map, coords, vec4<f32>(-1.0, 0.0, 0.0, 0.0));
+        return;
+    }
+
+    let color = texel.rgb;
     var min_distance: f32 = 1000000.0;
     for (var k: u32 = 0u; k < k_index; k = k + 1u) {
-        let distance_to_centroid = distance_cie94(color, centroids.data[k].rgb);
+        let distance_to_centroid = color_distance(color, centroids.data[k].rgb);
         min_distance = min(min_distance, distance_to_centroid);
     }
 
-    textureStore(distance_map, coords, vec4<f32>(min_distance, 0.0, 0.0, 0.0));
+    // Important pixels are further away from the centroids, and picked first.
+    textureStore(distance_map, coords, vec4<f32>(min_distance * weight, 0.0, 0.0, 0.0));
 }
\ No newline at end of file
@@ -0,0 +1,89 @@
+struct Centroids {
+    count: u32,
+    // Aligned 16. See https://www.w3.org/TR/WGSL/#address-space-layout-constraints
+    data: array<vec4<f32>>,
+};
+
+struct Settings {
+    // The first centroids are fixed, and never move.
+    fixed_count: u32,
+};
+
+struct ColorAggregator {
+    color: vec3<f32>,
+    weight: f32,
+    inertia: f32,
+};
+
+@group(0) @binding(0) var<storage, read_write> centroids: Centroids;
+@group(0) @binding(1) var color_indices: texture_2d<u32>;
+@group(0) @binding(2) var pixels: texture_2d<f32>;
+// How much each centroid learned, the summed weight of its pixels over all the previous batches.
+@group(0) @binding(3) var<storage, read_write> learned_weights: array<f32>;
+// For each batch since the last check, and each centroid, the weighted distance between its
+// pixels and itself, then the weight of its pixels.
+@group(0) @binding(4) var<storage, read_write> inertia: array<f32>;
+@group(0) @binding(5) var<uniform> settings: Settings;
+// Importance of each pixel of the batch, in the red channel.
+@group(0) @binding(6) var weights: texture_2d<f32>;
+// Where the inertia of this batch goes, among the batches since the last check.
+@group(0) @binding(7) var<uniform> slot: u32;
+
+const workgroup_size: u32 = 256u;
+
+var<workgroup> scratch: array<ColorAggregator, workgroup_size>;
+
+// #include functions/delta_e.wgsl
+
+// One workgroup per centroid. The centroid moves towards the mean of its pixels in the batch, at a
+// rate shrinking as it learns from more pixels, see Sculley's "Web-Scale K-Means Clustering".
+@compute
+@workgroup_size(256)
+fn main(
+    @builtin(workgroup_id) workgroup_id : vec3<u32>,
+    @builtin(local_invocation_id) local_id : vec3<u32>,
+) {
+    let k = workgroup_id.x;
+    let centroid = centroids.data[k].rgb;
+    let dimensions = textureDimensions(pixels);
+    let pixel_count = dimensions.x * dimensions.y;
+
+    var local = ColorAggregator(vec3<f32>(0.0), 0.0, 0.0);
+    for (var index = local_id.x; index < pixel_count; index = index + workgroup_size) {
+        let coords = vec2<u32>(index % dimensions.x, index / dimensions.x);
+        if (textureLoad(color_indices, coords, 0).r == k) {
+            let weight = textureLoad(weights, coords, 0).r;
+            let pixel = textureLoad(pixels, coords, 0).rgb;
+            local.color = local.color + pixel * weight;
+            local.weight = local.weight + weight;
+            local.inertia = local.inertia + color_distance(pixel, centroid) * weight;
+        }
+    }
+    scratch[local_id.x] = local;
+    workgroupBarrier();
+
+    for (var stride = workgroup_size / 2u; stride > 0u; stride = stride / 2u) {
+        if (local_id.x < stride) {
+            let other = scratch[local_id.x + stride];
+            scratch[local_id.x].color = scratch[local_id.x].color + other.color;
+            scratch[local_id.x].weight = scratch[local_id.x].weight + other.weight;
+            scratch[local_id.x].inertia = scratch[local_id.x].inertia + other.inertia;
+        }
+        workgroupBarrier();
+    }
+
+    if (local_id.x != 0u) {
+        return;
+    }
+
+    let sum = scratch[0];
+    let offset = (slot * arrayLength(&learned_weights) + k) * 2u;
+    inertia[offset] = sum.inertia;
+    inertia[offset + 1u] = sum.weight;
+
+    if (k >= settings.fixed_count && sum.weight > 0.0) {
+        learned_weights[k] = learned_weights[k] + sum.weight;
+        let rate = sum.weight / learned_weights[k];
+        centroids.data[k] = vec4<f32>(mix(centroid, sum.color / sum.weight, rate), 1.0);
+    }
+}
@@ -6,24 +6,63 @@ struct Centroids {
     data: array<vec4<f32>>,
 };
 
+struct ThresholdMap {
+    width: u32,
+    height: u32,
+    strength: f32,
+    // Negative when it should be computed from the centroids.
+    threshold: f32,
+    data: array<f32>,
+};
+
 @group(0) @binding(0) var input_texture: texture_2d<f32>;
 @group(0) @binding(1) var output_texture : texture_storage_2d<rgba32float, write>;
-@group(0) @binding(2) var color_indices: texture_2d<u32>;
+@group(0) @binding(2) var color_indices: texture_storage_2d<r32uint, write>;
 @group(0) @binding(3) var<storage, read> centroids: Centroids;
+@group(0) @binding(4) var<storage, read> threshold_map: ThresholdMap;
 
-const index_matrix: array<u32, 16> = array<u32, 16>(0u,  8u,  2u,  10u,
-                                                    12u, 4u,  14u, 6u,
-                                                    3u,  11u, 1u,  9u,
-                                                    15u, 7u,  13u, 5u);
+const max_int : u32 = 4294967295u;
 
 // #include functions/delta_e.wgsl
 
+// The reserved transparent entry, if any, is the last centroid, with an alpha of 0.
+fn has_transparent() -> bool {
+    return centroids.count > 0u && centroids.data[centroids.count - 1u].a == 0.0;
+}
+
+fn opaque_count() -> u32 {
+    return centroids.count - u32(has_transparent());
+}
+
+// Color used for transparent pixels: the transparent entry, or the pixel as it is.
+fn transparent(color: vec4<f32>) -> vec4<f32> {
+    if (has_transparent()) {
+        return centroids.data[centroids.count - 1u];
+    }
+    return color;
+}
+
+fn transparent_index() -> u32 {
+    return select(max_int, centroids.count - 1u, has_transparent());
+}
+
+fn closest_index(color: vec3<f32>) -> u32 {
+    var min_distance = color_distance(color, centroids.data[0].rgb);
+    var found_index = 0u;
+    for (var i: u32 = 1u; i < opaque_count(); i = i + 1u) {
+        let distance = color_distance(color, centroids.data[i].rgb);
+        if (distance < min_distance) {
+            min_distance = distance;
+            found_index = i;
+        }
+    }
+    return found_index;
+}
+
 fn index_value(coords: vec2<u32>) -> f32 {
-    let x = coords.x % 4u;
-    let y = coords.y % 4u;
-    let index = x + y * 4u;
-    var mine = index_matrix;
-    return f32(mine[index]) / 16.0;
+    let x = coords.x % threshold_map.width;
+    let y = coords.y % threshold_map.height;
+    return threshold_map.data[x + y * threshold_map.width];
 }
 
 fn two_closest_colors(color: vec4<f32>) -> array<vec4<f32>, 2> {
@@ -31,13 +70,13 @@ fn two_closest_colors(color: vec4<f32>) -> array<vec4<f32>, 2> {
     var closest = vec4<f32>(10000.0);
     var second_closest = vec4<f32>(10000.0);
 
-    for (var i: u32 = 0u; i < centroids.count; i = i + 1u) {
+    for (var i: u32 = 0u; i < opaque_count(); i = i + 1u) {
         let temp = centroids.data[i];
-        let temp_distance = distance_cie94(color.rgb, temp.rgb);
-        if (temp_distance < distance_cie94(color.rgb, closest.rgb)){
+        let temp_distance = color_distance(color.rgb, temp.rgb);
+        if (temp_distance < color_distance(color.rgb, closest.rgb)){
             second_closest = closest;
             closest = temp;
-        } else if (temp_distance < distance_cie94(color.rgb, second_closest.rgb)){
+        } else if (temp_distance < color_distance(color.rgb, second_closest.rgb)){
             second_closest = temp;
         }
     }
@@ -47,15 +86,14 @@ fn two_closest_colors(color: vec4<f32>) -> array<vec4<f32>, 2> {
     return values;
 }
 
-fn dither(color: vec4<f32>, coords: vec2<u32>) -> vec4<f32> {
-    // Based on https://en.wikipedia.org/wiki/Ordered_dithering
+fn computed_threshold() -> f32 {
     // Maybe this threshold should be computed by a different shader first?
     var color_a: vec3<f32> = centroids.data[0].rgb;
     var color_b: vec3<f32> = centroids.data[1].rgb;
-    var distance_a_b = distance_cie94(color_a, color_b);
-    for (var i: u32 = 2u; i < centroids.count; i = i + 1u) {
-        let distance_a = distance_cie94(centroids.data[i].rgb, color_a);
-        let distance_b = distance_cie94(centroids.data[i].rgb, color_b);
+    var distance_a_b = color_distance(color_a, color_b);
+    for (var i: u32 = 2u; i < opaque_count(); i = i + 1u) {
+        let distance_a = color_distance(centroids.data[i].rgb, color_a);
+        let distance_b = color_distance(centroids.data[i].rgb, color_b);
 
         if(distance_a > distance_b && distance_a > distance_a_b) {
             distance_a_b = distance_a;
@@ -65,28 +103,35 @@ fn dither(color: vec4<f32>, coords: vec2<u32>) -> vec4<f32> {
             color_a = centroids.data[i].rgb;
         }
     }
-    let threshold = vec3<f32>(distance_a_b / sqrt(f32(centroids.count)));
+    return distance_a_b / sqrt(f32(opaque_count()));
+}
+
+fn dither(color: vec4<f32>, coords: vec2<u32>) -> u32 {
+    // Based on https://en.wikipedia.org/wiki/Ordered_dithering
+    var spread = threshold_map.threshold;
+    if (spread < 0.0) {
+        spread = computed_threshold();
+    }
+    let threshold = vec3<f32>(spread * threshold_map.strength);
 
     let index_value = index_value(coords) - 0.5;
 
     let adjusted = color.rgb + threshold * index_value;
-    
-    var closest = vec3<f32>(10000.0);
-    for (var i: u32 = 0u; i < centroids.count; i = i + 1u) {
-        let temp = centroids.data[i].rgb;
-        let temp_distance = distance_cie94(adjusted, temp);
-        if (temp_distance < distance_cie94(adjusted, closest)){
-            closest = temp;
-        }
-    }
-    return vec4<f32>(closest, 1.0);
+
+    return closest_index(adjusted);
 }
 
 fn meld(color: vec4<f32>, coords: vec2<u32>) -> vec4<f32> {
     let closest_colors = two_closest_colors(color);
-    let factor = distance_cie94(color.rgb, closest_colors[1].rgb) / distance_cie94(closest_colors[0].rgb, closest_colors[1].rgb);
+    let distance_closest = color_distance(closest_colors[0].rgb, closest_colors[1].rgb);
+    if (distance_closest == 0.0) {
+        // Identical centroids, nothing to mix.
+        return vec4<f32>(closest_colors[0].rgb, color.a);
+    }
+    let factor = color_distance(color.rgb, closest_colors[1].rgb) / distance_closest;
 
-    return factor * closest_colors[0] + (1.0 - factor) * closest_colors[1];
+    let melded = factor * closest_colors[0] + (1.0 - factor) * closest_colors[1];
+    return vec4<f32>(melded.rgb, color.a);
 }
 
 @compute
@@ -100,16 +145,25 @@ fn main_dither(
     if (coords.x >= dimensions.x || coords.y >= dimensions.y) {
         return;
     }
-    
-    if (centroids.count == 1u) {
-        // Only one color, so nothing to meld.
-        textureStore(output_texture, coords, centroids.data[0]);
+
+    let color = textureLoad(input_texture, coords, 0);
+
+    if (color.a == 0.0) {
+        textureStore(color_indices, coords, vec4<u32>(transparent_index(), 0u, 0u, 0u));
+        textureStore(output_texture, coords, transparent(color));
         return;
     }
 
-    let color = textureLoad(input_texture, coords, 0);
+    if (opaque_count() <= 1u) {
+        // Only one color, so nothing to mix.
+        textureStore(color_indices, coords, vec4<u32>(0u));
+        textureStore(output_texture, coords, vec4<f32>(centroids.data[0].rgb, color.a));
+        return;
+    }
 
-    textureStore(output_texture, coords, dither(color, coords));
+    let index = dither(color, coords);
+    textureStore(color_indices, coords, vec4<u32>(index, 0u, 0u, 0u));
+    textureStore(output_texture, coords, vec4<f32>(centroids.data[index].rgb, color.a));
 }
 
 @compute
@@ -124,13 +178,21 @@ fn main_meld(
         return;
     }
 
-    if (centroids.count == 1u) {
-        // Only one color, so nothing to meld.
-        textureStore(output_texture, coords, centroids.data[0]);
+    let color = textureLoad(input_texture, coords, 0);
+
+    if (color.a == 0.0) {
+        textureStore(color_indices, coords, vec4<u32>(transparent_index(), 0u, 0u, 0u));
+        textureStore(output_texture, coords, transparent(color));
         return;
     }
 
-    let color = textureLoad(input_texture, coords, 0);
-    
+    if (opaque_count() <= 1u) {
+        // Only one color, so nothing to mix.
+        textureStore(color_indices, coords, vec4<u32>(0u));
+        textureStore(output_texture, coords, vec4<f32>(centroids.data[0].rgb, color.a));
+        return;
+    }
+
+    textureStore(color_indices, coords, vec4<u32>(closest_index(color.rgb), 0u, 0u, 0u));
     textureStore(output_texture, coords, meld(color, coords));
 }
\ No newline at end of file
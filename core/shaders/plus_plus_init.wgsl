@@ -16,6 +16,8 @@ const N_SEQ = 16u;
 const workgroup_size: u32 = 256u;
 const max_f32: f32 = 4294967295.0;
 const max_int : u32 = 4294967295u;
+// Replaced by a hash of the seed, to pick the first centroid.
+const initial_index: u32 = 0u;
 
 @group(0) @binding(0) var<storage, read_write> centroids: Centroids;
 @group(0) @binding(1) var pixels: texture_2d<f32>;
@@ -23,6 +25,7 @@ const max_int : u32 = 4294967295u;
 @group(0) @binding(3) var<storage, read_write> flag_buffer: array<atomic<u32>>;
 @group(0) @binding(4) var<storage, read_write> part_id_buffer : array<atomic<u32>>;
 @group(0) @binding(5) var distance_map: texture_2d<f32>;
+@group(0) @binding(6) var weights: texture_2d<f32>;
 @group(1) @binding(0) var<uniform> k_index: u32;
 
 var<workgroup> scratch: array<Candidate, workgroup_size>;
@@ -33,6 +36,14 @@ fn coords(pixel_index: u32, dimensions: vec2<u32>) -> vec2<u32> {
     return vec2<u32>(pixel_index % dimensions.x, pixel_index / dimensions.x);
 }
 
+// Transparent and zero weight pixels can't seed a centroid. A 1 x 1 white weight texture weighs every
+// pixel the same.
+fn is_candidate(pixel_index: u32, dimensions: vec2<u32>) -> bool {
+    let coords = coords(pixel_index, dimensions);
+    let weight = textureLoad(weights, min(coords, textureDimensions(weights) - vec2<u32>(1u)), 0).r;
+    return textureLoad(pixels, coords, 0).a > 0.0 && weight > 0.0;
+}
+
 fn last_group_idx() -> u32 {
     return arrayLength(&flag_buffer) - 1u;
 }
@@ -55,10 +66,6 @@ fn atomicLoadCandidate(index: u32, dimensions: vec2<u32>) -> Candidate {
     return output;
 }
 
-fn rand(seed: f32) -> f32 {
-    return fract(sin(dot(vec2<f32>(seed), vec2<f32>(12.9898,78.233))) * 43758.5453);
-}
-
 fn selectCandidate(a: Candidate, b: Candidate) -> Candidate {
     if (a.distance < b.distance) {
         return b;
@@ -81,7 +88,8 @@ fn main(
     let width = dimensions.x;
     let global_x = workgroup_x * workgroup_size + local_id.x;
 
-    var local = Candidate(0u, 0.0);
+    // Below the distance of any pixel, even the transparent ones.
+    var local = Candidate(0u, -2.0);
 
     for (var i: u32 = 0u; i < N_SEQ; i = i + 1u) {
         let pixel_index = global_x * N_SEQ + i;
@@ -160,11 +168,16 @@ fn main(
 @workgroup_size(1)
 fn initial() {
     let dimensions = textureDimensions(pixels);
-    let x = i32(f32(dimensions.x) * rand(42.0));
-    let y = i32(f32(dimensions.y) * rand(12.0));
 
-    let new_centroid = textureLoad(pixels, vec2<i32>(x, y), 0);
-    centroids.data[0] = new_centroid;
+    // Starting from the random pixel, look for the first one that is visible and weighs something.
+    let pixel_count = dimensions.x * dimensions.y;
+    let start = initial_index % pixel_count;
+    var pixel_index = start;
+    for (var i = 1u; i < pixel_count && !is_candidate(pixel_index, dimensions); i = i + 1u) {
+        pixel_index = (start + i) % pixel_count;
+    }
+    let new_centroid = textureLoad(pixels, coords(pixel_index, dimensions), 0);
+    centroids.data[0] = vec4<f32>(new_centroid.rgb, 1.0);
 }
 
 @compute
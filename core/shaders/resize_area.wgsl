@@ -0,0 +1,42 @@
+@group(0) @binding(0) var input_texture : texture_2d<f32>;
+@group(0) @binding(1) var output_texture : texture_storage_2d<rgba8unorm, write>;
+
+// Averages the input pixels covered by each output pixel. Colors are weighted by their alpha, so
+// that transparent pixels don't bleed into their neighbors. Large areas are averaged from an evenly
+// spread grid of at most MAX_STEPS x MAX_STEPS of their pixels, to bound the work of each thread.
+const MAX_STEPS = 16u;
+
+@compute
+@workgroup_size(16, 16)
+fn main(
+  @builtin(global_invocation_id) global_id : vec3<u32>,
+) {
+    let dimensions = textureDimensions(output_texture);
+    if(global_id.x >= dimensions.x || global_id.y >= dimensions.y) {
+        return;
+    }
+
+    let input_dimensions = textureDimensions(input_texture);
+    let start = global_id.xy * input_dimensions / dimensions;
+    let end = max((global_id.xy + 1u) * input_dimensions / dimensions, start + 1u);
+    let step = (end - start + MAX_STEPS - 1u) / MAX_STEPS;
+
+    var color = vec3<f32>(0.0);
+    var alpha = 0.0;
+    var count = 0.0;
+    for (var y = start.y; y < end.y; y = y + step.y) {
+        for (var x = start.x; x < end.x; x = x + step.x) {
+            let texel = textureLoad(input_texture, vec2<u32>(x, y), 0);
+            color = color + texel.rgb * texel.a;
+            alpha = alpha + texel.a;
+            count = count + 1.0;
+        }
+    }
+
+    var output = vec4<f32>(0.0);
+    if (alpha > 0.0) {
+        output = vec4<f32>(color / alpha, alpha / count);
+    }
+
+    textureStore(output_texture, vec2<i32>(global_id.xy), output);
+}
@@ -0,0 +1,37 @@
+@group(0) @binding(0) var image: texture_2d<f32>;
+@group(0) @binding(1) var batch: texture_storage_2d<rgba8unorm, write>;
+// Changes at every iteration, so that each batch draws other pixels.
+@group(0) @binding(2) var<uniform> seed: u32;
+// A 1 x 1 white texture weighs every pixel the same.
+@group(0) @binding(3) var weights: texture_2d<f32>;
+@group(0) @binding(4) var batch_weights: texture_storage_2d<rgba8unorm, write>;
+
+// PCG hash, see https://www.jcgt.org/published/0009/03/02/
+fn hash(input: u32) -> u32 {
+    let state = input * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+// Fills the batch with pixels drawn at random from the whole image, along with their weights.
+@compute
+@workgroup_size(16, 16)
+fn main(
+    @builtin(global_invocation_id) global_id : vec3<u32>,
+) {
+    let dimensions = textureDimensions(batch);
+    let coords = global_id.xy;
+
+    if(coords.x >= dimensions.x || coords.y >= dimensions.y) {
+        return;
+    }
+
+    let image_dimensions = textureDimensions(image);
+    let batch_index = coords.y * dimensions.x + coords.x;
+    let pixel_index = hash(batch_index ^ seed) % (image_dimensions.x * image_dimensions.y);
+    let pixel_coords = vec2<u32>(pixel_index % image_dimensions.x, pixel_index / image_dimensions.x);
+
+    textureStore(batch, coords, textureLoad(image, pixel_coords, 0));
+    let weight_coords = min(pixel_coords, textureDimensions(weights) - vec2<u32>(1u));
+    textureStore(batch_weights, coords, textureLoad(weights, weight_coords, 0));
+}
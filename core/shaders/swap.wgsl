@@ -6,6 +6,9 @@ struct Centroids {
 @group(0) @binding(0) var<storage, read> centroids: Centroids;
 @group(0) @binding(1) var color_indices: texture_2d<u32>;
 @group(0) @binding(2) var output_texture : texture_storage_2d<rgba32float, write>;
+@group(0) @binding(3) var pixels: texture_2d<f32>;
+
+const max_int : u32 = 4294967295u;
 
 @compute
 @workgroup_size(16, 16)
@@ -20,6 +23,14 @@ fn main(
     }
 
     let index = textureLoad(color_indices, coords, 0).r;
-    
-    textureStore(output_texture, coords.xy, centroids.data[index]);
+    let pixel = textureLoad(pixels, coords, 0);
+
+    if (index == max_int) {
+        // Transparent pixel, with no transparent entry to replace it.
+        textureStore(output_texture, coords.xy, pixel);
+        return;
+    }
+
+    let centroid = centroids.data[index];
+    textureStore(output_texture, coords.xy, vec4<f32>(centroid.rgb, min(centroid.a, pixel.a)));
 }
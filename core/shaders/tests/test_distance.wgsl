@@ -15,6 +15,12 @@ fn run_distance_cie2000(){
     output = distance_cie2000(input[0], input[1]);
 }
 
+@compute
+@workgroup_size(1)
+fn run_color_distance(){
+    output = color_distance(input[0], input[1]);
+}
+
 
 @compute
 @workgroup_size(1)
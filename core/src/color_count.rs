@@ -0,0 +1,194 @@
+use crate::{delta_e::color_distance, ColorCountScore, ColorCountSelection, DistanceMetric};
+
+/// Measures how well the centroids fit the opaque pixels, both expected in the working color
+/// space. With `weights`, one for each pixel, each pixel counts as much as its weight, and pixels
+/// weighing nothing are ignored, like k-means does.
+///
+/// The silhouette is the simplified one, comparing the distance to the closest centroid with the
+/// distance to the second closest, as the full one compares every pair of pixels.
+pub(crate) fn score(
+    pixels: &[[f32; 4]],
+    weights: Option<&[f32]>,
+    centroids: &[[f32; 4]],
+    distance_metric: &DistanceMetric,
+) -> ColorCountScore {
+    let mut total_weight = 0.0;
+    let mut total_distance = 0.0;
+    let mut max_distance: f32 = 0.0;
+    let mut total_silhouette = 0.0;
+
+    for (index, pixel) in pixels.iter().enumerate() {
+        let weight = weights.map_or(1.0, |weights| weights[index]);
+        if pixel[3] == 0.0 || weight <= 0.0 {
+            continue;
+        }
+
+        let mut closest = f32::MAX;
+        let mut second = f32::MAX;
+        for centroid in centroids.iter().filter(|centroid| centroid[3] != 0.0) {
+            let distance = color_distance(distance_metric, pixel, centroid);
+            if distance < closest {
+                second = closest;
+                closest = distance;
+            } else if distance < second {
+                second = distance;
+            }
+        }
+        if closest == f32::MAX {
+            continue;
+        }
+
+        total_weight += weight;
+        total_distance += closest * weight;
+        max_distance = max_distance.max(closest);
+        // A single centroid has no neighbor to compare to, and identical distances are a tie.
+        if second != f32::MAX && second > 0.0 {
+            total_silhouette += (second - closest) / second * weight;
+        }
+    }
+
+    let total_weight = if total_weight > 0.0 {
+        total_weight
+    } else {
+        1.0
+    };
+    ColorCountScore {
+        color_count: centroids.len() as u32,
+        mean_distance: total_distance / total_weight,
+        max_distance,
+        silhouette: total_silhouette / total_weight,
+    }
+}
+
+/// Index of the chosen score. Scores are expected sorted by color count.
+pub(crate) fn select(scores: &[ColorCountScore], selection: &ColorCountSelection) -> usize {
+    match selection {
+        ColorCountSelection::Elbow => elbow(scores),
+        ColorCountSelection::Silhouette => {
+            scores.iter().enumerate().fold(0, |best, (index, score)| {
+                if score.silhouette > scores[best].silhouette {
+                    index
+                } else {
+                    best
+                }
+            })
+        }
+        ColorCountSelection::MaxDeltaE(target) => scores
+            .iter()
+            .position(|score| score.max_distance <= *target)
+            .unwrap_or(scores.len() - 1),
+    }
+}
+
+/// Whether the chosen score meets the target of the selection, [ColorCountSelection::MaxDeltaE]
+/// falling back to the largest count when none does.
+pub(crate) fn target_met(score: &ColorCountScore, selection: &ColorCountSelection) -> bool {
+    match selection {
+        ColorCountSelection::MaxDeltaE(target) => score.max_distance <= *target,
+        _ => true,
+    }
+}
+
+/// The point of the mean distance curve furthest below the line joining its two ends, once both
+/// axes are scaled to the 0..1 range.
+fn elbow(scores: &[ColorCountScore]) -> usize {
+    let (first, last) = match (scores.first(), scores.last()) {
+        (Some(first), Some(last)) if scores.len() > 2 => (first, last),
+        _ => return scores.len().saturating_sub(1),
+    };
+
+    let width = (last.color_count - first.color_count) as f32;
+    let height = first.mean_distance - last.mean_distance;
+    if height <= 0.0 {
+        // Adding colors doesn't help, so the smallest count will do.
+        return 0;
+    }
+
+    let mut best = 0;
+    let mut best_gap = 0.0;
+    for (index, score) in scores.iter().enumerate() {
+        let x = (score.color_count - first.color_count) as f32 / width;
+        let y = (score.mean_distance - last.mean_distance) / height;
+        let gap = (1.0 - x) - y;
+        if gap > best_gap {
+            best = index;
+            best_gap = gap;
+        }
+    }
+
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scores(mean_distances: &[f32]) -> Vec<ColorCountScore> {
+        mean_distances
+            .iter()
+            .enumerate()
+            .map(|(index, &mean_distance)| ColorCountScore {
+                color_count: index as u32 + 2,
+                mean_distance,
+                max_distance: mean_distance * 4.0,
+                silhouette: 1.0 / (1.0 + (index as f32 - 2.0).abs()),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_score() {
+        let pixels = [
+            [0.0, 0.0, 0.0, 1.0],
+            [2.0, 0.0, 0.0, 1.0],
+            [10.0, 0.0, 0.0, 1.0],
+            [50.0, 0.0, 0.0, 0.0],
+        ];
+        let centroids = [[1.0, 0.0, 0.0, 1.0], [10.0, 0.0, 0.0, 1.0]];
+
+        let unweighted = score(&pixels, None, &centroids, &DistanceMetric::Euclidean);
+
+        assert_eq!(unweighted.color_count, 2);
+        assert_eq!(unweighted.mean_distance, 2.0 / 3.0);
+        assert_eq!(unweighted.max_distance, 1.0);
+        let silhouette = ((10.0 - 1.0) / 10.0 + (8.0 - 1.0) / 8.0 + 1.0) / 3.0;
+        assert!((unweighted.silhouette - silhouette).abs() < 1e-6);
+
+        // The pixel weighing nothing no longer counts, the other two are at a distance of 1.
+        let weights = [1.0, 3.0, 0.0, 1.0];
+        let weighted = score(
+            &pixels,
+            Some(&weights),
+            &centroids,
+            &DistanceMetric::Euclidean,
+        );
+        assert_eq!(weighted.mean_distance, 1.0);
+        assert_eq!(weighted.max_distance, 1.0);
+    }
+
+    #[test]
+    fn test_select() {
+        let scores = scores(&[40.0, 20.0, 10.0, 8.0, 7.0, 6.5]);
+
+        assert_eq!(select(&scores, &ColorCountSelection::Elbow), 2);
+        assert_eq!(select(&scores, &ColorCountSelection::Silhouette), 2);
+        assert_eq!(select(&scores, &ColorCountSelection::MaxDeltaE(30.0)), 4);
+        assert_eq!(select(&scores, &ColorCountSelection::MaxDeltaE(1.0)), 5);
+        assert!(target_met(
+            &scores[4],
+            &ColorCountSelection::MaxDeltaE(30.0)
+        ));
+        assert!(!target_met(
+            &scores[5],
+            &ColorCountSelection::MaxDeltaE(1.0)
+        ));
+        assert!(target_met(&scores[2], &ColorCountSelection::Elbow));
+    }
+
+    #[test]
+    fn test_elbow_short_curves() {
+        assert_eq!(elbow(&scores(&[10.0])), 0);
+        assert_eq!(elbow(&scores(&[10.0, 5.0])), 1);
+        assert_eq!(elbow(&scores(&[10.0, 10.0, 10.0])), 0);
+    }
+}
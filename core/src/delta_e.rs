@@ -0,0 +1,64 @@
+use palette::{color_difference::Ciede2000, white_point::D65, Lab};
+
+use crate::DistanceMetric;
+
+/// CPU counterpart of `color_distance` in `functions/delta_e.wgsl`, for the parts of the
+/// pipeline that can't run on the GPU.
+pub(crate) fn color_distance(distance_metric: &DistanceMetric, one: &[f32], second: &[f32]) -> f32 {
+    match distance_metric {
+        DistanceMetric::Euclidean | DistanceMetric::Cie76 => distance_euclidean(one, second),
+        DistanceMetric::Cie94 => distance_cie94(one, second),
+        DistanceMetric::Ciede2000 => Lab::<D65, f32>::new(one[0], one[1], one[2])
+            .difference(Lab::new(second[0], second[1], second[2])),
+    }
+}
+
+fn distance_euclidean(one: &[f32], second: &[f32]) -> f32 {
+    let d0 = one[0] - second[0];
+    let d1 = one[1] - second[1];
+    let d2 = one[2] - second[2];
+
+    (d0 * d0 + d1 * d1 + d2 * d2).sqrt()
+}
+
+fn distance_cie94(one: &[f32], second: &[f32]) -> f32 {
+    const K1: f32 = 0.045;
+    const K2: f32 = 0.015;
+    let d_l = one[0] - second[0];
+    let d_a = one[1] - second[1];
+    let d_b = one[2] - second[2];
+
+    let c1 = (one[1] * one[1] + one[2] * one[2]).sqrt();
+    let c2 = (second[1] * second[1] + second[2] * second[2]).sqrt();
+    let d_cab = c1 - c2;
+
+    let d_hab = (d_a * d_a + d_b * d_b - d_cab * d_cab).max(0.0).sqrt();
+
+    let s_c = 1.0 + K1 * c1;
+    let s_h = 1.0 + K2 * c1;
+
+    (d_l * d_l + (d_cab / s_c) * (d_cab / s_c) + (d_hab / s_h) * (d_hab / s_h)).sqrt()
+}
+
+/// Index of the opaque centroid closest to the given color.
+pub(crate) fn closest_centroid(
+    distance_metric: &DistanceMetric,
+    color: &[f32],
+    centroids: &[[f32; 4]],
+) -> usize {
+    let mut min_distance = f32::MAX;
+    let mut found_index = 0;
+    for (index, centroid) in centroids
+        .iter()
+        .enumerate()
+        .filter(|(_, centroid)| centroid[3] != 0.0)
+    {
+        let distance = color_distance(distance_metric, color, centroid);
+        if distance < min_distance {
+            min_distance = distance;
+            found_index = index;
+        }
+    }
+
+    found_index
+}
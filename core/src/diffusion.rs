@@ -0,0 +1,181 @@
+use crate::{delta_e::closest_centroid, structures::TRANSPARENT_INDEX, DistanceMetric};
+
+/// Error diffusion matrix, as a list of `(dx, dy, weight)` relative to the current pixel.
+/// See https://en.wikipedia.org/wiki/Error_diffusion
+pub(crate) struct DiffusionKernel {
+    spread: &'static [(i32, u32, f32)],
+    divisor: f32,
+}
+
+pub(crate) const FLOYD_STEINBERG: DiffusionKernel = DiffusionKernel {
+    spread: &[(1, 0, 7.0), (-1, 1, 3.0), (0, 1, 5.0), (1, 1, 1.0)],
+    divisor: 16.0,
+};
+
+/// Only diffuses 6/8th of the error, which keeps more contrast.
+pub(crate) const ATKINSON: DiffusionKernel = DiffusionKernel {
+    spread: &[
+        (1, 0, 1.0),
+        (2, 0, 1.0),
+        (-1, 1, 1.0),
+        (0, 1, 1.0),
+        (1, 1, 1.0),
+        (0, 2, 1.0),
+    ],
+    divisor: 8.0,
+};
+
+pub(crate) const JARVIS_JUDICE_NINKE: DiffusionKernel = DiffusionKernel {
+    spread: &[
+        (1, 0, 7.0),
+        (2, 0, 5.0),
+        (-2, 1, 3.0),
+        (-1, 1, 5.0),
+        (0, 1, 7.0),
+        (1, 1, 5.0),
+        (2, 1, 3.0),
+        (-2, 2, 1.0),
+        (-1, 2, 3.0),
+        (0, 2, 5.0),
+        (1, 2, 3.0),
+        (2, 2, 1.0),
+    ],
+    divisor: 48.0,
+};
+
+pub(crate) const SIERRA: DiffusionKernel = DiffusionKernel {
+    spread: &[
+        (1, 0, 5.0),
+        (2, 0, 3.0),
+        (-2, 1, 2.0),
+        (-1, 1, 4.0),
+        (0, 1, 5.0),
+        (1, 1, 4.0),
+        (2, 1, 2.0),
+        (-1, 2, 2.0),
+        (0, 2, 3.0),
+        (1, 2, 2.0),
+    ],
+    divisor: 32.0,
+};
+
+/// Maps every pixel to a centroid, pushing the quantization error onto the pixels not visited yet.
+/// Rows are scanned in serpentine order, alternating direction, to avoid the error drifting in a
+/// single direction.
+///
+/// Only `strength`, between 0 and 1, of the error is spread. Pixels and centroids are expected in
+/// the working color space. Returns the index of the chosen centroid for each pixel. Transparent
+/// pixels neither take nor spread any error: they get the transparent entry, expected last with an
+/// alpha of 0, or [TRANSPARENT_INDEX].
+pub(crate) fn diffuse(
+    pixels: &[[f32; 4]],
+    (width, height): (u32, u32),
+    centroids: &[[f32; 4]],
+    kernel: &DiffusionKernel,
+    strength: f32,
+    distance_metric: &DistanceMetric,
+) -> Vec<u32> {
+    let width = width as usize;
+    let height = height as usize;
+    let mut pixels = pixels.to_vec();
+    let mut indices = vec![0; pixels.len()];
+    let transparent_index = match centroids.last() {
+        Some(centroid) if centroid[3] == 0.0 => centroids.len() as u32 - 1,
+        _ => TRANSPARENT_INDEX,
+    };
+
+    for y in 0..height {
+        let reverse = y % 2 == 1;
+        for step in 0..width {
+            let x = if reverse { width - 1 - step } else { step };
+            let pixel = pixels[x + y * width];
+            if pixel[3] == 0.0 {
+                indices[x + y * width] = transparent_index;
+                continue;
+            }
+
+            let index = closest_centroid(distance_metric, &pixel, centroids);
+            indices[x + y * width] = index as u32;
+
+            let centroid = centroids[index];
+            let error = [
+                pixel[0] - centroid[0],
+                pixel[1] - centroid[1],
+                pixel[2] - centroid[2],
+            ];
+
+            for &(dx, dy, weight) in kernel.spread {
+                let dx = if reverse { -dx } else { dx };
+                let target_x = x as i32 + dx;
+                let target_y = y + dy as usize;
+                if target_x < 0 || target_x >= width as i32 || target_y >= height {
+                    continue;
+                }
+
+                let factor = strength * weight / kernel.divisor;
+                let target = &mut pixels[target_x as usize + target_y * width];
+                for (target, error) in target.iter_mut().zip(error) {
+                    *target += error * factor;
+                }
+            }
+        }
+    }
+
+    indices
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::DistanceMetric;
+
+    use super::{diffuse, FLOYD_STEINBERG};
+
+    #[test]
+    fn test_diffuse_mid_gray() {
+        let dimensions = (16, 16);
+        let pixels = vec![[0.5, 0.5, 0.5, 1.0]; 256];
+        let centroids = [[0.0, 0.0, 0.0, 1.0], [1.0, 1.0, 1.0, 1.0]];
+
+        let indices = diffuse(
+            &pixels,
+            dimensions,
+            &centroids,
+            &FLOYD_STEINBERG,
+            1.0,
+            &DistanceMetric::Euclidean,
+        );
+
+        let white_count = indices.iter().filter(|&&index| index == 1).count();
+        assert!(
+            (120..=136).contains(&white_count),
+            "Expected about half white pixels, got {white_count}"
+        );
+    }
+
+    #[test]
+    fn test_diffuse_strength() {
+        let dimensions = (16, 16);
+        let pixels = vec![[0.4, 0.4, 0.4, 1.0]; 256];
+        let centroids = [[0.0, 0.0, 0.0, 1.0], [1.0, 1.0, 1.0, 1.0]];
+        let white_count = |strength| {
+            diffuse(
+                &pixels,
+                dimensions,
+                &centroids,
+                &FLOYD_STEINBERG,
+                strength,
+                &DistanceMetric::Euclidean,
+            )
+            .iter()
+            .filter(|&&index| index == 1)
+            .count()
+        };
+
+        // Without any error spread, every pixel gets its closest color.
+        assert_eq!(white_count(0.0), 0);
+        assert!(
+            white_count(0.5) < white_count(1.0),
+            "A weaker diffusion should give fewer white pixels"
+        );
+    }
+}
@@ -17,6 +17,7 @@ impl Container for Vec<RGBA8> {
 impl Container for &[RGBA8] {}
 
 /// Image struct manipulated by the library.
+#[derive(Clone)]
 pub struct Image<C>
 where
     C: Container,
@@ -45,6 +46,172 @@ where
     pub fn into_raw_pixels(self) -> Vec<u8> {
         self.rgba.to_pixel_vec()
     }
+
+    pub(crate) fn borrowed(&self) -> Image<&[RGBA8]> {
+        Image {
+            dimensions: self.dimensions,
+            rgba: &self.rgba,
+        }
+    }
+}
+
+/// Image where each pixel is an index in the palette, as stored by indexed formats like GIF.
+pub struct IndexedImage {
+    pub(crate) dimensions: (u32, u32),
+    pub(crate) palette: Vec<RGBA8>,
+    pub(crate) indices: Indices,
+}
+
+/// Palette indices, row by row: on 8 bits when the palette fits in 256 colors, on 16 bits
+/// otherwise.
+pub enum Indices {
+    U8(Vec<u8>),
+    U16(Vec<u16>),
+}
+
+impl IndexedImage {
+    pub(crate) fn new(dimensions: (u32, u32), palette: Vec<RGBA8>, indices: Vec<u32>) -> Self {
+        let indices = if palette.len() <= 256 {
+            Indices::U8(indices.into_iter().map(|index| index as u8).collect())
+        } else {
+            Indices::U16(indices.into_iter().map(|index| index as u16).collect())
+        };
+
+        Self {
+            dimensions,
+            palette,
+            indices,
+        }
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        self.dimensions
+    }
+
+    pub fn palette(&self) -> &[RGBA8] {
+        &self.palette
+    }
+
+    pub fn indices(&self) -> &Indices {
+        &self.indices
+    }
+
+    pub fn get_index(&self, x: u32, y: u32) -> usize {
+        let index = (x + y * self.dimensions.0) as usize;
+        match &self.indices {
+            Indices::U8(indices) => indices[index] as usize,
+            Indices::U16(indices) => indices[index] as usize,
+        }
+    }
+
+    /// Looks up the palette for every pixel.
+    pub fn to_image(&self) -> Image<Vec<RGBA8>> {
+        let rgba = match &self.indices {
+            Indices::U8(indices) => indices
+                .iter()
+                .map(|&index| self.palette[index as usize])
+                .collect(),
+            Indices::U16(indices) => indices
+                .iter()
+                .map(|&index| self.palette[index as usize])
+                .collect(),
+        };
+
+        Image {
+            dimensions: self.dimensions,
+            rgba,
+        }
+    }
+}
+
+/// Image reduced tile by tile, as tile based hardware stores it: each tile uses one of the
+/// sub-palettes, and each pixel is an index in the sub-palette of its tile.
+pub struct TiledImage {
+    pub(crate) dimensions: (u32, u32),
+    pub(crate) tile_size: u32,
+    pub(crate) sub_palettes: Vec<Vec<RGBA8>>,
+    pub(crate) tile_palettes: Vec<usize>,
+    pub(crate) indices: Vec<u8>,
+}
+
+impl TiledImage {
+    pub fn dimensions(&self) -> (u32, u32) {
+        self.dimensions
+    }
+
+    /// Width and height of the tiles, in pixels.
+    pub fn tile_size(&self) -> u32 {
+        self.tile_size
+    }
+
+    /// Tiles in each row. The tiles on the right and bottom edges are cut short when the image
+    /// isn't a multiple of the tile size.
+    pub fn tile_columns(&self) -> u32 {
+        self.dimensions.0.div_ceil(self.tile_size)
+    }
+
+    pub fn sub_palettes(&self) -> &[Vec<RGBA8>] {
+        &self.sub_palettes
+    }
+
+    /// Sub-palette of each tile, row by row.
+    pub fn tile_palettes(&self) -> &[usize] {
+        &self.tile_palettes
+    }
+
+    /// Index of each pixel in the sub-palette of its tile, row by row.
+    pub fn indices(&self) -> &[u8] {
+        &self.indices
+    }
+
+    fn sub_palette_of(&self, position: usize) -> usize {
+        let (x, y) = (
+            position as u32 % self.dimensions.0,
+            position as u32 / self.dimensions.0,
+        );
+        let tile = (y / self.tile_size) * self.tile_columns() + x / self.tile_size;
+        self.tile_palettes[tile as usize]
+    }
+
+    /// Looks up the sub-palette of its tile for every pixel. The pixels are either opaque or fully
+    /// transparent, [crate::ImageProcessor::reduce_tiles] rejects semi-transparent images.
+    pub fn to_image(&self) -> Image<Vec<RGBA8>> {
+        let rgba = self
+            .indices
+            .iter()
+            .enumerate()
+            .map(|(position, &index)| {
+                self.sub_palettes[self.sub_palette_of(position)][index as usize]
+            })
+            .collect();
+
+        Image {
+            dimensions: self.dimensions,
+            rgba,
+        }
+    }
+
+    /// Indexed image with the sub-palettes one after the other in its palette, like in the color
+    /// memory of the SNES.
+    pub fn to_indexed(&self) -> IndexedImage {
+        let offsets: Vec<_> = self
+            .sub_palettes
+            .iter()
+            .scan(0, |offset, sub_palette| {
+                let start = *offset;
+                *offset += sub_palette.len() as u32;
+                Some(start)
+            })
+            .collect();
+        let indices = self
+            .indices
+            .iter()
+            .enumerate()
+            .map(|(position, &index)| offsets[self.sub_palette_of(position)] + index as u32)
+            .collect();
+
+        IndexedImage::new(self.dimensions, self.sub_palettes.concat(), indices)
+    }
 }
 
 pub fn copied_pixel(dimensions: (u32, u32), rbga: &[u8]) -> Image<Vec<RGBA8>> {
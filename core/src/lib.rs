@@ -1,25 +1,44 @@
 use anyhow::{anyhow, Result};
-use palette::{IntoColor, Lab, Srgba};
+use palette::{IntoColor, Lab, Oklab, Srgb};
 pub use rgb::RGBA8;
+use std::borrow::Cow;
 use std::sync::Arc;
-use std::{fmt::Display, str::FromStr};
+use std::{fmt::Display, ops::RangeInclusive, str::FromStr};
 use wgpu::{
     Device, DeviceDescriptor, Features, Instance, PowerPreference, Queue, RequestAdapterOptionsBase,
 };
 
-use crate::image::{Container, Image};
-use crate::structures::{CentroidsBuffer, InputTexture};
+use crate::diffusion::{ATKINSON, FLOYD_STEINBERG, JARVIS_JUDICE_NINKE, SIERRA};
+use crate::image::{Container, Image, IndexedImage, TiledImage};
+use crate::structures::{
+    CentroidsBuffer, ColorIndexTexture, InputTexture, OutputTexture, TRANSPARENT_INDEX,
+};
+
+const TRANSPARENT: RGBA8 = RGBA8 {
+    r: 0,
+    g: 0,
+    b: 0,
+    a: 0,
+};
 
+mod color_count;
+mod delta_e;
+mod diffusion;
 mod future;
+mod median_cut;
 mod modules;
+mod neuquant;
 mod octree;
 mod operations;
 #[cfg(test)]
 mod shader_tests;
 mod structures;
+mod tiles;
 mod utils;
+mod wu;
 
 pub mod image;
+pub mod swatch;
 
 pub struct ImageProcessor {
     device: Arc<Device>,
@@ -64,110 +83,786 @@ impl ImageProcessor {
         })
     }
 
+    /// Extracts a palette of `color_count` colors from the image. With a [Options::mask], only the
+    /// pixels under its white part are looked at.
     pub async fn palette<C: Container>(
         &self,
         color_count: u32,
         image: &Image<C>,
-        algo: Algorithm,
+        options: &Options,
     ) -> Result<Vec<RGBA8>> {
-        match algo {
-            Algorithm::Kmeans => kmeans_palette(self, color_count, image).await,
-            Algorithm::Octree => octree_palette(self, color_count, image).await,
+        let masked = masked_image(image, options.mask.as_ref().map(|mask| &mask.mask))?;
+        let image = masked
+            .as_ref()
+            .map_or_else(|| image.borrowed(), Image::borrowed);
+        let (mut colors, _) = self.extract_palette(color_count, &image, options).await?;
+
+        if options.alpha.reserve_transparent {
+            colors.push(TRANSPARENT);
         }
+        Ok(colors)
     }
 
-    pub async fn find<C: Container>(
+    /// Same as [ImageProcessor::palette] with [Algorithm::Kmeans], whatever [Options::algo] is,
+    /// along with how the run went.
+    pub async fn palette_kmeans<C: Container>(
+        &self,
+        color_count: u32,
+        image: &Image<C>,
+        options: &Options,
+    ) -> Result<(Vec<RGBA8>, KmeansReport)> {
+        let masked = masked_image(image, options.mask.as_ref().map(|mask| &mask.mask))?;
+        let image = masked
+            .as_ref()
+            .map_or_else(|| image.borrowed(), Image::borrowed);
+        let (mut colors, report) = kmeans_palette(self, color_count, &image, options).await?;
+
+        if options.alpha.reserve_transparent {
+            colors.push(TRANSPARENT);
+        }
+        Ok((colors, report))
+    }
+
+    /// Same as [ImageProcessor::palette_kmeans], trying every color count of the range and keeping
+    /// the one picked by `selection`. Every count learns from the same sample of the image, and is
+    /// scored on it, pixels counting as much as their [KmeansOptions::weights]. The report holds
+    /// the chosen count, and the score of each count that was tried.
+    pub async fn palette_auto<C: Container>(
+        &self,
+        color_counts: RangeInclusive<u32>,
+        selection: &ColorCountSelection,
+        image: &Image<C>,
+        options: &Options,
+    ) -> Result<(Vec<RGBA8>, ColorCountReport)> {
+        let masked = masked_image(image, options.mask.as_ref().map(|mask| &mask.mask))?;
+        let image = masked
+            .as_ref()
+            .map_or_else(|| image.borrowed(), Image::borrowed);
+        let input_texture = InputTexture::new(&self.device, &self.queue, &image);
+        let (centroids_buffer, report) = operations::extract_palette_auto(
+            &self.device,
+            &self.queue,
+            &input_texture,
+            &options.color_space,
+            &options.alpha.mode,
+            &options.distance_metric()?,
+            color_counts,
+            selection,
+            &options.kmeans,
+            &options.sampling,
+        )
+        .await?;
+        let mut colors = centroids_buffer
+            .pull_values(&self.device, &self.queue, &options.color_space)
+            .await?;
+
+        sort_by_lightness(&mut colors, &options.color_space);
+        if options.alpha.reserve_transparent {
+            colors.push(TRANSPARENT);
+        }
+        Ok((colors, report))
+    }
+
+    /// Same as [ImageProcessor::palette], along with the share of the image covered by each color.
+    pub async fn palette_entries<C: Container>(
+        &self,
+        color_count: u32,
+        image: &Image<C>,
+        options: &Options,
+    ) -> Result<Vec<PaletteEntry>> {
+        let colors = self.palette(color_count, image, options).await?;
+
+        self.palette_coverage(image, &colors, options).await
+    }
+
+    /// Measures the share of the image covered by each color of the palette, assigning every pixel
+    /// to its closest color. Transparent colors are expected at the end of the palette. With a
+    /// [Options::mask], only the pixels under its white part are counted.
+    pub async fn palette_coverage<C: Container>(
         &self,
         image: &Image<C>,
         colors: &[RGBA8],
-        reduce_mode: &ReduceMode,
-    ) -> Result<Image<Vec<RGBA8>>> {
+        options: &Options,
+    ) -> Result<Vec<PaletteEntry>> {
+        let mask = options.mask.as_ref().map(|mask| &mask.mask);
+        if let Some(mask) = mask {
+            check_mask_dimensions(image, mask)?;
+        }
         let input_texture = InputTexture::new(&self.device, &self.queue, image);
         let centroids_buffer =
-            CentroidsBuffer::fixed_centroids(colors, &ColorSpace::Lab, &self.device);
+            CentroidsBuffer::fixed_centroids(colors, &options.color_space, &self.device);
+        let (_, color_index_texture) = operations::find_colors(
+            &self.device,
+            &self.queue,
+            &input_texture,
+            &options.color_space,
+            &options.alpha.mode,
+            &options.distance_metric()?,
+            &centroids_buffer,
+        )?;
 
-        match reduce_mode {
-            ReduceMode::Replace => operations::find_colors(
-                &self.device,
-                &self.queue,
-                &input_texture,
-                &ColorSpace::Lab,
-                &centroids_buffer,
-            ),
-            ReduceMode::Dither => operations::dither_colors(
-                &self.device,
-                &self.queue,
-                &input_texture,
-                &ColorSpace::Lab,
-                &centroids_buffer,
-            ),
-            ReduceMode::Meld => operations::meld_colors(
-                &self.device,
-                &self.queue,
-                &input_texture,
-                &ColorSpace::Lab,
-                &centroids_buffer,
-            ),
-        }?
-        .pull_image(&self.device, &self.queue)
-        .await
+        let order = CentroidsBuffer::fixed_order(colors);
+        let mut pixel_counts = vec![0; colors.len()];
+        for (position, index) in color_index_texture
+            .pull_indices(&self.device, &self.queue)
+            .await?
+            .into_iter()
+            .enumerate()
+        {
+            if mask.is_some_and(|mask| !in_region(&mask.rgba[position])) {
+                continue;
+            }
+            // Transparent pixels without a transparent entry aren't counted.
+            if let Some(&index) = order.get(index as usize) {
+                pixel_counts[index] += 1;
+            }
+        }
+        let total: u64 = pixel_counts.iter().sum();
+
+        Ok(colors
+            .iter()
+            .zip(pixel_counts)
+            .map(|(&color, pixel_count)| PaletteEntry::new(color, pixel_count, total))
+            .collect())
     }
 
+    /// Remaps every pixel of the image to the given colors, according to [Options::reduce_mode].
+    /// With a [Options::mask], the masked out pixels are either kept or remapped too, as there is
+    /// a single palette, [MaskedPixels::Separate] isn't available.
+    pub async fn find<C: Container>(
+        &self,
+        image: &Image<C>,
+        colors: &[RGBA8],
+        options: &Options,
+    ) -> Result<Image<Vec<RGBA8>>> {
+        let remapped = self.find_remapped(image, colors, options).await?;
+        self.remapped_image(image, remapped, options).await
+    }
+
+    /// Same as [ImageProcessor::find], but keeps the index of the color picked for each pixel. The
+    /// palette of the indexed image is `colors` as given, followed by the transparent entry when
+    /// one is reserved or needed. Not available with [ReduceMode::Meld], which mixes colors, nor
+    /// for semi-transparent pixels with [AlphaMode::Preserve].
+    pub async fn find_indexed<C: Container>(
+        &self,
+        image: &Image<C>,
+        colors: &[RGBA8],
+        options: &Options,
+    ) -> Result<IndexedImage> {
+        check_indexable(image, options)?;
+        let remapped = self.find_remapped(image, colors, options).await?;
+        self.remapped_indexed(image, remapped, options).await
+    }
+
+    /// Extracts a palette of `color_count` colors with [Options::algo], then remaps every pixel of
+    /// the image to it, according to [Options::reduce_mode]. With a [Options::mask], the palette
+    /// only comes from the pixels under its white part, see [MaskedPixels] for the others.
     pub async fn reduce<C: Container>(
         &self,
         color_count: u32,
         image: &Image<C>,
-        algo: &Algorithm,
-        reduce_mode: &ReduceMode,
+        options: &Options,
     ) -> Result<Image<Vec<RGBA8>>> {
+        let remapped = self.reduce_remapped(color_count, image, options).await?;
+        self.remapped_image(image, remapped, options).await
+    }
+
+    /// Same as [ImageProcessor::reduce], but keeps the index of the color picked for each pixel.
+    /// Not available with [ReduceMode::Meld], which mixes colors, nor for semi-transparent pixels
+    /// with [AlphaMode::Preserve]. With [MaskedPixels::Separate], the palette of the masked out
+    /// pixels comes after the one of the region of interest.
+    pub async fn reduce_indexed<C: Container>(
+        &self,
+        color_count: u32,
+        image: &Image<C>,
+        options: &Options,
+    ) -> Result<IndexedImage> {
+        check_indexable(image, options)?;
+        let remapped = self.reduce_remapped(color_count, image, options).await?;
+        self.remapped_indexed(image, remapped, options).await
+    }
+
+    /// Reduces the image for tile based hardware, like the NES or the Game Boy Color. A palette of
+    /// `color_count` colors is extracted, then split into sub-palettes of
+    /// [TileOptions::colors_per_tile] colors, and each tile of the image only uses the colors of
+    /// one of them. Like for [ImageProcessor::reduce_indexed], the sub-palettes only have opaque
+    /// colors and the transparent entry, so semi-transparent pixels need [AlphaMode::Quantize].
+    /// With a [Options::mask], the palette only comes from the pixels under its white part, and the
+    /// masked out pixels must be remapped with the others, see [MaskedPixels::Remap].
+    pub async fn reduce_tiles<C: Container>(
+        &self,
+        color_count: u32,
+        image: &Image<C>,
+        tile_options: &TileOptions,
+        options: &Options,
+    ) -> Result<TiledImage> {
+        if !(1..=255).contains(&tile_options.colors_per_tile) {
+            return Err(anyhow!("A tile can use between 1 and 255 colors"));
+        }
+        if tile_options.sub_palette_count == 0 {
+            return Err(anyhow!("At least one sub-palette is needed"));
+        }
+        check_alpha(image, &options.alpha.mode)?;
+        let mask = match &options.mask {
+            Some(MaskOptions {
+                mask,
+                masked_pixels: MaskedPixels::Remap,
+            }) => Some(mask),
+            Some(_) => {
+                return Err(anyhow!(
+                    "Tiles share their sub-palettes, so masked out pixels must be remapped"
+                ))
+            }
+            None => None,
+        };
+        let color_space = &options.color_space;
+        let alpha_mode = &options.alpha.mode;
+        let distance_metric = &options.distance_metric()?;
+
         let input_texture = InputTexture::new(&self.device, &self.queue, image);
+        // The transparent entry goes at the end of every sub-palette instead.
+        let masked = masked_image(image, mask)?;
+        let (palette, _) = self
+            .extract_palette(
+                color_count,
+                &masked
+                    .as_ref()
+                    .map_or_else(|| image.borrowed(), Image::borrowed),
+                options,
+            )
+            .await?;
+        let centroids_buffer =
+            CentroidsBuffer::fixed_centroids(&palette, color_space, &self.device);
+        let components = centroids_buffer
+            .pull_components(&self.device, &self.queue)
+            .await?;
+
+        let (_, color_index_texture) = operations::find_colors(
+            &self.device,
+            &self.queue,
+            &input_texture,
+            color_space,
+            alpha_mode,
+            distance_metric,
+            &centroids_buffer,
+        )?;
+        let indices = color_index_texture
+            .pull_indices(&self.device, &self.queue)
+            .await?;
+
+        let tile_size = tile_options.tile_size.pixels();
+        let (width, height) = image.dimensions;
+        let tile_columns = width.div_ceil(tile_size);
+        let tile_of = |position: usize| {
+            let (x, y) = (position as u32 % width, position as u32 / width);
+            ((y / tile_size) * tile_columns + x / tile_size) as usize
+        };
 
-        let centroids_buffer = match algo {
-            Algorithm::Kmeans => operations::extract_palette_kmeans(
+        let mut histograms =
+            vec![vec![0; palette.len()]; (tile_columns * height.div_ceil(tile_size)) as usize];
+        for (position, &index) in indices.iter().enumerate() {
+            if index != TRANSPARENT_INDEX {
+                histograms[tile_of(position)][index as usize] += 1;
+            }
+        }
+        let distances: Vec<Vec<f32>> = components
+            .iter()
+            .map(|one| {
+                components
+                    .iter()
+                    .map(|other| delta_e::color_distance(distance_metric, one, other))
+                    .collect()
+            })
+            .collect();
+        let (sub_palettes, tile_palettes) = tiles::group_tiles(
+            &histograms,
+            &distances,
+            tile_options.colors_per_tile as usize,
+            tile_options.sub_palette_count as usize,
+        );
+
+        // Each pixel gets the closest color of the sub-palette of its tile, rather than the closest
+        // color of the whole palette, that its tile might not have.
+        let transparent = options.alpha.reserve_transparent || indices.contains(&TRANSPARENT_INDEX);
+        let mut pixel_indices = vec![0; indices.len()];
+        let mut sub_palette_colors = Vec::with_capacity(sub_palettes.len());
+        for (sub_palette_index, sub_palette) in sub_palettes.iter().enumerate() {
+            let mut colors: Vec<_> = sub_palette.iter().map(|&index| palette[index]).collect();
+            let sub_centroids_buffer =
+                CentroidsBuffer::fixed_centroids(&colors, color_space, &self.device);
+            let (_, color_index_texture) = operations::find_colors(
                 &self.device,
                 &self.queue,
                 &input_texture,
-                &ColorSpace::Lab,
-                color_count,
-            )?,
-            Algorithm::Octree => {
-                let palette = octree_palette(self, color_count, image).await?;
-                CentroidsBuffer::fixed_centroids(&palette, &ColorSpace::Lab, &self.device)
+                color_space,
+                alpha_mode,
+                distance_metric,
+                &sub_centroids_buffer,
+            )?;
+
+            for (position, index) in color_index_texture
+                .pull_indices(&self.device, &self.queue)
+                .await?
+                .into_iter()
+                .enumerate()
+                .filter(|(position, _)| tile_palettes[tile_of(*position)] == sub_palette_index)
+            {
+                pixel_indices[position] = if index == TRANSPARENT_INDEX {
+                    colors.len() as u8
+                } else {
+                    index as u8
+                };
+            }
+
+            if transparent {
+                colors.push(TRANSPARENT);
+            }
+            sub_palette_colors.push(colors);
+        }
+
+        Ok(TiledImage {
+            dimensions: image.dimensions,
+            tile_size,
+            sub_palettes: sub_palette_colors,
+            tile_palettes,
+            indices: pixel_indices,
+        })
+    }
+
+    /// Centroids of the given colors, along with the colors themselves and the reserved
+    /// transparent entry.
+    fn fixed_centroids(
+        &self,
+        colors: &[RGBA8],
+        options: &Options,
+    ) -> (Vec<RGBA8>, CentroidsBuffer) {
+        let mut colors = colors.to_vec();
+        if options.alpha.reserve_transparent {
+            colors.push(TRANSPARENT);
+        }
+        let centroids_buffer =
+            CentroidsBuffer::fixed_centroids(&colors, &options.color_space, &self.device);
+        (colors, centroids_buffer)
+    }
+
+    /// Palette of `color_count` colors extracted with [Options::algo], sorted by lightness, without
+    /// the reserved transparent entry. The k-means report comes along with [Algorithm::Kmeans].
+    async fn extract_palette<C: Container>(
+        &self,
+        color_count: u32,
+        image: &Image<C>,
+        options: &Options,
+    ) -> Result<(Vec<RGBA8>, Option<KmeansReport>)> {
+        if let Algorithm::Kmeans = options.algo {
+            let (colors, report) = kmeans_palette(self, color_count, image, options).await?;
+            return Ok((colors, Some(report)));
+        }
+
+        let kmeans = &options.kmeans;
+        if !kmeans.initial_colors.is_empty()
+            || kmeans.batch_size.is_some()
+            || matches!(kmeans.initialization, Initialization::Wu)
+        {
+            return Err(anyhow!(
+                "Initial colors, the Wu initialization and batches only work with k-means, not with {}",
+                options.algo
+            ));
+        }
+        if kmeans.weights.is_some() {
+            return Err(anyhow!(
+                "Weights only work with k-means, not with {}",
+                options.algo
+            ));
+        }
+        // The other algorithms can't learn around the fixed colors, so they extract the rest of the
+        // palette on their own, and the fixed colors are added to it.
+        let fixed_colors: Vec<_> = kmeans
+            .fixed_colors
+            .iter()
+            .filter(|color| color.a != 0)
+            .copied()
+            .collect();
+        if fixed_colors.len() > color_count as usize {
+            return Err(anyhow!(
+                "Got {} fixed colors for a palette of {color_count} colors",
+                fixed_colors.len()
+            ));
+        }
+        let free_count = color_count - fixed_colors.len() as u32;
+        let mut colors = if free_count == 0 {
+            vec![]
+        } else {
+            match options.algo {
+                Algorithm::Octree => octree_palette(self, free_count, image, options).await?,
+                Algorithm::NeuQuant => neuquant_palette(self, free_count, image, options).await?,
+                _ => histogram_palette(self, free_count, image, options).await?,
             }
         };
+        colors.extend(fixed_colors);
+        sort_by_lightness(&mut colors, &options.color_space);
+        Ok((colors, None))
+    }
 
-        let output_texture = match reduce_mode {
-            ReduceMode::Replace => operations::find_colors(
-                &self.device,
-                &self.queue,
-                &input_texture,
-                &ColorSpace::Lab,
-                &centroids_buffer,
-            ),
-            ReduceMode::Dither => operations::dither_colors(
-                &self.device,
-                &self.queue,
-                &input_texture,
-                &ColorSpace::Lab,
-                &centroids_buffer,
-            ),
-            ReduceMode::Meld => operations::meld_colors(
-                &self.device,
-                &self.queue,
+    /// Remaps the image to the given colors, for [ImageProcessor::find] and its indexed variant.
+    async fn find_remapped<C: Container>(
+        &self,
+        image: &Image<C>,
+        colors: &[RGBA8],
+        options: &Options,
+    ) -> Result<Remapped> {
+        check_findable(image, options.mask.as_ref())?;
+        let input_texture = InputTexture::new(&self.device, &self.queue, image);
+        let (palette, centroids_buffer) = self.fixed_centroids(colors, options);
+
+        let (output_texture, color_index_texture) = self
+            .remap(&input_texture, &centroids_buffer, options)
+            .await?;
+        Ok(Remapped {
+            region: RemappedRegion {
+                palette,
+                output_texture,
+                color_index_texture,
+            },
+            background: None,
+        })
+    }
+
+    /// Remaps the image to a palette extracted from the region of interest of the mask, or from
+    /// the whole image, for [ImageProcessor::reduce] and its indexed variant. With
+    /// [MaskedPixels::Separate], the image is also remapped to a palette extracted from the masked
+    /// out pixels.
+    async fn reduce_remapped<C: Container>(
+        &self,
+        color_count: u32,
+        image: &Image<C>,
+        options: &Options,
+    ) -> Result<Remapped> {
+        let input_texture = InputTexture::new(&self.device, &self.queue, image);
+        let mask = options.mask.as_ref();
+        let region = self
+            .remapped_region(
+                color_count,
+                image,
                 &input_texture,
-                &ColorSpace::Lab,
-                &centroids_buffer,
+                mask.map(|mask| &mask.mask),
+                options,
+            )
+            .await?;
+
+        let background = match mask {
+            Some(MaskOptions {
+                mask,
+                masked_pixels: MaskedPixels::Separate,
+            }) => Some(
+                self.remapped_region(
+                    color_count,
+                    image,
+                    &input_texture,
+                    Some(&inverted_mask(mask)),
+                    options,
+                )
+                .await?,
             ),
-        }?;
+            _ => None,
+        };
+        Ok(Remapped { region, background })
+    }
+
+    /// Remaps the whole image to a palette extracted from the pixels under the mask.
+    async fn remapped_region<C: Container>(
+        &self,
+        color_count: u32,
+        image: &Image<C>,
+        input_texture: &InputTexture,
+        mask: Option<&Image<Vec<RGBA8>>>,
+        options: &Options,
+    ) -> Result<RemappedRegion> {
+        let masked = masked_image(image, mask)?;
+        let (colors, _) = self
+            .extract_palette(
+                color_count,
+                &masked
+                    .as_ref()
+                    .map_or_else(|| image.borrowed(), Image::borrowed),
+                options,
+            )
+            .await?;
+        let (palette, centroids_buffer) = self.fixed_centroids(&colors, options);
 
-        output_texture.pull_image(&self.device, &self.queue).await
+        let (output_texture, color_index_texture) = self
+            .remap(input_texture, &centroids_buffer, options)
+            .await?;
+        Ok(RemappedRegion {
+            palette,
+            output_texture,
+            color_index_texture,
+        })
+    }
+
+    /// The remapped image, the masked out pixels being kept as they are or taken from the
+    /// background, according to [MaskOptions::masked_pixels].
+    async fn remapped_image<C: Container>(
+        &self,
+        image: &Image<C>,
+        remapped: Remapped,
+        options: &Options,
+    ) -> Result<Image<Vec<RGBA8>>> {
+        let mut output = remapped
+            .region
+            .output_texture
+            .pull_image(&self.device, &self.queue)
+            .await?;
+        match (&options.mask, remapped.background) {
+            (Some(mask), Some(background)) => {
+                let background = background
+                    .output_texture
+                    .pull_image(&self.device, &self.queue)
+                    .await?;
+                fill_masked_pixels(&mut output, &background, &mask.mask);
+            }
+            (
+                Some(MaskOptions {
+                    mask,
+                    masked_pixels: MaskedPixels::Keep,
+                }),
+                _,
+            ) => fill_masked_pixels(&mut output, image, mask),
+            _ => {}
+        }
+        Ok(output)
+    }
+
+    /// The remapped image as an indexed image, the palette of the background coming after the one
+    /// of the region of interest.
+    async fn remapped_indexed<C: Container>(
+        &self,
+        image: &Image<C>,
+        remapped: Remapped,
+        options: &Options,
+    ) -> Result<IndexedImage> {
+        let (mut palette, mut indices) = self
+            .indexed_colors(
+                &remapped.region.palette,
+                &remapped.region.color_index_texture,
+            )
+            .await?;
+        if let (Some(mask), Some(background)) = (&options.mask, remapped.background) {
+            let (background_palette, background_indices) = self
+                .indexed_colors(&background.palette, &background.color_index_texture)
+                .await?;
+            let offset = palette.len() as u32;
+            for ((index, background_index), mask_pixel) in indices
+                .iter_mut()
+                .zip(background_indices)
+                .zip(&mask.mask.rgba)
+            {
+                if !in_region(mask_pixel) {
+                    *index = offset + background_index;
+                }
+            }
+            palette.extend(background_palette);
+        }
+        Ok(IndexedImage::new(image.dimensions, palette, indices))
+    }
+
+    /// Palette and color indices of an image remapped to the [ImageProcessor::fixed_centroids] of
+    /// the palette, the indices pointing into the palette as it was given. Transparent pixels
+    /// without a transparent entry get one at the end of the palette.
+    async fn indexed_colors(
+        &self,
+        palette: &[RGBA8],
+        color_index_texture: &ColorIndexTexture,
+    ) -> Result<(Vec<RGBA8>, Vec<u32>)> {
+        let order = CentroidsBuffer::fixed_order(palette);
+        let mut palette = palette.to_vec();
+        let mut indices = color_index_texture
+            .pull_indices(&self.device, &self.queue)
+            .await?;
+
+        if indices.contains(&TRANSPARENT_INDEX) {
+            palette.push(TRANSPARENT);
+        }
+        for index in indices.iter_mut() {
+            *index = if *index == TRANSPARENT_INDEX {
+                palette.len() as u32 - 1
+            } else {
+                order[*index as usize] as u32
+            };
+        }
+
+        Ok((palette, indices))
+    }
+
+    /// Replaces the colors of the input texture by the centroids, according to the reduce mode.
+    async fn remap(
+        &self,
+        input_texture: &InputTexture,
+        centroids_buffer: &CentroidsBuffer,
+        options: &Options,
+    ) -> Result<(OutputTexture, ColorIndexTexture)> {
+        let color_space = &options.color_space;
+        let alpha_mode = &options.alpha.mode;
+        let distance_metric = &options.distance_metric()?;
+        let kernel = match options.reduce_mode {
+            ReduceMode::Replace => {
+                return operations::find_colors(
+                    &self.device,
+                    &self.queue,
+                    input_texture,
+                    color_space,
+                    alpha_mode,
+                    distance_metric,
+                    centroids_buffer,
+                )
+            }
+            ReduceMode::Dither => {
+                return operations::dither_colors(
+                    &self.device,
+                    &self.queue,
+                    input_texture,
+                    color_space,
+                    alpha_mode,
+                    distance_metric,
+                    centroids_buffer,
+                    &options.dither,
+                )
+            }
+            ReduceMode::Meld => {
+                return operations::meld_colors(
+                    &self.device,
+                    &self.queue,
+                    input_texture,
+                    color_space,
+                    alpha_mode,
+                    distance_metric,
+                    centroids_buffer,
+                )
+            }
+            ReduceMode::FloydSteinberg => &FLOYD_STEINBERG,
+            ReduceMode::Atkinson => &ATKINSON,
+            ReduceMode::JarvisJudiceNinke => &JARVIS_JUDICE_NINKE,
+            ReduceMode::Sierra => &SIERRA,
+        };
+
+        operations::diffuse_colors(
+            &self.device,
+            &self.queue,
+            input_texture,
+            color_space,
+            alpha_mode,
+            distance_metric,
+            centroids_buffer,
+            kernel,
+            &options.dither,
+        )
+        .await
     }
 }
 
-#[derive(Clone, Copy)]
+/// The image remapped to the palette of the region of interest, or of the whole image, and with
+/// [MaskedPixels::Separate], to the palette of the masked out pixels.
+struct Remapped {
+    region: RemappedRegion,
+    background: Option<RemappedRegion>,
+}
+
+/// A palette, and the image remapped to it.
+struct RemappedRegion {
+    palette: Vec<RGBA8>,
+    output_texture: OutputTexture,
+    color_index_texture: ColorIndexTexture,
+}
+
+/// Everything that drives how a palette is extracted and how the image is remapped to it. Every
+/// field has a default, so only the ones that matter need to be set:
+/// ```rust
+/// use kmeans_color_gpu::{Algorithm, Options, ReduceMode};
+///
+/// let options = Options {
+///     algo: Algorithm::Wu,
+///     reduce_mode: ReduceMode::FloydSteinberg,
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Clone, Default)]
+pub struct Options {
+    /// Algorithm extracting the palette.
+    pub algo: Algorithm,
+    /// Used by [Algorithm::Kmeans].
+    pub kmeans: KmeansOptions,
+    pub sampling: SamplingOptions,
+    /// How pixels are remapped to the palette.
+    pub reduce_mode: ReduceMode,
+    /// Used by [ReduceMode::Dither] and the error diffusion modes.
+    pub dither: DitherOptions,
+    pub alpha: AlphaOptions,
+    pub color_space: ColorSpace,
+    /// When `None`, [ColorSpace::default_distance_metric].
+    pub distance_metric: Option<DistanceMetric>,
+    /// Region of interest, like a product cutout, the rest of the image being left out of the
+    /// palette.
+    pub mask: Option<MaskOptions>,
+}
+
+impl Options {
+    /// The distance metric to use, the ΔE formulas only being defined in [ColorSpace::Lab].
+    fn distance_metric(&self) -> Result<DistanceMetric> {
+        match (self.distance_metric, self.color_space) {
+            (None, color_space) => Ok(color_space.default_distance_metric()),
+            (
+                Some(
+                    distance_metric @ (DistanceMetric::Cie76
+                    | DistanceMetric::Cie94
+                    | DistanceMetric::Ciede2000),
+                ),
+                color_space @ (ColorSpace::Rgb | ColorSpace::Oklab | ColorSpace::LinearRgb),
+            ) => Err(anyhow!(
+                "The {distance_metric} metric only works in lab, not in {color_space}"
+            )),
+            (Some(distance_metric), _) => Ok(distance_metric),
+        }
+    }
+}
+
+/// A color of the palette, with the part of the image it covers.
+#[derive(Clone, Copy, Debug)]
+pub struct PaletteEntry {
+    pub color: RGBA8,
+    /// Number of pixels of the image closest to this color.
+    pub pixel_count: u64,
+    /// Share of the counted pixels closest to this color, between 0 and 1. Transparent pixels
+    /// are only counted when a transparent entry is reserved.
+    pub fraction: f32,
+    /// The color in CIE L*a*b*, as `[l, a, b]`.
+    pub lab: [f32; 3],
+}
+
+impl PaletteEntry {
+    fn new(color: RGBA8, pixel_count: u64, total: u64) -> Self {
+        let lab: Lab = Srgb::new(color.r, color.g, color.b)
+            .into_format::<f32>()
+            .into_color();
+
+        Self {
+            color,
+            pixel_count,
+            fraction: if total > 0 {
+                pixel_count as f32 / total as f32
+            } else {
+                0.0
+            },
+            lab: [lab.l, lab.a, lab.b],
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default)]
 pub enum ColorSpace {
+    #[default]
     Lab,
     Rgb,
+    Oklab,
+    LinearRgb,
 }
 
 impl ColorSpace {
@@ -175,6 +870,8 @@ impl ColorSpace {
         match str {
             "lab" => Some(ColorSpace::Lab),
             "rgb" => Some(ColorSpace::Rgb),
+            "oklab" => Some(ColorSpace::Oklab),
+            "linear-rgb" => Some(ColorSpace::LinearRgb),
             _ => None,
         }
     }
@@ -183,6 +880,19 @@ impl ColorSpace {
         match self {
             ColorSpace::Lab => "lab",
             ColorSpace::Rgb => "rgb",
+            ColorSpace::Oklab => "oklab",
+            ColorSpace::LinearRgb => "linear-rgb",
+        }
+    }
+
+    /// The metric used when none is given: [DistanceMetric::Cie94] in Lab, where the ΔE formulas
+    /// are defined, [DistanceMetric::Euclidean] in the other color spaces.
+    pub fn default_distance_metric(&self) -> DistanceMetric {
+        match self {
+            ColorSpace::Lab => DistanceMetric::Cie94,
+            ColorSpace::Rgb | ColorSpace::Oklab | ColorSpace::LinearRgb => {
+                DistanceMetric::Euclidean
+            }
         }
     }
 
@@ -190,6 +900,8 @@ impl ColorSpace {
         match self {
             ColorSpace::Lab => 1.0,
             ColorSpace::Rgb => 0.01,
+            ColorSpace::Oklab => 0.01,
+            ColorSpace::LinearRgb => 0.01,
         }
     }
 }
@@ -198,11 +910,7 @@ impl FromStr for ColorSpace {
     type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "lab" => Ok(ColorSpace::Lab),
-            "rgb" => Ok(ColorSpace::Rgb),
-            _ => Err(anyhow!("Unsupported color space {s}")),
-        }
+        ColorSpace::from(s).ok_or_else(|| anyhow!("Unsupported color space {s}"))
     }
 }
 
@@ -212,10 +920,75 @@ impl Display for ColorSpace {
     }
 }
 
+/// Formula used to measure how far apart two colors are, both when clustering and when
+/// mapping pixels to the closest palette color.
 #[derive(Clone, Copy)]
+pub enum DistanceMetric {
+    /// Weighs chroma and hue differences by chroma, a good tradeoff between speed and accuracy.
+    /// Only defined in [ColorSpace::Lab].
+    Cie94,
+    /// The most accurate, but also the slowest. Only defined in [ColorSpace::Lab].
+    Ciede2000,
+    /// Plain euclidean distance in the working color space. In Lab, this is
+    /// [DistanceMetric::Cie76].
+    Euclidean,
+    /// The first ΔE formula, the euclidean distance in Lab. Only defined in [ColorSpace::Lab].
+    Cie76,
+}
+
+impl DistanceMetric {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DistanceMetric::Cie94 => "cie94",
+            DistanceMetric::Ciede2000 => "ciede2000",
+            DistanceMetric::Euclidean => "euclidean",
+            DistanceMetric::Cie76 => "cie76",
+        }
+    }
+
+    /// Value of the `distance_metric` constant in `functions/delta_e.wgsl`.
+    pub(crate) fn shader_index(&self) -> u32 {
+        match self {
+            DistanceMetric::Euclidean | DistanceMetric::Cie76 => 0,
+            DistanceMetric::Cie94 => 1,
+            DistanceMetric::Ciede2000 => 2,
+        }
+    }
+}
+
+impl FromStr for DistanceMetric {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cie94" => Ok(DistanceMetric::Cie94),
+            "ciede2000" => Ok(DistanceMetric::Ciede2000),
+            "euclidean" => Ok(DistanceMetric::Euclidean),
+            "cie76" => Ok(DistanceMetric::Cie76),
+            _ => Err(anyhow!("Unsupported distance metric {s}")),
+        }
+    }
+}
+
+impl Display for DistanceMetric {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[derive(Clone, Copy, Default)]
 pub enum Algorithm {
+    #[default]
     Kmeans,
     Octree,
+    /// Heckbert's median cut, as found in many legacy tools.
+    MedianCut,
+    /// Xiaolin Wu's variance minimizing quantizer. Deterministic, and close to k-means in quality,
+    /// which makes its palette a good start for k-means, see [Initialization::Wu].
+    Wu,
+    /// Anthony Dekker's NeuQuant neural network, as used by many GIF encoders, from the
+    /// `color_quant` crate. Runs on the CPU, and drops the neurons that no pixel is closest to.
+    NeuQuant,
 }
 
 impl Display for Algorithm {
@@ -226,16 +999,370 @@ impl Display for Algorithm {
             match self {
                 Algorithm::Kmeans => "kmeans",
                 Algorithm::Octree => "octree",
+                Algorithm::MedianCut => "median-cut",
+                Algorithm::Wu => "wu",
+                Algorithm::NeuQuant => "neuquant",
             }
         )
     }
 }
 
+/// Options used by [Algorithm::Kmeans].
+#[derive(Clone)]
+pub struct KmeansOptions {
+    /// Picks the first centroid. The same seed gives the same palette on a given adapter.
+    pub seed: u64,
+    /// Stops after this many iterations, even without convergence.
+    pub max_iterations: u32,
+    /// Convergence is checked every `check_interval` iterations, as each check waits on the GPU.
+    pub check_interval: u32,
+    /// The run converged once no centroid moves by more than this distance, in the working color
+    /// space, between two iterations. When `None`, 1.0 for [ColorSpace::Lab], 0.01 otherwise. Not
+    /// used with a [KmeansOptions::batch_size].
+    pub tolerance: Option<f32>,
+    /// Colors the first centroids start from, like the palette of a previous frame or a brand
+    /// palette, instead of picking them from the image. Missing centroids start according to
+    /// [KmeansOptions::initialization], and fully transparent colors are ignored.
+    pub initial_colors: Vec<RGBA8>,
+    /// How the centroids that aren't fixed or initial colors start.
+    pub initialization: Initialization,
+    /// Colors that are part of the palette no matter what, like pure black and white or brand
+    /// colors. They count towards the color count, and the other centroids are learned around them.
+    /// Fully transparent colors are ignored. The other algorithms keep them too, and extract the
+    /// rest of the palette on their own, but they reject initial colors, the Wu initialization,
+    /// batches and weights.
+    pub fixed_colors: Vec<RGBA8>,
+    /// Runs mini-batch k-means: each iteration draws this many random pixels from the full
+    /// resolution image, rounded up to a multiple of 256, and moves the centroids towards them.
+    /// Meant for very large images, where a downsampled image loses details and every pixel is
+    /// too many. [SamplingOptions] are then ignored. The run converged once the mean distance
+    /// between the pixels of each batch and their centroid, averaged over about as many pixels as
+    /// the image has, stops improving for ten batches in a row.
+    pub batch_size: Option<u32>,
+    /// Importance of each pixel, like a saliency map or a face mask, so that small but important
+    /// regions keep their colors. A grayscale image of the same dimensions as the image, read from
+    /// its red channel: black pixels are ignored, white ones count fully.
+    pub weights: Option<Image<Vec<RGBA8>>>,
+}
+
+impl Default for KmeansOptions {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            max_iterations: 128,
+            check_interval: 8,
+            tolerance: None,
+            initial_colors: vec![],
+            initialization: Initialization::KmeansPlusPlus,
+            fixed_colors: vec![],
+            batch_size: None,
+            weights: None,
+        }
+    }
+}
+
+/// How the k-means centroids start, see [KmeansOptions::initialization].
+#[derive(Clone, Copy, Default)]
+pub enum Initialization {
+    /// Picks pixels of the image at random, each new one more likely the further it is from the
+    /// others. Depends on [KmeansOptions::seed].
+    #[default]
+    KmeansPlusPlus,
+    /// Starts from the palette of [Algorithm::Wu], computed on the full resolution image. Stable
+    /// across seeds, and usually converges in fewer iterations.
+    Wu,
+}
+
+/// How much of the image is looked at to extract the palette. Bigger samples keep small details,
+/// like logos, at the cost of speed. Samples never have fewer pixels than there are colors
+/// to find, as long as the image has that many.
+#[derive(Clone, Copy, Default)]
+pub struct SamplingOptions {
+    pub sampling: Sampling,
+    pub filter: ResizeFilter,
+}
+
+#[derive(Clone, Copy, Default)]
+pub enum Sampling {
+    /// Downsamples to 256 pixels on the longest side for k-means and NeuQuant, 128 for octree.
+    /// Median cut and Wu look at every pixel, as their histogram is cheap to build.
+    #[default]
+    Auto,
+    /// Every pixel of the image.
+    Full,
+    /// Downsamples the image so that its longest side fits in this many pixels.
+    MaxDimension(u32),
+    /// Downsamples the image to at most this many pixels, keeping its aspect ratio.
+    PixelBudget(u32),
+}
+
+/// How pixels are combined when downsampling the image.
+#[derive(Clone, Copy, Default)]
+pub enum ResizeFilter {
+    /// Interpolates between the 4 pixels closest to each sample. Fast, but skips pixels.
+    #[default]
+    Bilinear,
+    /// Averages the pixels covered by each sample, from a grid of at most 16x16 of them when they
+    /// cover more.
+    Area,
+}
+
+/// How a k-means run went.
+#[derive(Clone, Copy, Debug)]
+pub struct KmeansReport {
+    /// Number of iterations that ran.
+    pub iterations: u32,
+    /// Whether the centroids stopped moving before reaching the maximum iteration count.
+    pub converged: bool,
+}
+
+/// How [ImageProcessor::palette_auto] picks the color count.
 #[derive(Clone, Copy)]
+pub enum ColorCountSelection {
+    /// Where adding colors stops paying off: the bend of the mean distance curve.
+    Elbow,
+    /// The count whose colors are the most distinct from each other, relative to how close the
+    /// pixels are to their own color.
+    Silhouette,
+    /// The smallest count keeping every pixel within this distance of its color. In
+    /// [ColorSpace::Lab], the distance is a ΔE. When no count does, the largest one is chosen, see
+    /// [ColorCountReport::target_met].
+    MaxDeltaE(f32),
+}
+
+/// How well a k-means palette fits the image, distances being measured in the working color space.
+#[derive(Clone, Copy, Debug)]
+pub struct ColorCountScore {
+    pub color_count: u32,
+    /// Mean distance between the pixels and their closest color.
+    pub mean_distance: f32,
+    /// Distance between the worst represented pixel and its closest color.
+    pub max_distance: f32,
+    /// Simplified silhouette, from 0 to 1, higher meaning better separated colors.
+    pub silhouette: f32,
+}
+
+/// The color count chosen by [ImageProcessor::palette_auto].
+#[derive(Clone, Debug)]
+pub struct ColorCountReport {
+    pub color_count: u32,
+    /// Whether the chosen count meets the target of the [ColorCountSelection]. When no count keeps
+    /// every pixel within [ColorCountSelection::MaxDeltaE], the largest one is chosen instead.
+    pub target_met: bool,
+    /// Score of every count that was tried, from the smallest count to the largest.
+    pub scores: Vec<ColorCountScore>,
+}
+
+#[derive(Clone, Copy, Default)]
 pub enum ReduceMode {
+    #[default]
     Replace,
+    /// Ordered dithering, using the threshold matrix of the [DitherOptions].
     Dither,
     Meld,
+    /// Error diffusion dithering, spreading the error over 4 neighbors.
+    FloydSteinberg,
+    /// Error diffusion dithering that only spreads 3/4 of the error, keeping more contrast.
+    Atkinson,
+    /// Error diffusion dithering spreading the error over 12 neighbors, smoother but slower.
+    JarvisJudiceNinke,
+    /// Error diffusion dithering spreading the error over 10 neighbors.
+    Sierra,
+}
+
+/// How the alpha channel of the image is handled. Fully transparent pixels are always left out
+/// of the palette.
+#[derive(Clone, Copy, Default)]
+pub struct AlphaOptions {
+    pub mode: AlphaMode,
+    /// Adds a fully transparent entry at the end of the palette, used for transparent pixels.
+    pub reserve_transparent: bool,
+}
+
+#[derive(Clone, Copy, Default)]
+pub enum AlphaMode {
+    /// Keeps the alpha of each pixel as it is.
+    #[default]
+    Preserve,
+    /// Makes each pixel either fully opaque or fully transparent, cutting at half opacity.
+    Quantize,
+}
+
+impl AlphaMode {
+    /// Whether a pixel is transparent, and should be left out of the palette.
+    pub(crate) fn is_transparent(&self, color: &RGBA8) -> bool {
+        match self {
+            AlphaMode::Preserve => color.a == 0,
+            AlphaMode::Quantize => color.a < 128,
+        }
+    }
+}
+
+/// Region of interest, see [Options::mask].
+#[derive(Clone)]
+pub struct MaskOptions {
+    /// Black and white image of the same dimensions as the image, read from its red channel: only
+    /// the pixels under its white part are used to extract the palette.
+    pub mask: Image<Vec<RGBA8>>,
+    /// Only used by [ImageProcessor::find] and [ImageProcessor::reduce], and their indexed
+    /// variants.
+    pub masked_pixels: MaskedPixels,
+}
+
+/// What happens to the masked out pixels in the output.
+#[derive(Clone, Copy, Default)]
+pub enum MaskedPixels {
+    /// Left as they are, like a background.
+    #[default]
+    Keep,
+    /// Remapped to the palette like the other pixels, even though they were left out of it.
+    Remap,
+    /// Remapped to a palette of their own, of the same color count, extracted from the masked out
+    /// pixels only. Not available with [ImageProcessor::find], which has a single palette.
+    Separate,
+}
+
+/// Constraints of tile based hardware, like the NES, the Game Boy Color or the SNES, for
+/// [ImageProcessor::reduce_tiles].
+#[derive(Clone, Copy)]
+pub struct TileOptions {
+    pub tile_size: TileSize,
+    /// Colors of each sub-palette, so the most colors a tile can use, not counting the transparent
+    /// entry.
+    pub colors_per_tile: u32,
+    /// Sub-palettes the tiles pick from. Fewer are returned when they are enough for every tile.
+    pub sub_palette_count: u32,
+}
+
+#[derive(Clone, Copy, Default)]
+pub enum TileSize {
+    /// 8 x 8 pixels, like the NES, the Game Boy and most SNES backgrounds.
+    #[default]
+    Eight,
+    /// 16 x 16 pixels, like the NES attribute table and large SNES backgrounds.
+    Sixteen,
+}
+
+impl TileSize {
+    pub fn pixels(&self) -> u32 {
+        match self {
+            TileSize::Eight => 8,
+            TileSize::Sixteen => 16,
+        }
+    }
+}
+
+/// Options used by [ReduceMode::Dither] and, for the strength only, by the error diffusion modes.
+#[derive(Clone)]
+pub struct DitherOptions {
+    pub matrix: DitherMatrix,
+    /// Scales the threshold, or the share of the error that error diffusion spreads, from 0 (no
+    /// dithering) to 1.
+    pub strength: f32,
+    /// Spread of the dithering, as a distance in the working color space. When `None`, it is
+    /// derived from the distance between the two most distant colors of the palette. Error
+    /// diffusion always spreads the error itself, and returns an error when this is set.
+    pub threshold: Option<f32>,
+}
+
+impl Default for DitherOptions {
+    fn default() -> Self {
+        Self {
+            matrix: DitherMatrix::default(),
+            strength: 1.0,
+            threshold: None,
+        }
+    }
+}
+
+/// Threshold map used for ordered dithering, tiled over the whole image.
+#[derive(Clone, Default)]
+pub enum DitherMatrix {
+    Bayer2,
+    #[default]
+    Bayer4,
+    Bayer8,
+    Bayer16,
+    /// A threshold texture, typically blue noise, which hides the regular pattern of the Bayer
+    /// matrices.
+    BlueNoise(ThresholdTexture),
+}
+
+/// Thresholds of [DitherMatrix::BlueNoise], read from the red channel of an image, so a grayscale
+/// image works fine.
+#[derive(Clone)]
+pub struct ThresholdTexture {
+    dimensions: (u32, u32),
+    thresholds: Vec<f32>,
+}
+
+impl ThresholdTexture {
+    /// Fails when the image is empty, or doesn't hold as many pixels as its dimensions tell.
+    pub fn new<C: Container>(image: &Image<C>) -> Result<Self> {
+        let (width, height) = image.dimensions;
+        if width == 0 || height == 0 {
+            return Err(anyhow!("The threshold texture can't be empty"));
+        }
+        if image.rgba.len() as u64 != width as u64 * height as u64 {
+            return Err(anyhow!(
+                "The threshold texture is {width}x{height}, but holds {} pixels",
+                image.rgba.len()
+            ));
+        }
+
+        Ok(Self {
+            dimensions: image.dimensions,
+            thresholds: image
+                .rgba
+                .iter()
+                .map(|pixel| pixel.r as f32 / 256.0)
+                .collect(),
+        })
+    }
+}
+
+impl DitherMatrix {
+    /// Thresholds between 0 and 1, row by row, with the dimensions of the map.
+    pub(crate) fn thresholds(&self) -> ((u32, u32), Vec<f32>) {
+        match self {
+            DitherMatrix::Bayer2 => bayer_matrix(2),
+            DitherMatrix::Bayer4 => bayer_matrix(4),
+            DitherMatrix::Bayer8 => bayer_matrix(8),
+            DitherMatrix::Bayer16 => bayer_matrix(16),
+            DitherMatrix::BlueNoise(texture) => (texture.dimensions, texture.thresholds.clone()),
+        }
+    }
+}
+
+/// Builds a `size` x `size` Bayer matrix, `size` being a power of 2.
+/// See https://en.wikipedia.org/wiki/Ordered_dithering
+fn bayer_matrix(size: u32) -> ((u32, u32), Vec<f32>) {
+    let mut matrix = vec![0u32];
+    let mut current_size = 1;
+    while current_size < size {
+        let next_size = current_size * 2;
+        let mut next = vec![0; (next_size * next_size) as usize];
+        for y in 0..current_size {
+            for x in 0..current_size {
+                let value = 4 * matrix[(x + y * current_size) as usize];
+                next[(x + y * next_size) as usize] = value;
+                next[(x + current_size + y * next_size) as usize] = value + 2;
+                next[(x + (y + current_size) * next_size) as usize] = value + 3;
+                next[(x + current_size + (y + current_size) * next_size) as usize] = value + 1;
+            }
+        }
+        matrix = next;
+        current_size = next_size;
+    }
+
+    let cell_count = (size * size) as f32;
+    (
+        (size, size),
+        matrix
+            .into_iter()
+            .map(|value| value as f32 / cell_count)
+            .collect(),
+    )
 }
 
 impl Display for ReduceMode {
@@ -247,85 +1374,428 @@ impl Display for ReduceMode {
                 ReduceMode::Replace => "replace",
                 ReduceMode::Dither => "dither",
                 ReduceMode::Meld => "meld",
+                ReduceMode::FloydSteinberg => "floyd-steinberg",
+                ReduceMode::Atkinson => "atkinson",
+                ReduceMode::JarvisJudiceNinke => "jarvis-judice-ninke",
+                ReduceMode::Sierra => "sierra",
             }
         )
     }
 }
 
+fn check_indexable<C: Container>(image: &Image<C>, options: &Options) -> Result<()> {
+    let reduce_mode = &options.reduce_mode;
+    if let ReduceMode::Meld = reduce_mode {
+        return Err(anyhow!(
+            "The {reduce_mode} mode mixes colors, so it can't produce an indexed image"
+        ));
+    }
+    if let Some(MaskOptions {
+        masked_pixels: MaskedPixels::Keep,
+        ..
+    }) = options.mask
+    {
+        return Err(anyhow!(
+            "Masked out pixels can't be kept as they are in an indexed image, remap them instead"
+        ));
+    }
+    check_alpha(image, &options.alpha.mode)
+}
+
+/// The palette of an indexed image only has opaque colors and the transparent entry, so
+/// semi-transparent pixels would silently become opaque.
+fn check_alpha<C: Container>(image: &Image<C>, alpha_mode: &AlphaMode) -> Result<()> {
+    if let AlphaMode::Preserve = alpha_mode {
+        if image
+            .rgba
+            .iter()
+            .any(|pixel| pixel.a != 0 && pixel.a != 255)
+        {
+            return Err(anyhow!(
+                "The image has semi-transparent pixels, an indexed image can't keep them, \
+                quantize the alpha instead"
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// [ImageProcessor::find] remaps to the given colors, so masked out pixels can't get a palette of
+/// their own.
+fn check_findable<C: Container>(image: &Image<C>, mask: Option<&MaskOptions>) -> Result<()> {
+    let Some(mask) = mask else {
+        return Ok(());
+    };
+    if let MaskedPixels::Separate = mask.masked_pixels {
+        return Err(anyhow!(
+            "Find remaps to a single palette, masked out pixels can't get their own"
+        ));
+    }
+    check_mask_dimensions(image, &mask.mask)
+}
+
+fn check_mask_dimensions<C: Container>(image: &Image<C>, mask: &Image<Vec<RGBA8>>) -> Result<()> {
+    if mask.dimensions != image.dimensions {
+        return Err(anyhow!(
+            "The mask is {}x{}, but the image is {}x{}",
+            mask.dimensions.0,
+            mask.dimensions.1,
+            image.dimensions.0,
+            image.dimensions.1
+        ));
+    }
+    Ok(())
+}
+
+/// Whether the pixel of the mask selects the pixel under it.
+fn in_region(mask_pixel: &RGBA8) -> bool {
+    mask_pixel.r >= 128
+}
+
+/// Copy of the image where the masked out pixels are fully transparent, so that every algorithm
+/// leaves them out of the palette, or `None` without a mask.
+fn masked_image<C: Container>(
+    image: &Image<C>,
+    mask: Option<&Image<Vec<RGBA8>>>,
+) -> Result<Option<Image<Vec<RGBA8>>>> {
+    let Some(mask) = mask else {
+        return Ok(None);
+    };
+    check_mask_dimensions(image, mask)?;
+
+    let rgba = image
+        .rgba
+        .iter()
+        .zip(mask.rgba.iter())
+        .map(|(&pixel, mask_pixel)| {
+            if in_region(mask_pixel) {
+                pixel
+            } else {
+                TRANSPARENT
+            }
+        })
+        .collect();
+    Ok(Some(Image::new(image.dimensions, rgba)))
+}
+
+/// Mask selecting the pixels the given mask leaves out.
+fn inverted_mask(mask: &Image<Vec<RGBA8>>) -> Image<Vec<RGBA8>> {
+    let rgba = mask
+        .rgba
+        .iter()
+        .map(|mask_pixel| {
+            let value = if in_region(mask_pixel) { 0 } else { 255 };
+            RGBA8::new(value, value, value, 255)
+        })
+        .collect();
+    Image::new(mask.dimensions, rgba)
+}
+
+/// Copies the pixels the mask leaves out from `source` to the output.
+fn fill_masked_pixels<C: Container>(
+    output: &mut Image<Vec<RGBA8>>,
+    source: &Image<C>,
+    mask: &Image<Vec<RGBA8>>,
+) {
+    for ((output_pixel, &pixel), mask_pixel) in output
+        .rgba
+        .iter_mut()
+        .zip(source.rgba.iter())
+        .zip(&mask.rgba)
+    {
+        if !in_region(mask_pixel) {
+            *output_pixel = pixel;
+        }
+    }
+}
+
 async fn kmeans_palette<C: Container>(
     image_processor: &ImageProcessor,
     color_count: u32,
     image: &Image<C>,
-) -> Result<Vec<RGBA8>> {
+    options: &Options,
+) -> Result<(Vec<RGBA8>, KmeansReport)> {
     let input_texture = InputTexture::new(&image_processor.device, &image_processor.queue, image);
 
-    let mut colors = operations::extract_palette_kmeans(
+    let (centroids_buffer, report) = operations::extract_palette_kmeans(
         &image_processor.device,
         &image_processor.queue,
         &input_texture,
-        &ColorSpace::Lab,
+        &options.color_space,
+        &options.alpha.mode,
+        &options.distance_metric()?,
         color_count,
-    )?
-    .pull_values(
-        &image_processor.device,
-        &image_processor.queue,
-        &ColorSpace::Lab,
+        &options.kmeans,
+        &options.sampling,
     )
     .await?;
+    let mut colors = centroids_buffer
+        .pull_values(
+            &image_processor.device,
+            &image_processor.queue,
+            &options.color_space,
+        )
+        .await?;
 
-    colors.sort_unstable_by(|a, b| {
-        let a: Lab = Srgba::new(a.r, a.g, a.b, a.a)
-            .into_format::<_, f32>()
-            .into_color();
-        let b: Lab = Srgba::new(b.r, b.g, b.b, b.a)
-            .into_format::<_, f32>()
-            .into_color();
-        a.l.partial_cmp(&b.l).unwrap()
-    });
-    Ok(colors)
+    sort_by_lightness(&mut colors, &options.color_space);
+    Ok((colors, report))
 }
 
 async fn octree_palette<C: Container>(
     image_processor: &ImageProcessor,
     color_count: u32,
     image: &Image<C>,
+    options: &Options,
 ) -> Result<Vec<RGBA8>> {
-    const MAX_SIZE: u32 = 128;
+    const AUTO_MAX_DIMENSION: u32 = 128;
+
+    let pixels = sampled_pixels(
+        image_processor,
+        color_count,
+        image,
+        AUTO_MAX_DIMENSION,
+        options,
+    )
+    .await?;
+    let mut colors = operations::extract_palette_octree(&pixels, color_count, &options.alpha.mode)?;
+
+    sort_by_lightness(&mut colors, &options.color_space);
 
-    let (width, height) = image.dimensions;
-    let resized = if width > MAX_SIZE || height > MAX_SIZE {
-        let input_texture = InputTexture::new(
+    Ok(colors)
+}
+
+async fn neuquant_palette<C: Container>(
+    image_processor: &ImageProcessor,
+    color_count: u32,
+    image: &Image<C>,
+    options: &Options,
+) -> Result<Vec<RGBA8>> {
+    const AUTO_MAX_DIMENSION: u32 = 256;
+
+    let pixels = sampled_pixels(
+        image_processor,
+        color_count,
+        image,
+        AUTO_MAX_DIMENSION,
+        options,
+    )
+    .await?;
+    let mut colors =
+        operations::extract_palette_neuquant(&pixels, color_count, &options.alpha.mode)?;
+
+    sort_by_lightness(&mut colors, &options.color_space);
+
+    Ok(colors)
+}
+
+/// Pixels of the image downsampled according to [Options::sampling], for the algorithms running
+/// on the CPU. [Sampling::Auto] fits the image in `auto_max_dimension` pixels on its longest side.
+async fn sampled_pixels<'a, C: Container>(
+    image_processor: &ImageProcessor,
+    color_count: u32,
+    image: &'a Image<C>,
+    auto_max_dimension: u32,
+    options: &Options,
+) -> Result<Cow<'a, [RGBA8]>> {
+    let resized = InputTexture::new(&image_processor.device, &image_processor.queue, image)
+        .sampled(
+            &options.sampling,
+            auto_max_dimension,
+            color_count,
             &image_processor.device,
             &image_processor.queue,
-            image,
-        )
-        .resized(MAX_SIZE, &image_processor.device, &image_processor.queue);
-        Some(
-            input_texture
+        )?;
+
+    Ok(match resized {
+        Some(resized) => Cow::Owned(
+            resized
                 .pull_image(&image_processor.device, &image_processor.queue)
-                .await?,
-        )
-    } else {
-        None
-    };
+                .await?
+                .rgba,
+        ),
+        None => Cow::Borrowed(&image.rgba),
+    })
+}
 
-    let pixels: &[RGBA8] = if let Some(resized) = &resized {
-        &resized.rgba
-    } else {
-        &image.rgba
+/// Palette of the histogram based algorithms, [Algorithm::MedianCut] and [Algorithm::Wu].
+async fn histogram_palette<C: Container>(
+    image_processor: &ImageProcessor,
+    color_count: u32,
+    image: &Image<C>,
+    options: &Options,
+) -> Result<Vec<RGBA8>> {
+    let input_texture = InputTexture::new(&image_processor.device, &image_processor.queue, image);
+    let resized = input_texture.sampled(
+        &options.sampling,
+        u32::MAX,
+        color_count,
+        &image_processor.device,
+        &image_processor.queue,
+    )?;
+    let input_texture = resized.as_ref().unwrap_or(&input_texture);
+
+    let mut colors = match options.algo {
+        Algorithm::Wu => {
+            operations::extract_palette_wu(
+                &image_processor.device,
+                &image_processor.queue,
+                input_texture,
+                &options.alpha.mode,
+                color_count,
+            )
+            .await?
+        }
+        _ => {
+            operations::extract_palette_median_cut(
+                &image_processor.device,
+                &image_processor.queue,
+                input_texture,
+                &options.alpha.mode,
+                color_count,
+            )
+            .await?
+        }
     };
 
-    let mut colors = operations::extract_palette_octree(pixels, color_count)?;
+    sort_by_lightness(&mut colors, &options.color_space);
 
+    Ok(colors)
+}
+
+/// Sorts colors from darkest to lightest, measuring lightness in the given color space.
+fn sort_by_lightness(colors: &mut [RGBA8], color_space: &ColorSpace) {
     colors.sort_unstable_by(|a, b| {
-        let a: Lab = Srgba::new(a.r, a.g, a.b, a.a)
-            .into_format::<_, f32>()
-            .into_color();
-        let b: Lab = Srgba::new(b.r, b.g, b.b, b.a)
-            .into_format::<_, f32>()
-            .into_color();
-        a.l.partial_cmp(&b.l).unwrap()
+        lightness(a, color_space)
+            .partial_cmp(&lightness(b, color_space))
+            .unwrap()
     });
+}
 
-    Ok(colors)
+fn lightness(color: &RGBA8, color_space: &ColorSpace) -> f32 {
+    let srgb: Srgb = Srgb::new(color.r, color.g, color.b).into_format();
+    match color_space {
+        ColorSpace::Lab => IntoColor::<Lab>::into_color(srgb).l,
+        ColorSpace::Oklab => IntoColor::<Oklab>::into_color(srgb).l,
+        ColorSpace::Rgb => 0.2126 * srgb.red + 0.7152 * srgb.green + 0.0722 * srgb.blue,
+        ColorSpace::LinearRgb => {
+            let linear = srgb.into_linear::<f32>();
+            0.2126 * linear.red + 0.7152 * linear.green + 0.0722 * linear.blue
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        bayer_matrix, check_indexable, fill_masked_pixels, inverted_mask, masked_image, AlphaMode,
+        AlphaOptions, ColorSpace, DistanceMetric, Options, ThresholdTexture,
+    };
+    use crate::image::Image;
+    use rgb::RGBA8;
+
+    #[test]
+    fn test_bayer_matrix() {
+        let (dimensions, thresholds) = bayer_matrix(4);
+        let expected =
+            [0, 8, 2, 10, 12, 4, 14, 6, 3, 11, 1, 9, 15, 7, 13, 5].map(|value| value as f32 / 16.0);
+
+        assert_eq!((4, 4), dimensions);
+        assert_eq!(&expected[..], &thresholds[..]);
+    }
+
+    #[test]
+    fn test_threshold_texture() {
+        let pixels = vec![RGBA8::new(0, 0, 0, 255), RGBA8::new(128, 0, 0, 255)];
+
+        let texture = ThresholdTexture::new(&Image::new((2, 1), pixels.clone())).unwrap();
+        assert_eq!(texture.dimensions, (2, 1));
+        assert_eq!(texture.thresholds, vec![0.0, 0.5]);
+        assert!(ThresholdTexture::new(&Image::new((0, 0), vec![])).is_err());
+        assert!(ThresholdTexture::new(&Image::new((2, 2), pixels)).is_err());
+    }
+
+    #[test]
+    fn test_masks() {
+        let red = RGBA8::new(255, 0, 0, 255);
+        let blue = RGBA8::new(0, 0, 255, 255);
+        let image = Image::new((2, 1), vec![red, blue]);
+        let mask = Image::new(
+            (2, 1),
+            vec![RGBA8::new(255, 255, 255, 255), RGBA8::new(0, 0, 0, 255)],
+        );
+
+        let masked = masked_image(&image, Some(&mask)).unwrap().unwrap();
+        assert_eq!(masked.rgba, vec![red, RGBA8::new(0, 0, 0, 0)]);
+        assert!(masked_image(&image, None).unwrap().is_none());
+        assert!(masked_image(&image, Some(&Image::new((1, 1), vec![red]))).is_err());
+
+        let black = RGBA8::new(0, 0, 0, 255);
+        let mut output = Image::new((2, 1), vec![black, black]);
+        fill_masked_pixels(&mut output, &image, &mask);
+        assert_eq!(output.rgba, vec![black, blue]);
+
+        let inverted = inverted_mask(&mask);
+        let masked = masked_image(&image, Some(&inverted)).unwrap().unwrap();
+        assert_eq!(masked.rgba, vec![RGBA8::new(0, 0, 0, 0), blue]);
+    }
+
+    #[test]
+    fn test_distance_metric() {
+        let options = |color_space, distance_metric| Options {
+            color_space,
+            distance_metric,
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            options(ColorSpace::Lab, None).distance_metric(),
+            Ok(DistanceMetric::Cie94)
+        ));
+        assert!(matches!(
+            options(ColorSpace::Oklab, None).distance_metric(),
+            Ok(DistanceMetric::Euclidean)
+        ));
+        assert!(matches!(
+            options(ColorSpace::Lab, Some(DistanceMetric::Ciede2000)).distance_metric(),
+            Ok(DistanceMetric::Ciede2000)
+        ));
+        assert!(matches!(
+            options(ColorSpace::Rgb, Some(DistanceMetric::Euclidean)).distance_metric(),
+            Ok(DistanceMetric::Euclidean)
+        ));
+        assert!(options(ColorSpace::LinearRgb, Some(DistanceMetric::Cie94))
+            .distance_metric()
+            .is_err());
+        assert!(matches!(
+            options(ColorSpace::Lab, Some(DistanceMetric::Cie76)).distance_metric(),
+            Ok(DistanceMetric::Cie76)
+        ));
+        assert!(options(ColorSpace::Oklab, Some(DistanceMetric::Cie76))
+            .distance_metric()
+            .is_err());
+        assert!(matches!(
+            "cie76".parse::<DistanceMetric>(),
+            Ok(DistanceMetric::Cie76)
+        ));
+    }
+
+    #[test]
+    fn test_check_indexable() {
+        let opaque = Image::new(
+            (2, 1),
+            vec![RGBA8::new(255, 0, 0, 255), RGBA8::new(0, 0, 0, 0)],
+        );
+        let translucent = Image::new((1, 1), vec![RGBA8::new(255, 0, 0, 128)]);
+        let quantize = Options {
+            alpha: AlphaOptions {
+                mode: AlphaMode::Quantize,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        assert!(check_indexable(&opaque, &Options::default()).is_ok());
+        assert!(check_indexable(&translucent, &Options::default()).is_err());
+        assert!(check_indexable(&translucent, &quantize).is_ok());
+    }
 }
@@ -0,0 +1,143 @@
+use rgb::RGBA8;
+
+/// A histogram bin: the mean color of its pixels, and how many there are.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Bin {
+    color: [f32; 3],
+    count: u32,
+}
+
+/// Non empty bins of a histogram filled by `histogram.wgsl`: 32 x 32 x 32 bins, each holding its
+/// pixel count, then the sum of the 3 low bits of each channel.
+pub(crate) fn bins(histogram: &[[u32; 4]]) -> Vec<Bin> {
+    histogram
+        .iter()
+        .enumerate()
+        .filter(|(_, bin)| bin[0] > 0)
+        .map(|(index, &[count, r, g, b])| {
+            let base = [(index >> 10) & 31, (index >> 5) & 31, index & 31];
+            let low_bits = [r, g, b];
+            Bin {
+                color: [0, 1, 2].map(|channel| {
+                    (base[channel] << 3) as f32 + low_bits[channel] as f32 / count as f32
+                }),
+                count,
+            }
+        })
+        .collect()
+}
+
+/// Heckbert's median cut. Starting from a box holding every bin, splits the box holding the most
+/// pixels in two, at its median pixel along its longest side, until there are `color_count`
+/// boxes or none can be split anymore. Each box gives the mean color of its pixels.
+pub(crate) fn median_cut(bins: Vec<Bin>, color_count: u32) -> Vec<RGBA8> {
+    if bins.is_empty() {
+        return vec![];
+    }
+
+    let mut boxes = vec![bins];
+    while boxes.len() < color_count as usize {
+        let largest = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, bins)| bins.len() > 1)
+            .max_by_key(|(_, bins)| pixel_count(bins))
+            .map(|(index, _)| index);
+        let Some(largest) = largest else {
+            break;
+        };
+
+        let mut bins = boxes.swap_remove(largest);
+        let axis = longest_axis(&bins);
+        bins.sort_unstable_by(|a, b| a.color[axis].total_cmp(&b.color[axis]));
+
+        let half = pixel_count(&bins).div_ceil(2);
+        let mut cumulated = 0;
+        let median = bins
+            .iter()
+            .position(|bin| {
+                cumulated += bin.count as u64;
+                cumulated >= half
+            })
+            .unwrap_or(0);
+        // Both halves keep at least one bin.
+        let other = bins.split_off((median + 1).min(bins.len() - 1));
+
+        boxes.push(bins);
+        boxes.push(other);
+    }
+
+    boxes.iter().map(|bins| mean_color(bins)).collect()
+}
+
+fn pixel_count(bins: &[Bin]) -> u64 {
+    bins.iter().map(|bin| bin.count as u64).sum()
+}
+
+fn longest_axis(bins: &[Bin]) -> usize {
+    let ranges = [0, 1, 2].map(|channel| {
+        let (min, max) = bins.iter().fold((f32::MAX, f32::MIN), |(min, max), bin| {
+            (min.min(bin.color[channel]), max.max(bin.color[channel]))
+        });
+        max - min
+    });
+
+    (0..3)
+        .max_by(|&a, &b| ranges[a].total_cmp(&ranges[b]))
+        .unwrap_or(0)
+}
+
+fn mean_color(bins: &[Bin]) -> RGBA8 {
+    let pixel_count = pixel_count(bins) as f64;
+    let [r, g, b] = [0, 1, 2].map(|channel| {
+        let sum: f64 = bins
+            .iter()
+            .map(|bin| bin.color[channel] as f64 * bin.count as f64)
+            .sum();
+        (sum / pixel_count).round() as u8
+    });
+
+    RGBA8 { r, g, b, a: 255 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structures::tests::histogram;
+
+    #[test]
+    fn test_bins() {
+        let bins = bins(&histogram(&[([255, 0, 9], 2), ([253, 2, 9], 2)]));
+
+        assert_eq!(
+            bins,
+            vec![Bin {
+                color: [254.0, 1.0, 9.0],
+                count: 4
+            }]
+        );
+    }
+
+    #[test]
+    fn test_median_cut() {
+        let bins = bins(&histogram(&[
+            ([0, 0, 0], 10),
+            ([16, 0, 0], 10),
+            ([200, 0, 0], 1),
+            ([0, 0, 100], 1),
+        ]));
+
+        let mut colors = median_cut(bins.clone(), 2);
+        colors.sort_by_key(|color| (color.r, color.b));
+        // The red axis is the longest, and the median falls right after the black pixels.
+        assert_eq!(
+            colors,
+            vec![RGBA8::new(0, 0, 9, 255), RGBA8::new(33, 0, 0, 255)]
+        );
+
+        assert_eq!(median_cut(bins.clone(), 4).len(), 4);
+        // There are only 4 colors to split.
+        assert_eq!(median_cut(bins, 8).len(), 4);
+        assert!(median_cut(vec![], 8).is_empty());
+    }
+}
@@ -1,20 +1,23 @@
+use bytemuck::Pod;
 use log::{debug, log_enabled};
-use std::sync::mpsc::channel;
+use std::{marker::PhantomData, sync::mpsc::channel};
 use wgpu::{
     util::{BufferInitDescriptor, DeviceExt},
     BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayoutDescriptor,
     BindGroupLayoutEntry, BindingResource, BindingType, Buffer, BufferAddress, BufferBinding,
-    BufferBindingType, BufferDescriptor, BufferUsages, CommandEncoderDescriptor, ComputePass,
-    ComputePassDescriptor, ComputePipeline, ComputePipelineDescriptor, Device, MapMode,
-    PipelineLayoutDescriptor, Queue, ShaderSource, ShaderStages, StorageTextureAccess, Texture,
-    TextureDimension, TextureFormat, TextureSampleType, TextureUsages, TextureViewDescriptor,
-    TextureViewDimension,
+    BufferBindingType, BufferDescriptor, BufferUsages, CommandEncoder, CommandEncoderDescriptor,
+    ComputePass, ComputePassDescriptor, ComputePipeline, ComputePipelineDescriptor, Device,
+    MapMode, PipelineLayoutDescriptor, Queue, ShaderSource, ShaderStages, StorageTextureAccess,
+    Texture, TextureDimension, TextureFormat, TextureSampleType, TextureUsages,
+    TextureViewDescriptor, TextureViewDimension,
 };
 
 use crate::{
-    structures::{ColorIndexTexture, OutputTexture, WorkTexture},
-    utils::compute_work_group_count,
-    CentroidsBuffer, ColorSpace, InputTexture,
+    structures::{
+        ColorIndexTexture, HistogramBuffer, OutputTexture, ThresholdMapBuffer, WorkTexture,
+    },
+    utils::{compute_work_group_count, hash_seed},
+    AlphaMode, CentroidsBuffer, ColorSpace, DistanceMetric, InputTexture, KmeansReport,
 };
 
 macro_rules! include_shader {
@@ -24,6 +27,33 @@ macro_rules! include_shader {
 }
 pub(crate) use include_shader;
 
+const DISTANCE_METRIC_DECLARATION: &str = "const distance_metric: u32 = 1u;";
+
+/// Picks the function behind `color_distance` for a shader including `functions/delta_e.wgsl`.
+pub(crate) fn with_distance_metric(shader: &str, distance_metric: &DistanceMetric) -> String {
+    debug_assert!(shader.contains(DISTANCE_METRIC_DECLARATION));
+    shader.replace(
+        DISTANCE_METRIC_DECLARATION,
+        &format!(
+            "const distance_metric: u32 = {}u;",
+            distance_metric.shader_index()
+        ),
+    )
+}
+
+const ALPHA_MODE_DECLARATION: &str = "const quantize_alpha: bool = false;";
+
+/// Sets how `work_alpha` treats alpha, for a shader including `functions/alpha.wgsl`.
+pub(crate) fn with_alpha_mode(shader: &str, alpha_mode: &AlphaMode) -> String {
+    debug_assert!(shader.contains(ALPHA_MODE_DECLARATION));
+    match alpha_mode {
+        AlphaMode::Preserve => shader.to_owned(),
+        AlphaMode::Quantize => {
+            shader.replace(ALPHA_MODE_DECLARATION, "const quantize_alpha: bool = true;")
+        }
+    }
+}
+
 pub(crate) trait Module {
     fn dispatch<'a>(&'a self, compute_pass: &mut ComputePass<'a>);
 }
@@ -38,6 +68,7 @@ impl ColorConverterModule {
     pub fn new(
         device: &Device,
         color_space: &ColorSpace,
+        alpha_mode: &AlphaMode,
         image_dimensions: (u32, u32),
         input_texture: &InputTexture,
         work_texture: &WorkTexture,
@@ -45,10 +76,21 @@ impl ColorConverterModule {
         let convert_color_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("Convert color shader"),
             source: ShaderSource::Wgsl(
-                match color_space {
-                    ColorSpace::Lab => include_shader!("shaders/converters/rgb_to_lab.wgsl"),
-                    ColorSpace::Rgb => include_shader!("shaders/converters/rgb8u_to_rgb32f.wgsl"),
-                }
+                with_alpha_mode(
+                    match color_space {
+                        ColorSpace::Lab => include_shader!("shaders/converters/rgb_to_lab.wgsl"),
+                        ColorSpace::Rgb => {
+                            include_shader!("shaders/converters/rgb8u_to_rgb32f.wgsl")
+                        }
+                        ColorSpace::Oklab => {
+                            include_shader!("shaders/converters/rgb_to_oklab.wgsl")
+                        }
+                        ColorSpace::LinearRgb => {
+                            include_shader!("shaders/converters/rgb_to_linear_rgb.wgsl")
+                        }
+                    },
+                    alpha_mode,
+                )
                 .into(),
             ),
         });
@@ -149,6 +191,10 @@ impl ColorReverterModule {
                 match color_space {
                     ColorSpace::Lab => include_shader!("shaders/converters/lab_to_rgb.wgsl"),
                     ColorSpace::Rgb => include_shader!("shaders/converters/rgb32f_to_rgb8u.wgsl"),
+                    ColorSpace::Oklab => include_shader!("shaders/converters/oklab_to_rgb.wgsl"),
+                    ColorSpace::LinearRgb => {
+                        include_shader!("shaders/converters/linear_rgb_to_rgb.wgsl")
+                    }
                 }
                 .into(),
             ),
@@ -250,6 +296,7 @@ impl SwapModule {
         device: &Device,
         image_dimensions: (u32, u32),
         work_texture: &WorkTexture,
+        swapped_texture: &WorkTexture,
         centroids_buffer: &CentroidsBuffer,
         color_index_texture: &ColorIndexTexture,
     ) -> Self {
@@ -282,6 +329,7 @@ impl SwapModule {
                     count: None,
                 },
                 WorkTexture::texture_storage_layout(2),
+                WorkTexture::texture_2d_layout(3),
             ],
         });
 
@@ -301,6 +349,12 @@ impl SwapModule {
                 },
                 BindGroupEntry {
                     binding: 2,
+                    resource: BindingResource::TextureView(
+                        &swapped_texture.create_view(&TextureViewDescriptor::default()),
+                    ),
+                },
+                BindGroupEntry {
+                    binding: 3,
                     resource: BindingResource::TextureView(
                         &work_texture.create_view(&TextureViewDescriptor::default()),
                     ),
@@ -338,6 +392,97 @@ impl Module for SwapModule {
     }
 }
 
+pub(crate) struct HistogramModule {
+    pipeline: ComputePipeline,
+    bind_group: BindGroup,
+    dispatch_size: (u32, u32),
+}
+
+impl HistogramModule {
+    pub fn new(
+        device: &Device,
+        alpha_mode: &AlphaMode,
+        image_dimensions: (u32, u32),
+        input_texture: &InputTexture,
+        histogram_buffer: &HistogramBuffer,
+    ) -> Self {
+        let histogram_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Histogram shader"),
+            source: ShaderSource::Wgsl(
+                with_alpha_mode(include_shader!("shaders/histogram.wgsl"), alpha_mode).into(),
+            ),
+        });
+
+        let histogram_bind_group_layout =
+            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some("Histogram bind group layout"),
+                entries: &[
+                    BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Texture {
+                            sample_type: TextureSampleType::Float { filterable: true },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    HistogramBuffer::layout(1),
+                ],
+            });
+
+        let histogram_bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Histogram bind group"),
+            layout: &histogram_bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    // Raw sRGB values, the bins are in the image color space.
+                    resource: BindingResource::TextureView(&input_texture.create_view(
+                        &TextureViewDescriptor {
+                            format: Some(TextureFormat::Rgba8Unorm),
+                            dimension: Some(TextureViewDimension::D2),
+                            ..Default::default()
+                        },
+                    )),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: histogram_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let histogram_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Histogram pipeline layout"),
+            bind_group_layouts: &[&histogram_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let histogram_pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: Some("Histogram pipeline"),
+            layout: Some(&histogram_pipeline_layout),
+            module: &histogram_shader,
+            entry_point: "main",
+        });
+
+        let dispatch_size = compute_work_group_count(image_dimensions, (16, 16));
+
+        Self {
+            pipeline: histogram_pipeline,
+            bind_group: histogram_bind_group,
+            dispatch_size,
+        }
+    }
+}
+
+impl Module for HistogramModule {
+    fn dispatch<'a>(&'a self, compute_pass: &mut ComputePass<'a>) {
+        compute_pass.set_pipeline(&self.pipeline);
+        compute_pass.set_bind_group(0, &self.bind_group, &[]);
+        compute_pass.dispatch_workgroups(self.dispatch_size.0, self.dispatch_size.1, 1);
+    }
+}
+
 pub(crate) struct FindCentroidModule {
     pipeline: ComputePipeline,
     bind_group: BindGroup,
@@ -347,6 +492,7 @@ pub(crate) struct FindCentroidModule {
 impl FindCentroidModule {
     pub fn new(
         device: &Device,
+        distance_metric: &DistanceMetric,
         image_dimensions: (u32, u32),
         work_texture: &WorkTexture,
         centroids_buffer: &CentroidsBuffer,
@@ -354,7 +500,13 @@ impl FindCentroidModule {
     ) -> Self {
         let find_centroid_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("Find centroid shader"),
-            source: ShaderSource::Wgsl(include_shader!("shaders/find_centroid.wgsl").into()),
+            source: ShaderSource::Wgsl(
+                with_distance_metric(
+                    include_shader!("shaders/find_centroid.wgsl"),
+                    distance_metric,
+                )
+                .into(),
+            ),
         });
 
         let find_centroid_bind_group_layout =
@@ -449,20 +601,82 @@ impl Module for FindCentroidModule {
     }
 }
 
-pub(crate) struct ConvergenceBuffer {
+/// Storage buffer read back by the CPU, like convergence flags.
+pub(crate) struct ReadbackBuffer<T> {
     gpu_buffer: Buffer,
     mapped_buffer: Buffer,
+    len: u32,
+    marker: PhantomData<T>,
+}
+
+impl<T: Pod> ReadbackBuffer<T> {
+    fn new(device: &Device, len: u32) -> Self {
+        let gpu_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::cast_slice::<T, u8>(&vec![T::zeroed(); len as usize]),
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC,
+        });
+
+        let mapped_buffer = device.create_buffer(&BufferDescriptor {
+            label: None,
+            size: Self::size(len),
+            usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            gpu_buffer,
+            mapped_buffer,
+            len,
+            marker: PhantomData,
+        }
+    }
+
+    fn size(len: u32) -> BufferAddress {
+        len as BufferAddress * std::mem::size_of::<T>() as BufferAddress
+    }
+
+    /// Submits the encoder, then reads the buffer back, or `None` if it couldn't be.
+    fn read(&self, device: &Device, queue: &Queue, mut encoder: CommandEncoder) -> Option<Vec<T>> {
+        encoder.copy_buffer_to_buffer(
+            &self.gpu_buffer,
+            0,
+            &self.mapped_buffer,
+            0,
+            Self::size(self.len),
+        );
+
+        queue.submit(Some(encoder.finish()));
+        let slice = self.mapped_buffer.slice(..);
+
+        let (sender, receiver) = channel();
+        slice.map_async(MapMode::Read, move |v| {
+            sender.send(v).expect("Couldn't send result");
+        });
+
+        device.poll(wgpu::Maintain::Wait);
+
+        match receiver.recv() {
+            Ok(Ok(_)) => {
+                let data = bytemuck::pod_collect_to_vec(&slice.get_mapped_range());
+                self.mapped_buffer.unmap();
+                Some(data)
+            }
+            _ => None,
+        }
+    }
 }
 
 pub(crate) struct ChooseCentroidModule<'a> {
     k: u32,
+    fixed_count: u32,
     choose_pipeline: ComputePipeline,
     pick_pipeline: ComputePipeline,
     bind_group_0: BindGroup,
     bind_group_1: BindGroup,
     bind_groups: Vec<BindGroup>,
     dispatch_size: u32,
-    convergence_buffer: ConvergenceBuffer,
+    convergence_buffer: ReadbackBuffer<u32>,
     find_centroid_module: &'a FindCentroidModule,
     centroids_buffer: &'a CentroidsBuffer,
 }
@@ -471,10 +685,13 @@ impl<'a> ChooseCentroidModule<'a> {
     #[allow(clippy::too_many_arguments)]
     pub fn new(
         device: &Device,
-        color_space: &ColorSpace,
+        tolerance: f32,
+        distance_metric: &DistanceMetric,
         image_dimensions: (u32, u32),
         k: u32,
+        fixed_count: u32,
         work_texture: &WorkTexture,
+        weight_texture: &InputTexture,
         centroids_buffer: &'a CentroidsBuffer,
         color_index_texture: &ColorIndexTexture,
         find_centroid_module: &'a FindCentroidModule,
@@ -483,7 +700,13 @@ impl<'a> ChooseCentroidModule<'a> {
         const N_SEQ: u32 = 20;
         let choose_centroid_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("Choose centroid shader"),
-            source: ShaderSource::Wgsl(include_shader!("shaders/choose_centroid.wgsl").into()),
+            source: ShaderSource::Wgsl(
+                with_distance_metric(
+                    include_shader!("shaders/choose_centroid.wgsl"),
+                    distance_metric,
+                )
+                .into(),
+            ),
         });
 
         let choose_centroid_bind_group_0_layout =
@@ -530,6 +753,7 @@ impl<'a> ChooseCentroidModule<'a> {
                         },
                         count: None,
                     },
+                    InputTexture::texture_2d_layout(4),
                 ],
             });
 
@@ -564,13 +788,19 @@ impl<'a> ChooseCentroidModule<'a> {
                     binding: 3,
                     resource: part_id_buffer.as_entire_binding(),
                 },
+                BindGroupEntry {
+                    binding: 4,
+                    resource: BindingResource::TextureView(
+                        &weight_texture.create_view(&TextureViewDescriptor::default()),
+                    ),
+                },
             ],
         });
 
         let mut choose_centroid_settings_content: Vec<u8> = Vec::new();
         choose_centroid_settings_content.extend_from_slice(bytemuck::cast_slice(&[N_SEQ]));
-        choose_centroid_settings_content
-            .extend_from_slice(bytemuck::cast_slice(&[color_space.convergence()]));
+        choose_centroid_settings_content.extend_from_slice(bytemuck::cast_slice(&[tolerance]));
+        choose_centroid_settings_content.extend_from_slice(bytemuck::cast_slice(&[fixed_count]));
         let choose_centroid_settings_buffer = device.create_buffer_init(&BufferInitDescriptor {
             label: None,
             contents: &choose_centroid_settings_content,
@@ -595,18 +825,8 @@ impl<'a> ChooseCentroidModule<'a> {
             usage: BufferUsages::STORAGE,
             mapped_at_creation: false,
         });
-        let convergence_buffer = device.create_buffer_init(&BufferInitDescriptor {
-            label: None,
-            contents: bytemuck::cast_slice::<u32, u8>(&vec![0; k as usize + 1]),
-            usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC,
-        });
-
-        let check_convergence_buffer = device.create_buffer(&BufferDescriptor {
-            label: None,
-            size: (k + 1) as u64 * 4,
-            usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
-            mapped_at_creation: false,
-        });
+        // One flag per centroid, then their sum.
+        let convergence_buffer = ReadbackBuffer::new(device, k + 1);
 
         let choose_centroid_bind_group_1_layout =
             device.create_bind_group_layout(&BindGroupLayoutDescriptor {
@@ -669,7 +889,7 @@ impl<'a> ChooseCentroidModule<'a> {
                 },
                 BindGroupEntry {
                     binding: 2,
-                    resource: convergence_buffer.as_entire_binding(),
+                    resource: convergence_buffer.gpu_buffer.as_entire_binding(),
                 },
                 BindGroupEntry {
                     binding: 3,
@@ -745,28 +965,33 @@ impl<'a> ChooseCentroidModule<'a> {
 
         Self {
             k,
+            fixed_count,
             choose_pipeline,
             pick_pipeline,
             bind_group_0: choose_centroid_bind_group_0,
             bind_group_1: choose_centroid_bind_group_1,
             bind_groups,
             dispatch_size,
-            convergence_buffer: ConvergenceBuffer {
-                gpu_buffer: convergence_buffer,
-                mapped_buffer: check_convergence_buffer,
-            },
+            convergence_buffer,
             find_centroid_module,
             centroids_buffer,
         }
     }
 
-    pub(crate) fn compute(&self, device: &Device, queue: &Queue) {
+    /// Runs up to `max_iterations` iterations, checking for convergence every `check_interval`
+    /// iterations, and after the last one.
+    pub(crate) fn compute(
+        &self,
+        device: &Device,
+        queue: &Queue,
+        max_iterations: u32,
+        check_interval: u32,
+    ) -> KmeansReport {
         const MAX_OBS_CHAIN: usize = 64;
-        const MAX_ITERATION: u32 = 128;
-        const MAX_ITERATION_BEFORE_CONVERGENCE_CHECK: u32 = 8;
         let mut current_iteration = 0;
+        let mut converged = false;
 
-        'iteration: for iteration in 0..MAX_ITERATION {
+        'iteration: for iteration in 0..max_iterations {
             current_iteration = iteration;
             let mut encoder =
                 device.create_command_encoder(&CommandEncoderDescriptor { label: None });
@@ -779,9 +1004,12 @@ impl<'a> ChooseCentroidModule<'a> {
                 compute_pass.set_bind_group(0, &self.bind_group_0, &[]);
                 compute_pass.set_bind_group(1, &self.bind_group_1, &[]);
                 for k in k_start..max_k {
-                    compute_pass.set_bind_group(2, &self.bind_groups[k as usize], &[]);
-                    compute_pass.set_pipeline(&self.choose_pipeline);
-                    compute_pass.dispatch_workgroups(self.dispatch_size, 1, 1);
+                    compute_pass.set_bind_group(2, &self.bind_groups[k], &[]);
+                    // Fixed centroids don't need their pixels averaged, only the convergence flag.
+                    if k >= self.fixed_count as usize {
+                        compute_pass.set_pipeline(&self.choose_pipeline);
+                        compute_pass.dispatch_workgroups(self.dispatch_size, 1, 1);
+                    }
                     compute_pass.set_pipeline(&self.pick_pipeline);
                     compute_pass.dispatch_workgroups(1, 1, 1);
                 }
@@ -799,41 +1027,19 @@ impl<'a> ChooseCentroidModule<'a> {
                 self.find_centroid_module.dispatch(&mut compute_pass);
             }
 
-            if iteration > 0 && iteration % MAX_ITERATION_BEFORE_CONVERGENCE_CHECK == 0 {
-                encoder.copy_buffer_to_buffer(
-                    &self.convergence_buffer.gpu_buffer,
-                    0,
-                    &self.convergence_buffer.mapped_buffer,
-                    0,
-                    (self.k + 1) as u64 * 4,
-                );
-
-                queue.submit(Some(encoder.finish()));
-                let check_convergence_slice = self.convergence_buffer.mapped_buffer.slice(..);
-
-                let (sender, receiver) = channel();
-                check_convergence_slice.map_async(MapMode::Read, move |v| {
-                    sender.send(v).expect("Couldn't send result");
-                });
-
-                device.poll(wgpu::Maintain::Wait);
-
-                match receiver.recv() {
-                    Ok(Ok(_)) => {
-                        let convergence_data = bytemuck::cast_slice::<u8, u32>(
-                            &check_convergence_slice.get_mapped_range(),
-                        )
-                        .to_vec();
+            let last_iteration = iteration + 1 == max_iterations;
+            if (iteration > 0 && iteration % check_interval == 0) || last_iteration {
+                match self.convergence_buffer.read(device, queue, encoder) {
+                    Some(convergence_data) => {
                         if convergence_data[self.k as usize] >= self.k {
                             // We converged, time to go.
                             debug!("We have convergence, checked at iteration {iteration}");
+                            converged = true;
                             break 'iteration;
                         }
                     }
-                    _ => break 'iteration,
+                    None => break 'iteration,
                 }
-
-                self.convergence_buffer.mapped_buffer.unmap();
             } else {
                 queue.submit(Some(encoder.finish()));
             }
@@ -868,6 +1074,416 @@ impl<'a> ChooseCentroidModule<'a> {
             staging_buffer.unmap();
             debug!("========================");
         }
+
+        KmeansReport {
+            iterations: current_iteration + 1,
+            converged,
+        }
+    }
+}
+
+/// Fills a batch with pixels drawn at random from the whole image.
+pub(crate) struct SampleBatchModule {
+    pipeline: ComputePipeline,
+    bind_group: BindGroup,
+    seed_buffer: Buffer,
+    dispatch_size: (u32, u32),
+}
+
+impl SampleBatchModule {
+    pub fn new(
+        device: &Device,
+        input_texture: &InputTexture,
+        weight_texture: &InputTexture,
+        batch_texture: &InputTexture,
+        batch_weight_texture: &InputTexture,
+    ) -> Self {
+        let sample_batch_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Sample batch shader"),
+            source: ShaderSource::Wgsl(include_shader!("shaders/sample_batch.wgsl").into()),
+        });
+
+        let pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: Some("Sample batch pipeline"),
+            layout: None,
+            module: &sample_batch_shader,
+            entry_point: "main",
+        });
+
+        let seed_buffer = device.create_buffer(&BufferDescriptor {
+            label: None,
+            size: 4,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Sample batch bind group"),
+            layout: &pipeline.get_bind_group_layout(0),
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(
+                        &input_texture.create_view(&TextureViewDescriptor::default()),
+                    ),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::TextureView(
+                        &batch_texture.create_view(&TextureViewDescriptor::default()),
+                    ),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: seed_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: BindingResource::TextureView(
+                        &weight_texture.create_view(&TextureViewDescriptor::default()),
+                    ),
+                },
+                BindGroupEntry {
+                    binding: 4,
+                    resource: BindingResource::TextureView(
+                        &batch_weight_texture.create_view(&TextureViewDescriptor::default()),
+                    ),
+                },
+            ],
+        });
+
+        let dispatch_size = compute_work_group_count(batch_texture.dimensions, (16, 16));
+
+        Self {
+            pipeline,
+            bind_group,
+            seed_buffer,
+            dispatch_size,
+        }
+    }
+
+    /// Picks the pixels of the next batches, taking effect at the next submission.
+    pub fn write_seed(&self, queue: &Queue, seed: u64) {
+        queue.write_buffer(
+            &self.seed_buffer,
+            0,
+            bytemuck::cast_slice(&[hash_seed(seed)]),
+        );
+    }
+}
+
+impl Module for SampleBatchModule {
+    fn dispatch<'a>(&'a self, compute_pass: &mut ComputePass<'a>) {
+        compute_pass.set_pipeline(&self.pipeline);
+        compute_pass.set_bind_group(0, &self.bind_group, &[]);
+        compute_pass.dispatch_workgroups(self.dispatch_size.0, self.dispatch_size.1, 1);
+    }
+}
+
+/// Mini-batch k-means: each iteration moves the centroids towards a new batch of random pixels,
+/// instead of averaging every pixel of the image.
+pub(crate) struct MiniBatchModule<'a> {
+    k: u32,
+    check_interval: u32,
+    pipeline: ComputePipeline,
+    bind_group: BindGroup,
+    inertia_buffer: ReadbackBuffer<f32>,
+    slot_buffer: Buffer,
+    sample_batch_module: &'a SampleBatchModule,
+    color_converter_module: &'a ColorConverterModule,
+    find_centroid_module: &'a FindCentroidModule,
+}
+
+impl<'a> MiniBatchModule<'a> {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        device: &Device,
+        distance_metric: &DistanceMetric,
+        k: u32,
+        fixed_count: u32,
+        check_interval: u32,
+        work_texture: &WorkTexture,
+        batch_weight_texture: &InputTexture,
+        centroids_buffer: &CentroidsBuffer,
+        color_index_texture: &ColorIndexTexture,
+        sample_batch_module: &'a SampleBatchModule,
+        color_converter_module: &'a ColorConverterModule,
+        find_centroid_module: &'a FindCentroidModule,
+    ) -> Self {
+        let mini_batch_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Mini-batch shader"),
+            source: ShaderSource::Wgsl(
+                with_distance_metric(include_shader!("shaders/mini_batch.wgsl"), distance_metric)
+                    .into(),
+            ),
+        });
+
+        let storage_layout = |binding| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only: false },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let mini_batch_bind_group_layout =
+            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some("Mini-batch bind group layout"),
+                entries: &[
+                    storage_layout(0),
+                    BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Texture {
+                            sample_type: TextureSampleType::Uint,
+                            view_dimension: TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    WorkTexture::texture_2d_layout(2),
+                    storage_layout(3),
+                    storage_layout(4),
+                    BindGroupLayoutEntry {
+                        binding: 5,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    InputTexture::texture_2d_layout(6),
+                    BindGroupLayoutEntry {
+                        binding: 7,
+                        visibility: ShaderStages::COMPUTE,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+            });
+
+        let learned_weights_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::cast_slice::<f32, u8>(&vec![0.0; k as usize]),
+            usage: BufferUsages::STORAGE,
+        });
+        // The inertia and the weight of each centroid, for every batch between two checks.
+        let inertia_buffer = ReadbackBuffer::new(device, check_interval * k * 2);
+        let settings_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::cast_slice(&[fixed_count]),
+            usage: BufferUsages::UNIFORM,
+        });
+        let slot_buffer = device.create_buffer(&BufferDescriptor {
+            label: None,
+            size: 4,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Mini-batch bind group"),
+            layout: &mini_batch_bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: centroids_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::TextureView(
+                        &color_index_texture.create_view(&TextureViewDescriptor::default()),
+                    ),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: BindingResource::TextureView(
+                        &work_texture.create_view(&TextureViewDescriptor::default()),
+                    ),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: learned_weights_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 4,
+                    resource: inertia_buffer.gpu_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 5,
+                    resource: settings_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 6,
+                    resource: BindingResource::TextureView(
+                        &batch_weight_texture.create_view(&TextureViewDescriptor::default()),
+                    ),
+                },
+                BindGroupEntry {
+                    binding: 7,
+                    resource: slot_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let mini_batch_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Mini-batch pipeline layout"),
+            bind_group_layouts: &[&mini_batch_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: Some("Mini-batch pipeline"),
+            layout: Some(&mini_batch_pipeline_layout),
+            module: &mini_batch_shader,
+            entry_point: "main",
+        });
+
+        Self {
+            k,
+            check_interval,
+            pipeline,
+            bind_group,
+            inertia_buffer,
+            slot_buffer,
+            sample_batch_module,
+            color_converter_module,
+            find_centroid_module,
+        }
+    }
+
+    /// Runs up to `max_iterations` iterations, each on a new batch, checking for convergence every
+    /// `check_interval` iterations, and after the last one. The first batch is expected to be
+    /// sampled and converted already, as k-means++ picks the initial centroids from it.
+    ///
+    /// The centroids move less and less with every batch, so how much they move says little. Like
+    /// scikit-learn, the run converged once the mean distance between the pixels of each batch and
+    /// their centroid, smoothed over the batches by `smoothing`, didn't improve for
+    /// [PATIENCE] batches in a row.
+    pub(crate) fn compute(
+        &self,
+        device: &Device,
+        queue: &Queue,
+        seed: u64,
+        max_iterations: u32,
+        smoothing: f32,
+    ) -> KmeansReport {
+        let mut current_iteration = 0;
+        let mut converged = false;
+        let mut stopping = InertiaStopping::new(smoothing);
+
+        for iteration in 0..max_iterations {
+            current_iteration = iteration;
+            let slot = iteration % self.check_interval;
+            queue.write_buffer(&self.slot_buffer, 0, bytemuck::cast_slice(&[slot]));
+            let mut encoder =
+                device.create_command_encoder(&CommandEncoderDescriptor { label: None });
+            {
+                let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+                    label: Some("Mini-batch pass"),
+                });
+                if iteration > 0 {
+                    self.sample_batch_module
+                        .write_seed(queue, seed.wrapping_add(iteration as u64));
+                    self.sample_batch_module.dispatch(&mut compute_pass);
+                    self.color_converter_module.dispatch(&mut compute_pass);
+                }
+                self.find_centroid_module.dispatch(&mut compute_pass);
+
+                compute_pass.set_pipeline(&self.pipeline);
+                compute_pass.set_bind_group(0, &self.bind_group, &[]);
+                compute_pass.dispatch_workgroups(self.k, 1, 1);
+            }
+
+            let last_iteration = iteration + 1 == max_iterations;
+            if slot + 1 == self.check_interval || last_iteration {
+                match self.inertia_buffer.read(device, queue, encoder) {
+                    Some(inertia_data) => {
+                        let batches = inertia_data
+                            .chunks_exact(self.k as usize * 2)
+                            .take(slot as usize + 1);
+                        if batches
+                            .map(batch_inertia)
+                            .any(|inertia| stopping.stop(inertia))
+                        {
+                            debug!("We have convergence, checked at iteration {iteration}");
+                            converged = true;
+                            break;
+                        }
+                    }
+                    None => break,
+                }
+            } else {
+                queue.submit(Some(encoder.finish()));
+            }
+        }
+
+        KmeansReport {
+            iterations: current_iteration + 1,
+            converged,
+        }
+    }
+}
+
+/// Batches in a row without improvement before mini-batch k-means stops, like scikit-learn.
+pub(crate) const PATIENCE: u32 = 10;
+
+/// Mean distance between the pixels of a batch and their centroid, from the inertia and weight of
+/// each centroid.
+fn batch_inertia(centroids: &[f32]) -> f32 {
+    let (inertia, weight) = centroids
+        .chunks_exact(2)
+        .fold((0.0, 0.0), |(inertia, weight), centroid| {
+            (inertia + centroid[0], weight + centroid[1])
+        });
+    if weight > 0.0 {
+        inertia / weight
+    } else {
+        0.0
+    }
+}
+
+/// Stopping rule of mini-batch k-means: an exponentially weighted average of the batch inertia,
+/// that has to keep reaching new lows.
+pub(crate) struct InertiaStopping {
+    smoothing: f32,
+    average: Option<f32>,
+    lowest: f32,
+    without_improvement: u32,
+}
+
+impl InertiaStopping {
+    pub(crate) fn new(smoothing: f32) -> Self {
+        Self {
+            smoothing,
+            average: None,
+            lowest: f32::MAX,
+            without_improvement: 0,
+        }
+    }
+
+    /// Takes the inertia of the next batch, and tells whether to stop.
+    pub(crate) fn stop(&mut self, inertia: f32) -> bool {
+        let average = match self.average {
+            Some(average) => average + (inertia - average) * self.smoothing,
+            None => inertia,
+        };
+        self.average = Some(average);
+
+        if average < self.lowest {
+            self.lowest = average;
+            self.without_improvement = 0;
+        } else {
+            self.without_improvement += 1;
+        }
+        self.without_improvement >= PATIENCE
     }
 }
 
@@ -921,25 +1537,40 @@ impl DistanceMapTexture {
     }
 }
 
+const INITIAL_INDEX_DECLARATION: &str = "const initial_index: u32 = 0u;";
+
 pub(crate) struct PlusPlusInitModule<'a> {
+    distance_metric: DistanceMetric,
     k: u32,
+    initial_count: u32,
+    seed: u64,
     image_dimensions: (u32, u32),
     centroids_buffer: &'a CentroidsBuffer,
     work_texture: &'a WorkTexture,
+    weight_texture: &'a InputTexture,
 }
 
 impl<'a> PlusPlusInitModule<'a> {
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn new(
+        distance_metric: &DistanceMetric,
         image_dimensions: (u32, u32),
         k: u32,
+        initial_count: u32,
+        seed: u64,
         work_texture: &'a WorkTexture,
+        weight_texture: &'a InputTexture,
         centroids_buffer: &'a CentroidsBuffer,
     ) -> Self {
         Self {
+            distance_metric: *distance_metric,
             k,
+            initial_count,
+            seed,
             image_dimensions,
             centroids_buffer,
             work_texture,
+            weight_texture,
         }
     }
 
@@ -951,7 +1582,14 @@ impl<'a> PlusPlusInitModule<'a> {
         let distance_map_texture = DistanceMapTexture::new(device, self.image_dimensions);
         let choose_centroid_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("Plus plus init shader"),
-            source: ShaderSource::Wgsl(include_shader!("shaders/plus_plus_init.wgsl").into()),
+            source: ShaderSource::Wgsl(
+                include_shader!("shaders/plus_plus_init.wgsl")
+                    .replace(
+                        INITIAL_INDEX_DECLARATION,
+                        &format!("const initial_index: u32 = {}u;", hash_seed(self.seed)),
+                    )
+                    .into(),
+            ),
         });
 
         let choose_centroid_bind_group_layout =
@@ -1009,6 +1647,7 @@ impl<'a> PlusPlusInitModule<'a> {
                         count: None,
                     },
                     DistanceMapTexture::texture_2d_layout(5),
+                    InputTexture::texture_2d_layout(6),
                 ],
             });
 
@@ -1071,6 +1710,14 @@ impl<'a> PlusPlusInitModule<'a> {
                             .create_view(&TextureViewDescriptor::default()),
                     ),
                 },
+                BindGroupEntry {
+                    binding: 6,
+                    resource: BindingResource::TextureView(
+                        &self
+                            .weight_texture
+                            .create_view(&TextureViewDescriptor::default()),
+                    ),
+                },
             ],
         });
 
@@ -1158,6 +1805,7 @@ impl<'a> PlusPlusInitModule<'a> {
                     CentroidsBuffer::layout(0, true),
                     WorkTexture::texture_2d_layout(1),
                     DistanceMapTexture::texture_storage_layout(2),
+                    InputTexture::texture_2d_layout(3),
                 ],
             });
 
@@ -1169,7 +1817,13 @@ impl<'a> PlusPlusInitModule<'a> {
 
         let calc_diff_shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("Calc diff shader"),
-            source: ShaderSource::Wgsl(include_shader!("shaders/kmeans++_calc_diff.wgsl").into()),
+            source: ShaderSource::Wgsl(
+                with_distance_metric(
+                    include_shader!("shaders/kmeans++_calc_diff.wgsl"),
+                    &self.distance_metric,
+                )
+                .into(),
+            ),
         });
 
         let calc_diff_bind_group = device.create_bind_group(&BindGroupDescriptor {
@@ -1196,6 +1850,14 @@ impl<'a> PlusPlusInitModule<'a> {
                             .create_view(&TextureViewDescriptor::default()),
                     ),
                 },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: BindingResource::TextureView(
+                        &self
+                            .weight_texture
+                            .create_view(&TextureViewDescriptor::default()),
+                    ),
+                },
             ],
         });
 
@@ -1208,7 +1870,9 @@ impl<'a> PlusPlusInitModule<'a> {
 
         let calc_diff_dispatch_size = compute_work_group_count(self.image_dimensions, (16, 16));
 
-        for k_start in (0..self.k as usize).step_by(MAX_OPERATIONS_CHAIN) {
+        // The first centroids were given, picking continues from there.
+        for k_start in (self.initial_count as usize..self.k as usize).step_by(MAX_OPERATIONS_CHAIN)
+        {
             let max_k = (k_start + MAX_OPERATIONS_CHAIN).min(self.k as usize);
 
             let mut encoder =
@@ -1217,8 +1881,8 @@ impl<'a> PlusPlusInitModule<'a> {
                 let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
                     label: Some("Plus plus init pass"),
                 });
-                for k in k_start..max_k {
-                    compute_pass.set_bind_group(1, &bind_groups[k as usize], &[]);
+                for (k, k_bind_group) in bind_groups.iter().enumerate().take(max_k).skip(k_start) {
+                    compute_pass.set_bind_group(1, k_bind_group, &[]);
                     if k == 0 {
                         compute_pass.set_pipeline(&initial_pipeline);
                         compute_pass.set_bind_group(0, &bind_group, &[]);
@@ -1297,18 +1961,24 @@ pub(crate) struct MixColorsModule {
 }
 
 impl MixColorsModule {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         device: &Device,
+        distance_metric: &DistanceMetric,
         image_dimensions: (u32, u32),
         input_texture: &WorkTexture,
         output_texture: &WorkTexture,
         color_index_texture: &ColorIndexTexture,
         centroids_buffer: &CentroidsBuffer,
+        threshold_map_buffer: &ThresholdMapBuffer,
         mix_mode: &MixMode,
     ) -> Self {
         let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("Mix colors shader"),
-            source: ShaderSource::Wgsl(include_shader!("shaders/mix_colors.wgsl").into()),
+            source: ShaderSource::Wgsl(
+                with_distance_metric(include_shader!("shaders/mix_colors.wgsl"), distance_metric)
+                    .into(),
+            ),
         });
 
         let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
@@ -1316,8 +1986,9 @@ impl MixColorsModule {
             entries: &[
                 WorkTexture::texture_2d_layout(0),
                 WorkTexture::texture_storage_layout(1),
-                ColorIndexTexture::texture_2d_layout(2),
+                ColorIndexTexture::texture_storage_layout(2),
                 CentroidsBuffer::layout(3, true),
+                ThresholdMapBuffer::layout(4),
             ],
         });
 
@@ -1347,6 +2018,10 @@ impl MixColorsModule {
                     binding: 3,
                     resource: centroids_buffer.as_entire_binding(),
                 },
+                BindGroupEntry {
+                    binding: 4,
+                    resource: threshold_map_buffer.as_entire_binding(),
+                },
             ],
         });
 
@@ -1383,3 +2058,26 @@ impl Module for MixColorsModule {
         compute_pass.dispatch_workgroups(self.dispatch_size.0, self.dispatch_size.1, 1);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{InertiaStopping, PATIENCE};
+
+    #[test]
+    fn test_inertia_stopping() {
+        // Keeps going while the inertia drops, even by a little.
+        let mut stopping = InertiaStopping::new(0.5);
+        for batch in 0..100 {
+            assert!(!stopping.stop(100.0 / (batch + 1) as f32));
+        }
+
+        // Stops after the patience runs out on a plateau, noise included.
+        let mut stopping = InertiaStopping::new(0.5);
+        let batches = (0..100)
+            .map(|batch| if batch % 2 == 0 { 10.0 } else { 10.5 })
+            .position(|inertia| stopping.stop(inertia))
+            .unwrap();
+        assert!(batches as u32 >= PATIENCE);
+        assert!(batches < 20);
+    }
+}
@@ -0,0 +1,87 @@
+use color_quant::NeuQuant;
+use rgb::{ComponentBytes, RGBA8};
+
+/// Learns from every pixel, so the pixels are expected to be downsampled beforehand.
+const SAMPLE_FACTOR: i32 = 1;
+
+/// Anthony Dekker's NeuQuant, a Kohonen self-organizing map, from the `color_quant` crate. The
+/// neurons start along the gray axis, then each pixel pulls the neuron closest to it, and to a
+/// lesser extent its neighbors in the network, towards its color. A bias keeps a few neurons from
+/// winning every pixel.
+///
+/// Neurons that win no pixel, like the ones left along the gray axis when the image has fewer
+/// colors than the network, are dropped, along with duplicates, so the palette can be smaller than
+/// `color_count`. Transparent pixels are expected to be filtered out.
+pub(crate) fn neuquant(pixels: &[RGBA8], color_count: u32) -> Vec<RGBA8> {
+    if pixels.is_empty() || color_count == 0 {
+        return vec![];
+    }
+
+    // Only the colors are learned, the caller already took care of the alpha.
+    let pixels: Vec<_> = pixels.iter().map(|pixel| pixel.rgb().alpha(255)).collect();
+    let network = NeuQuant::new(SAMPLE_FACTOR, color_count as usize, pixels.as_bytes());
+
+    let network: Vec<_> = network
+        .color_map_rgba()
+        .chunks_exact(4)
+        .map(|color| RGBA8::new(color[0], color[1], color[2], 255))
+        .collect();
+
+    // The lookup of the crate only searches around the green of the pixel, and can miss the
+    // closest neuron, so each pixel looks through all of them.
+    let mut won = vec![false; network.len()];
+    for pixel in &pixels {
+        let closest = network
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, neuron)| {
+                [
+                    neuron.r.abs_diff(pixel.r),
+                    neuron.g.abs_diff(pixel.g),
+                    neuron.b.abs_diff(pixel.b),
+                ]
+                .map(|difference| difference as u32 * difference as u32)
+                .iter()
+                .sum::<u32>()
+            })
+            .map(|(index, _)| index);
+        if let Some(closest) = closest {
+            won[closest] = true;
+        }
+    }
+
+    let mut colors: Vec<RGBA8> = vec![];
+    for (color, _) in network.into_iter().zip(won).filter(|(_, won)| *won) {
+        if !colors.contains(&color) {
+            colors.push(color);
+        }
+    }
+    colors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_neuquant() {
+        let colors = [
+            RGBA8::new(255, 0, 0, 255),
+            RGBA8::new(0, 0, 255, 255),
+            RGBA8::new(255, 255, 255, 255),
+        ];
+        let pixels: Vec<_> = colors.iter().cycle().take(3000).copied().collect();
+
+        let palette = neuquant(&pixels, 3);
+
+        assert_eq!(palette.len(), 3);
+        for color in colors {
+            assert!(palette.contains(&color), "{color:?} not in {palette:?}");
+        }
+        assert!(neuquant(&[], 3).is_empty());
+
+        // Neurons no pixel picks are dropped.
+        let palette = neuquant(&pixels, 16);
+        assert!(palette.len() <= 3, "{palette:?}");
+    }
+}
@@ -216,31 +216,31 @@ impl Eq for Node {}
 
 impl PartialOrd for Node {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Node {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
         if self.eq(other) {
-            return Some(std::cmp::Ordering::Equal);
+            return std::cmp::Ordering::Equal;
         }
 
-        match self.child_count.partial_cmp(&other.child_count) {
-            Some(core::cmp::Ordering::Equal) => {}
+        match self.child_count.cmp(&other.child_count) {
+            std::cmp::Ordering::Equal => {}
             ord => return ord,
         };
 
         let ac = self.accumulator.pixel_count >> self.level;
         let bc = other.accumulator.pixel_count >> other.level;
 
-        match ac.partial_cmp(&bc) {
-            Some(std::cmp::Ordering::Equal) => self.node_id.partial_cmp(&other.node_id),
+        match ac.cmp(&bc) {
+            std::cmp::Ordering::Equal => self.node_id.0.cmp(&other.node_id.0),
             ord => ord,
         }
     }
 }
 
-impl Ord for Node {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.partial_cmp(other).unwrap_or(std::cmp::Ordering::Equal)
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use rgb::RGBA;
@@ -299,7 +299,7 @@ mod tests {
             [232, 193, 112, 255],
             [235, 237, 233, 255],
         ]
-        .map(|rgba| RGBA::from(rgba));
+        .map(RGBA::from);
         println!("Sorting {} colors", pixels.len());
         for pixel in &pixels {
             tree.add_color(pixel);
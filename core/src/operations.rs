@@ -1,49 +1,235 @@
-use anyhow::Result;
+use std::{ops::RangeInclusive, sync::Arc};
+
+use anyhow::{anyhow, Result};
 use rgb::RGBA8;
 use wgpu::{CommandEncoderDescriptor, ComputePassDescriptor, Device, Queue};
 
 use crate::{
+    color_count,
+    diffusion::{diffuse, DiffusionKernel},
+    image::Image,
+    median_cut,
     modules::{
         ChooseCentroidModule, ColorConverterModule, ColorReverterModule, FindCentroidModule,
-        MixColorsModule, MixMode, Module, PlusPlusInitModule, SwapModule,
+        HistogramModule, MiniBatchModule, MixColorsModule, MixMode, Module, PlusPlusInitModule,
+        SampleBatchModule, SwapModule,
     },
+    neuquant,
     octree::ColorTree,
-    structures::{ColorIndexTexture, OutputTexture, WorkTexture},
-    CentroidsBuffer, ColorSpace, InputTexture,
+    structures::{
+        ColorIndexTexture, HistogramBuffer, OutputTexture, ThresholdMapBuffer, WorkTexture,
+        TRANSPARENT_INDEX,
+    },
+    wu, AlphaMode, CentroidsBuffer, ColorCountReport, ColorCountSelection, ColorSpace,
+    DistanceMetric, DitherOptions, Initialization, InputTexture, KmeansOptions, KmeansReport,
+    ResizeFilter, SamplingOptions,
 };
 
-pub(crate) fn extract_palette_kmeans(
+/// Longest side of the image k-means works on, with [Sampling::Auto].
+const KMEANS_MAX_DIMENSION: u32 = 256;
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn extract_palette_kmeans(
+    device: &Arc<Device>,
+    queue: &Queue,
+    input_texture: &InputTexture,
+    color_space: &ColorSpace,
+    alpha_mode: &AlphaMode,
+    distance_metric: &DistanceMetric,
+    k: u32,
+    kmeans_options: &KmeansOptions,
+    sampling_options: &SamplingOptions,
+) -> Result<(CentroidsBuffer, KmeansReport)> {
+    let sample = KmeansSample::new(
+        device,
+        queue,
+        input_texture,
+        alpha_mode,
+        kmeans_options,
+        sampling_options,
+        k,
+    )
+    .await?;
+    kmeans(
+        device,
+        queue,
+        input_texture,
+        &sample,
+        color_space,
+        alpha_mode,
+        distance_metric,
+        k,
+        kmeans_options,
+    )
+}
+
+/// The pixels k-means learns from: the image downsampled according to the [SamplingOptions], along
+/// with the weight of each of them. Mini-batch k-means draws its batches from the full resolution
+/// image instead.
+struct KmeansSample {
+    /// `None` when the image is used as is.
+    texture: Option<InputTexture>,
+    weight_texture: InputTexture,
+    /// Histogram of the full resolution image, that the centroids start from with
+    /// [Initialization::Wu].
+    wu_histogram: Option<Vec<[u32; 4]>>,
+}
+
+impl KmeansSample {
+    async fn new(
+        device: &Arc<Device>,
+        queue: &Queue,
+        input_texture: &InputTexture,
+        alpha_mode: &AlphaMode,
+        kmeans_options: &KmeansOptions,
+        sampling_options: &SamplingOptions,
+        k: u32,
+    ) -> Result<Self> {
+        let texture =
+            input_texture.sampled(sampling_options, KMEANS_MAX_DIMENSION, k, device, queue)?;
+        let weight_texture = weight_texture(
+            device,
+            queue,
+            kmeans_options.weights.as_ref(),
+            input_texture.dimensions,
+            texture
+                .as_ref()
+                .map_or(input_texture.dimensions, |texture| texture.dimensions),
+            &sampling_options.filter,
+        )?;
+        let wu_histogram = match kmeans_options.initialization {
+            Initialization::KmeansPlusPlus => None,
+            Initialization::Wu => Some(histogram(device, queue, input_texture, alpha_mode).await?),
+        };
+        Ok(Self {
+            texture,
+            weight_texture,
+            wu_histogram,
+        })
+    }
+
+    /// Weight of each pixel of the sample, from 0 to 1, or `None` when they all weigh the same.
+    async fn weights(
+        &self,
+        device: &Arc<Device>,
+        queue: &Queue,
+        kmeans_options: &KmeansOptions,
+    ) -> Result<Option<Vec<f32>>> {
+        let weights = match (&kmeans_options.weights, &self.texture) {
+            (None, _) => return Ok(None),
+            (Some(weights), None) => weights.rgba.clone(),
+            (Some(_), Some(_)) => self.weight_texture.pull_image(device, queue).await?.rgba,
+        };
+        Ok(Some(
+            weights
+                .iter()
+                .map(|weight| weight.r as f32 / 255.0)
+                .collect(),
+        ))
+    }
+}
+
+/// K-means on the sample, or mini-batch k-means on the image with a [KmeansOptions::batch_size].
+#[allow(clippy::too_many_arguments)]
+fn kmeans(
     device: &Device,
     queue: &Queue,
     input_texture: &InputTexture,
+    sample: &KmeansSample,
     color_space: &ColorSpace,
+    alpha_mode: &AlphaMode,
+    distance_metric: &DistanceMetric,
     k: u32,
-) -> Result<CentroidsBuffer> {
-    let centroids_buffer = CentroidsBuffer::empty_centroids(k, device);
+    kmeans_options: &KmeansOptions,
+) -> Result<(CentroidsBuffer, KmeansReport)> {
+    if kmeans_options.max_iterations == 0 {
+        return Err(anyhow!("K-means needs at least one iteration"));
+    }
+    if kmeans_options.check_interval == 0 {
+        return Err(anyhow!("The convergence check interval can't be 0"));
+    }
+    let tolerance = kmeans_options
+        .tolerance
+        .unwrap_or_else(|| color_space.convergence());
+    if tolerance < 0.0 {
+        return Err(anyhow!("The convergence tolerance can't be negative"));
+    }
+    if kmeans_options.batch_size == Some(0) {
+        return Err(anyhow!("The batch size can't be 0"));
+    }
 
-    let shrunk = input_texture.shrunk(device, queue);
-    let input_texture = if let Some(shrunk) = &shrunk {
-        shrunk
-    } else {
-        input_texture
-    };
+    // Fixed colors come first, so that k-means++ and the iterations can skip them.
+    let mut initial_colors: Vec<_> = kmeans_options
+        .fixed_colors
+        .iter()
+        .chain(&kmeans_options.initial_colors)
+        .filter(|color| color.a != 0)
+        .copied()
+        .collect();
+    if initial_colors.len() > k as usize {
+        return Err(anyhow!(
+            "Got {} fixed and initial colors for a palette of {k} colors",
+            initial_colors.len()
+        ));
+    }
+    if let Some(histogram) = &sample.wu_histogram {
+        let free_count = k - initial_colors.len() as u32;
+        if free_count > 0 {
+            initial_colors.extend(wu::wu(histogram, free_count));
+        }
+    }
+    let fixed_count = kmeans_options
+        .fixed_colors
+        .iter()
+        .filter(|color| color.a != 0)
+        .count() as u32;
+
+    let centroids_buffer =
+        CentroidsBuffer::initial_centroids(k, &initial_colors, color_space, device);
+
+    if let Some(batch_size) = kmeans_options.batch_size {
+        let report = mini_batch_kmeans(
+            device,
+            queue,
+            input_texture,
+            color_space,
+            alpha_mode,
+            distance_metric,
+            k,
+            initial_colors.len() as u32,
+            fixed_count,
+            batch_size,
+            kmeans_options,
+            &centroids_buffer,
+        )?;
+        return Ok((centroids_buffer, report));
+    }
+
+    let input_texture = sample.texture.as_ref().unwrap_or(input_texture);
+    let weight_texture = &sample.weight_texture;
     let work_texture = WorkTexture::new(device, input_texture.dimensions);
     let color_index_texture = ColorIndexTexture::new(device, input_texture.dimensions);
     let plus_plus_init_module = PlusPlusInitModule::new(
+        distance_metric,
         input_texture.dimensions,
         k,
+        initial_colors.len() as u32,
+        kmeans_options.seed,
         &work_texture,
+        weight_texture,
         &centroids_buffer,
     );
     let color_converter_module = ColorConverterModule::new(
         device,
         color_space,
+        alpha_mode,
         input_texture.dimensions,
         input_texture,
         &work_texture,
     );
     let find_centroid_module = FindCentroidModule::new(
         device,
+        distance_metric,
         input_texture.dimensions,
         &work_texture,
         &centroids_buffer,
@@ -51,10 +237,13 @@ pub(crate) fn extract_palette_kmeans(
     );
     let choose_centroid_module = ChooseCentroidModule::new(
         device,
-        color_space,
+        tolerance,
+        distance_metric,
         input_texture.dimensions,
         k,
+        fixed_count,
         &work_texture,
+        weight_texture,
         &centroids_buffer,
         &color_index_texture,
         &find_centroid_module,
@@ -82,27 +271,355 @@ pub(crate) fn extract_palette_kmeans(
 
     queue.submit(Some(encoder.finish()));
 
-    choose_centroid_module.compute(device, queue);
+    let report = choose_centroid_module.compute(
+        device,
+        queue,
+        kmeans_options.max_iterations,
+        kmeans_options.check_interval,
+    );
+
+    Ok((centroids_buffer, report))
+}
+
+/// Mini-batch k-means, on batches drawn from the full resolution image. `initial_count`
+/// centroids, the first `fixed_count` of them being fixed, are expected in the centroids buffer.
+#[allow(clippy::too_many_arguments)]
+fn mini_batch_kmeans(
+    device: &Device,
+    queue: &Queue,
+    input_texture: &InputTexture,
+    color_space: &ColorSpace,
+    alpha_mode: &AlphaMode,
+    distance_metric: &DistanceMetric,
+    k: u32,
+    initial_count: u32,
+    fixed_count: u32,
+    batch_size: u32,
+    kmeans_options: &KmeansOptions,
+    centroids_buffer: &CentroidsBuffer,
+) -> Result<KmeansReport> {
+    const BATCH_WIDTH: u32 = 256;
+    let batch_dimensions = (BATCH_WIDTH, batch_size.div_ceil(BATCH_WIDTH));
+
+    let weight_texture = weight_texture(
+        device,
+        queue,
+        kmeans_options.weights.as_ref(),
+        input_texture.dimensions,
+        input_texture.dimensions,
+        &ResizeFilter::Bilinear,
+    )?;
+    let batch_texture = InputTexture::empty(device, batch_dimensions);
+    let batch_weight_texture = InputTexture::empty(device, batch_dimensions);
+    let work_texture = WorkTexture::new(device, batch_dimensions);
+    let color_index_texture = ColorIndexTexture::new(device, batch_dimensions);
+    let sample_batch_module = SampleBatchModule::new(
+        device,
+        input_texture,
+        &weight_texture,
+        &batch_texture,
+        &batch_weight_texture,
+    );
+    let color_converter_module = ColorConverterModule::new(
+        device,
+        color_space,
+        alpha_mode,
+        batch_dimensions,
+        &batch_texture,
+        &work_texture,
+    );
+    let plus_plus_init_module = PlusPlusInitModule::new(
+        distance_metric,
+        batch_dimensions,
+        k,
+        initial_count,
+        kmeans_options.seed,
+        &work_texture,
+        &batch_weight_texture,
+        centroids_buffer,
+    );
+    let find_centroid_module = FindCentroidModule::new(
+        device,
+        distance_metric,
+        batch_dimensions,
+        &work_texture,
+        centroids_buffer,
+        &color_index_texture,
+    );
+    let mini_batch_module = MiniBatchModule::new(
+        device,
+        distance_metric,
+        k,
+        fixed_count,
+        kmeans_options.check_interval,
+        &work_texture,
+        &batch_weight_texture,
+        centroids_buffer,
+        &color_index_texture,
+        &sample_batch_module,
+        &color_converter_module,
+        &find_centroid_module,
+    );
+
+    // The first batch is also the one k-means++ picks from.
+    sample_batch_module.write_seed(queue, kmeans_options.seed);
+    let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor { label: None });
+    {
+        let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: Some("Init pass"),
+        });
+        sample_batch_module.dispatch(&mut compute_pass);
+        color_converter_module.dispatch(&mut compute_pass);
+    }
+    queue.submit(Some(encoder.finish()));
+
+    plus_plus_init_module.compute(device, queue);
+
+    // Like scikit-learn, the average covers about as many pixels as the image.
+    let (width, height) = input_texture.dimensions;
+    let smoothing = (2.0 * (batch_dimensions.0 * batch_dimensions.1) as f32
+        / (width as f32 * height as f32 + 1.0))
+        .min(1.0);
+    Ok(mini_batch_module.compute(
+        device,
+        queue,
+        kmeans_options.seed,
+        kmeans_options.max_iterations,
+        smoothing,
+    ))
+}
+
+/// Uploads the weights of the pixels, resized to `dimensions`, or a 1 x 1 white texture weighing
+/// every pixel the same.
+fn weight_texture(
+    device: &Device,
+    queue: &Queue,
+    weights: Option<&Image<Vec<RGBA8>>>,
+    image_dimensions: (u32, u32),
+    dimensions: (u32, u32),
+    filter: &ResizeFilter,
+) -> Result<InputTexture> {
+    let Some(weights) = weights else {
+        let white = Image::new((1, 1), vec![RGBA8::new(255, 255, 255, 255)]);
+        return Ok(InputTexture::new(device, queue, &white));
+    };
+    if weights.dimensions != image_dimensions {
+        return Err(anyhow!(
+            "The weights are {}x{}, but the image is {}x{}",
+            weights.dimensions.0,
+            weights.dimensions.1,
+            image_dimensions.0,
+            image_dimensions.1
+        ));
+    }
 
-    Ok(centroids_buffer)
+    let weight_texture = InputTexture::new(device, queue, weights);
+    Ok(if dimensions == image_dimensions {
+        weight_texture
+    } else {
+        weight_texture.resized(dimensions, filter, device, queue)
+    })
 }
 
-pub(crate) fn extract_palette_octree(pixels: &[RGBA8], color_count: u32) -> Result<Vec<RGBA8>> {
+/// Runs k-means for every color count of the range, on the same downsampled image, and keeps the
+/// centroids of the count picked by `selection`.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn extract_palette_auto(
+    device: &Arc<Device>,
+    queue: &Queue,
+    input_texture: &InputTexture,
+    color_space: &ColorSpace,
+    alpha_mode: &AlphaMode,
+    distance_metric: &DistanceMetric,
+    color_counts: RangeInclusive<u32>,
+    selection: &ColorCountSelection,
+    kmeans_options: &KmeansOptions,
+    sampling_options: &SamplingOptions,
+) -> Result<(CentroidsBuffer, ColorCountReport)> {
+    if color_counts.is_empty() || *color_counts.start() == 0 {
+        return Err(anyhow!(
+            "Invalid color count range {}..={}",
+            color_counts.start(),
+            color_counts.end()
+        ));
+    }
+
+    // Every run learns from the same pixels, that they are scored on.
+    let sample = KmeansSample::new(
+        device,
+        queue,
+        input_texture,
+        alpha_mode,
+        kmeans_options,
+        sampling_options,
+        *color_counts.end(),
+    )
+    .await?;
+    let sampled_texture = sample.texture.as_ref().unwrap_or(input_texture);
+
+    let work_texture = WorkTexture::new(device, sampled_texture.dimensions);
+    let color_converter_module = ColorConverterModule::new(
+        device,
+        color_space,
+        alpha_mode,
+        sampled_texture.dimensions,
+        sampled_texture,
+        &work_texture,
+    );
+    let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor { label: None });
+    {
+        let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: Some("Init pass"),
+        });
+        color_converter_module.dispatch(&mut compute_pass);
+    }
+    queue.submit(Some(encoder.finish()));
+    let pixels = work_texture.pull_values(device, queue).await?;
+    let weights = sample.weights(device, queue, kmeans_options).await?;
+
+    let mut centroids_buffers = vec![];
+    let mut scores = vec![];
+    for k in color_counts {
+        let (centroids_buffer, _) = kmeans(
+            device,
+            queue,
+            input_texture,
+            &sample,
+            color_space,
+            alpha_mode,
+            distance_metric,
+            k,
+            kmeans_options,
+        )?;
+        let centroids = centroids_buffer.pull_components(device, queue).await?;
+        scores.push(color_count::score(
+            &pixels,
+            weights.as_deref(),
+            &centroids,
+            distance_metric,
+        ));
+        centroids_buffers.push(centroids_buffer);
+    }
+
+    let chosen = color_count::select(&scores, selection);
+    let report = ColorCountReport {
+        color_count: scores[chosen].color_count,
+        target_met: color_count::target_met(&scores[chosen], selection),
+        scores,
+    };
+
+    Ok((centroids_buffers.swap_remove(chosen), report))
+}
+
+pub(crate) fn extract_palette_octree(
+    pixels: &[RGBA8],
+    color_count: u32,
+    alpha_mode: &AlphaMode,
+) -> Result<Vec<RGBA8>> {
     let mut tree = ColorTree::new();
-    for pixel in pixels {
+    for pixel in pixels
+        .iter()
+        .filter(|pixel| !alpha_mode.is_transparent(pixel))
+    {
         tree.add_color(pixel);
     }
 
     Ok(tree.reduce(color_count as usize))
 }
 
+pub(crate) fn extract_palette_neuquant(
+    pixels: &[RGBA8],
+    color_count: u32,
+    alpha_mode: &AlphaMode,
+) -> Result<Vec<RGBA8>> {
+    let pixels: Vec<_> = pixels
+        .iter()
+        .filter(|pixel| !alpha_mode.is_transparent(pixel))
+        .copied()
+        .collect();
+
+    Ok(neuquant::neuquant(&pixels, color_count))
+}
+
+/// Builds the histogram of the image on the GPU, then cuts it on the CPU.
+pub(crate) async fn extract_palette_median_cut(
+    device: &Arc<Device>,
+    queue: &Queue,
+    input_texture: &InputTexture,
+    alpha_mode: &AlphaMode,
+    color_count: u32,
+) -> Result<Vec<RGBA8>> {
+    let histogram = histogram(device, queue, input_texture, alpha_mode).await?;
+
+    Ok(median_cut::median_cut(
+        median_cut::bins(&histogram),
+        color_count,
+    ))
+}
+
+/// Builds the histogram of the image on the GPU, then splits it on the CPU.
+pub(crate) async fn extract_palette_wu(
+    device: &Arc<Device>,
+    queue: &Queue,
+    input_texture: &InputTexture,
+    alpha_mode: &AlphaMode,
+    color_count: u32,
+) -> Result<Vec<RGBA8>> {
+    let histogram = histogram(device, queue, input_texture, alpha_mode).await?;
+
+    Ok(wu::wu(&histogram, color_count))
+}
+
+/// Counts the opaque pixels of the image, see [HistogramBuffer::pull_bins].
+async fn histogram(
+    device: &Arc<Device>,
+    queue: &Queue,
+    input_texture: &InputTexture,
+    alpha_mode: &AlphaMode,
+) -> Result<Vec<[u32; 4]>> {
+    let histogram_buffer = HistogramBuffer::new(device);
+    let histogram_module = HistogramModule::new(
+        device,
+        alpha_mode,
+        input_texture.dimensions,
+        input_texture,
+        &histogram_buffer,
+    );
+
+    let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor { label: None });
+    {
+        let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: Some("Histogram pass"),
+        });
+        histogram_module.dispatch(&mut compute_pass);
+    }
+    queue.submit(Some(encoder.finish()));
+
+    histogram_buffer.pull_bins(device, queue).await
+}
+
+#[allow(clippy::too_many_arguments)]
 pub(crate) fn dither_colors(
     device: &Device,
     queue: &Queue,
     input_texture: &InputTexture,
     color_space: &ColorSpace,
+    alpha_mode: &AlphaMode,
+    distance_metric: &DistanceMetric,
     centroids_buffer: &CentroidsBuffer,
-) -> Result<OutputTexture> {
+    dither_options: &DitherOptions,
+) -> Result<(OutputTexture, ColorIndexTexture)> {
+    if !(0.0..=1.0).contains(&dither_options.strength) {
+        return Err(anyhow!(
+            "The dither strength should be between 0 and 1, was {}",
+            dither_options.strength
+        ));
+    }
+    if matches!(dither_options.threshold, Some(threshold) if threshold < 0.0) {
+        return Err(anyhow!("The dither threshold can't be negative"));
+    }
+
+    let threshold_map_buffer = ThresholdMapBuffer::new(device, dither_options);
     let work_texture = WorkTexture::new(device, input_texture.dimensions);
     let dithered_texture = WorkTexture::new(device, input_texture.dimensions);
     let color_index_texture = ColorIndexTexture::new(device, input_texture.dimensions);
@@ -111,17 +628,20 @@ pub(crate) fn dither_colors(
     let color_converter_module = ColorConverterModule::new(
         device,
         color_space,
+        alpha_mode,
         input_texture.dimensions,
         input_texture,
         &work_texture,
     );
     let mix_colors_module = MixColorsModule::new(
         device,
+        distance_metric,
         input_texture.dimensions,
         &work_texture,
         &dithered_texture,
         &color_index_texture,
         centroids_buffer,
+        &threshold_map_buffer,
         &MixMode::Dither,
     );
     let color_reverter_module = ColorReverterModule::new(
@@ -151,7 +671,103 @@ pub(crate) fn dither_colors(
     }
     queue.submit(Some(encoder.finish()));
 
-    Ok(output_texture)
+    Ok((output_texture, color_index_texture))
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn diffuse_colors(
+    device: &Arc<Device>,
+    queue: &Queue,
+    input_texture: &InputTexture,
+    color_space: &ColorSpace,
+    alpha_mode: &AlphaMode,
+    distance_metric: &DistanceMetric,
+    centroids_buffer: &CentroidsBuffer,
+    kernel: &DiffusionKernel,
+    dither_options: &DitherOptions,
+) -> Result<(OutputTexture, ColorIndexTexture)> {
+    if !(0.0..=1.0).contains(&dither_options.strength) {
+        return Err(anyhow!(
+            "The dither strength should be between 0 and 1, was {}",
+            dither_options.strength
+        ));
+    }
+    if dither_options.threshold.is_some() {
+        return Err(anyhow!(
+            "Error diffusion spreads the whole error, it doesn't take a dither threshold"
+        ));
+    }
+
+    let work_texture = WorkTexture::new(device, input_texture.dimensions);
+    let output_texture = OutputTexture::new(device, input_texture.dimensions);
+
+    let color_converter_module = ColorConverterModule::new(
+        device,
+        color_space,
+        alpha_mode,
+        input_texture.dimensions,
+        input_texture,
+        &work_texture,
+    );
+    let color_reverter_module = ColorReverterModule::new(
+        device,
+        color_space,
+        input_texture.dimensions,
+        &work_texture,
+        &output_texture,
+    );
+
+    let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor { label: None });
+    {
+        let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: Some("Init pass"),
+        });
+        color_converter_module.dispatch(&mut compute_pass);
+    }
+    queue.submit(Some(encoder.finish()));
+
+    // Error diffusion is sequential by nature, so it runs on the CPU.
+    let pixels = work_texture.pull_values(device, queue).await?;
+    let centroids = centroids_buffer.pull_components(device, queue).await?;
+    let indices = diffuse(
+        &pixels,
+        input_texture.dimensions,
+        &centroids,
+        kernel,
+        dither_options.strength,
+        distance_metric,
+    );
+    let diffused: Vec<_> = indices
+        .iter()
+        .zip(pixels)
+        .map(|(index, pixel)| {
+            if *index == TRANSPARENT_INDEX {
+                pixel
+            } else {
+                let centroid = centroids[*index as usize];
+                [
+                    centroid[0],
+                    centroid[1],
+                    centroid[2],
+                    centroid[3].min(pixel[3]),
+                ]
+            }
+        })
+        .collect();
+    work_texture.write_values(queue, &diffused);
+    let color_index_texture = ColorIndexTexture::new(device, input_texture.dimensions);
+    color_index_texture.write_indices(queue, &indices);
+
+    let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor { label: None });
+    {
+        let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: Some("Fetch result pass"),
+        });
+        color_reverter_module.dispatch(&mut compute_pass);
+    }
+    queue.submit(Some(encoder.finish()));
+
+    Ok((output_texture, color_index_texture))
 }
 
 pub(crate) fn meld_colors(
@@ -159,8 +775,12 @@ pub(crate) fn meld_colors(
     queue: &Queue,
     input_texture: &InputTexture,
     color_space: &ColorSpace,
+    alpha_mode: &AlphaMode,
+    distance_metric: &DistanceMetric,
     centroids_buffer: &CentroidsBuffer,
-) -> Result<OutputTexture> {
+) -> Result<(OutputTexture, ColorIndexTexture)> {
+    // Melding doesn't read the thresholds, but the mix colors module still binds them.
+    let threshold_map_buffer = ThresholdMapBuffer::new(device, &DitherOptions::default());
     let work_texture = WorkTexture::new(device, input_texture.dimensions);
     let dithered_texture = WorkTexture::new(device, input_texture.dimensions);
     let color_index_texture = ColorIndexTexture::new(device, input_texture.dimensions);
@@ -169,17 +789,20 @@ pub(crate) fn meld_colors(
     let color_converter_module = ColorConverterModule::new(
         device,
         color_space,
+        alpha_mode,
         input_texture.dimensions,
         input_texture,
         &work_texture,
     );
     let mix_colors_module = MixColorsModule::new(
         device,
+        distance_metric,
         input_texture.dimensions,
         &work_texture,
         &dithered_texture,
         &color_index_texture,
         centroids_buffer,
+        &threshold_map_buffer,
         &MixMode::Meld,
     );
     let color_reverter_module = ColorReverterModule::new(
@@ -209,7 +832,7 @@ pub(crate) fn meld_colors(
     }
     queue.submit(Some(encoder.finish()));
 
-    Ok(output_texture)
+    Ok((output_texture, color_index_texture))
 }
 
 pub(crate) fn find_colors(
@@ -217,15 +840,19 @@ pub(crate) fn find_colors(
     queue: &Queue,
     input_texture: &InputTexture,
     color_space: &ColorSpace,
+    alpha_mode: &AlphaMode,
+    distance_metric: &DistanceMetric,
     centroids_buffer: &CentroidsBuffer,
-) -> Result<OutputTexture> {
+) -> Result<(OutputTexture, ColorIndexTexture)> {
     let work_texture = WorkTexture::new(device, input_texture.dimensions);
+    let swapped_texture = WorkTexture::new(device, input_texture.dimensions);
     let color_index_texture = ColorIndexTexture::new(device, input_texture.dimensions);
     let output_texture = OutputTexture::new(device, input_texture.dimensions);
 
     let color_converter_module = ColorConverterModule::new(
         device,
         color_space,
+        alpha_mode,
         input_texture.dimensions,
         input_texture,
         &work_texture,
@@ -234,11 +861,12 @@ pub(crate) fn find_colors(
         device,
         color_space,
         input_texture.dimensions,
-        &work_texture,
+        &swapped_texture,
         &output_texture,
     );
     let find_centroid_module = FindCentroidModule::new(
         device,
+        distance_metric,
         input_texture.dimensions,
         &work_texture,
         centroids_buffer,
@@ -249,6 +877,7 @@ pub(crate) fn find_colors(
         device,
         input_texture.dimensions,
         &work_texture,
+        &swapped_texture,
         centroids_buffer,
         &color_index_texture,
     );
@@ -267,5 +896,5 @@ pub(crate) fn find_colors(
 
     queue.submit(Some(encoder.finish()));
 
-    Ok(output_texture)
+    Ok((output_texture, color_index_texture))
 }
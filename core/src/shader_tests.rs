@@ -1,6 +1,10 @@
 use std::{borrow::Cow, sync::mpsc::channel};
 
-use palette::{IntoColor, Lab, Srgb};
+use palette::{
+    color_difference::{Ciede2000, EuclideanDistance},
+    white_point::D65,
+    IntoColor, Lab, Oklab, Srgb,
+};
 use pollster::FutureExt;
 use wgpu::{
     util::{self, DeviceExt},
@@ -9,7 +13,15 @@ use wgpu::{
     MaintainBase, MapMode, Queue, ShaderSource,
 };
 
-use crate::modules::include_shader;
+use crate::{
+    image::Image,
+    modules::{
+        include_shader, with_distance_metric, ColorConverterModule, ColorReverterModule, Module,
+    },
+    structures::{InputTexture, OutputTexture, WorkTexture},
+    Algorithm, AlphaMode, ColorSpace, DistanceMetric, ImageProcessor, Initialization,
+    KmeansOptions, MaskOptions, MaskedPixels, Options, ResizeFilter, TileOptions, TileSize, RGBA8,
+};
 
 struct TestingContext {
     device: Device,
@@ -29,6 +41,92 @@ impl ToLab for [u8; 3] {
     }
 }
 
+const COLOR_PAIRS: [([u8; 3], [u8; 3]); 5] = [
+    ([255, 0, 0], [255, 128, 0]),
+    ([0, 0, 0], [255, 255, 255]),
+    ([12, 200, 80], [30, 180, 95]),
+    ([40, 40, 200], [200, 40, 40]),
+    ([128, 128, 128], [130, 126, 129]),
+];
+
+/// Converts the colors to the color space and back on the GPU, returning the converted colors and
+/// the colors they were reverted to.
+fn convert_colors(color_space: &ColorSpace, colors: &[[u8; 3]]) -> (Vec<[f32; 4]>, Vec<RGBA8>) {
+    let image_processor = ImageProcessor::new().block_on().unwrap();
+    let (device, queue) = (&image_processor.device, &image_processor.queue);
+
+    let rgba: Vec<_> = colors
+        .iter()
+        .map(|&[r, g, b]| RGBA8::new(r, g, b, 255))
+        .collect();
+    let input_texture =
+        InputTexture::new(device, queue, &Image::new((colors.len() as u32, 1), rgba));
+    let work_texture = WorkTexture::new(device, input_texture.dimensions);
+    let output_texture = OutputTexture::new(device, input_texture.dimensions);
+    let color_converter_module = ColorConverterModule::new(
+        device,
+        color_space,
+        &AlphaMode::Preserve,
+        input_texture.dimensions,
+        &input_texture,
+        &work_texture,
+    );
+    let color_reverter_module = ColorReverterModule::new(
+        device,
+        color_space,
+        input_texture.dimensions,
+        &work_texture,
+        &output_texture,
+    );
+
+    let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor { label: None });
+    {
+        let mut compute_pass =
+            encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None });
+        color_converter_module.dispatch(&mut compute_pass);
+        color_reverter_module.dispatch(&mut compute_pass);
+    }
+    queue.submit(Some(encoder.finish()));
+
+    (
+        work_texture.pull_values(device, queue).block_on().unwrap(),
+        output_texture
+            .pull_image(device, queue)
+            .block_on()
+            .unwrap()
+            .rgba,
+    )
+}
+
+fn color_distance(distance_metric: &DistanceMetric, a: [f32; 3], b: [f32; 3]) -> f32 {
+    vec3x2_as_input_f32_as_output(
+        with_distance_metric(
+            include_shader!("shaders/tests/test_distance.wgsl"),
+            distance_metric,
+        )
+        .into(),
+        "run_color_distance",
+        a,
+        b,
+    )
+}
+
+/// CPU reference of the graphic arts flavor of CIE94 used in `functions/delta_e.wgsl`.
+fn cie94_reference(a: [f32; 3], b: [f32; 3]) -> f32 {
+    let d_l = a[0] - b[0];
+    let c1 = (a[1] * a[1] + a[2] * a[2]).sqrt();
+    let c2 = (b[1] * b[1] + b[2] * b[2]).sqrt();
+    let d_c = c1 - c2;
+    let d_a = a[1] - b[1];
+    let d_b = a[2] - b[2];
+    let d_h = (d_a * d_a + d_b * d_b - d_c * d_c).max(0.0).sqrt();
+
+    let s_c = 1.0 + 0.045 * c1;
+    let s_h = 1.0 + 0.015 * c1;
+
+    (d_l * d_l + (d_c / s_c) * (d_c / s_c) + (d_h / s_h) * (d_h / s_h)).sqrt()
+}
+
 fn vec3x2_as_input_f32_as_output(
     shader_string: Cow<str>,
     entry_point: &str,
@@ -117,7 +215,7 @@ fn vec3x2_as_input_f32_as_output(
 
         context.device.poll(MaintainBase::Wait);
 
-        if let Ok(_) = receiver.recv() {
+        if receiver.recv().is_ok() {
             let data = buffer_slice.get_mapped_range();
             let result: f32 = bytemuck::cast_slice(&data)[0];
 
@@ -166,6 +264,33 @@ fn run_wgpu_test<T>(test_function: impl FnOnce(&TestingContext) -> T) -> T {
     result
 }
 
+/// Square image of `size` pixels per side, cycling through the colors pixel after pixel.
+fn striped_image(colors: &[RGBA8], size: u32) -> Image<Vec<RGBA8>> {
+    Image::new(
+        (size, size),
+        (0..size * size)
+            .map(|position| colors[position as usize % colors.len()])
+            .collect(),
+    )
+}
+
+/// K-means palette of `k` colors, the options other than k-means ones left to their defaults.
+fn kmeans_palette(
+    image_processor: &ImageProcessor,
+    k: u32,
+    image: &Image<Vec<RGBA8>>,
+    kmeans: KmeansOptions,
+) -> Vec<RGBA8> {
+    let options = Options {
+        kmeans,
+        ..Default::default()
+    };
+    image_processor
+        .palette(k, image, &options)
+        .block_on()
+        .unwrap()
+}
+
 #[test]
 fn test_delta_e_cie94() {
     fn delta_e_cie94(a: [f32; 3], b: [f32; 3]) -> f32 {
@@ -232,10 +357,508 @@ fn test_pow() {
     let pow = 7.0;
 
     let result = run_pow(number, pow);
-    let expected = 180.1088541;
+    let expected = 180.108_86;
 
     assert!(
         (result - expected).abs() < 0.1,
         "{number}^{pow} = {expected}, was {result}"
     );
 }
+
+#[test]
+fn test_color_distance_euclidean_lab() {
+    for distance_metric in [DistanceMetric::Euclidean, DistanceMetric::Cie76] {
+        for (a, b) in COLOR_PAIRS {
+            let (a, b) = (a.to_lab(), b.to_lab());
+            let result = color_distance(&distance_metric, a, b);
+            let expected =
+                Lab::<D65, f32>::new(a[0], a[1], a[2]).distance(Lab::new(b[0], b[1], b[2]));
+
+            assert!(
+                (result - expected).abs() < 0.01,
+                "{distance_metric} expected {expected}, was {result}"
+            );
+        }
+    }
+}
+
+#[test]
+fn test_color_distance_cie94() {
+    for (a, b) in COLOR_PAIRS {
+        let (a, b) = (a.to_lab(), b.to_lab());
+        let result = color_distance(&DistanceMetric::Cie94, a, b);
+        let expected = cie94_reference(a, b);
+
+        assert!(
+            (result - expected).abs() < 0.01,
+            "CIE94 expected {expected}, was {result}"
+        );
+    }
+}
+
+#[test]
+fn test_color_distance_ciede2000() {
+    for (a, b) in COLOR_PAIRS {
+        let (a, b) = (a.to_lab(), b.to_lab());
+        let result = color_distance(&DistanceMetric::Ciede2000, a, b);
+        let expected =
+            Lab::<D65, f32>::new(a[0], a[1], a[2]).difference(Lab::new(b[0], b[1], b[2]));
+
+        assert!(
+            (result - expected).abs() < 0.01,
+            "CIEDE2000 expected {expected}, was {result}"
+        );
+    }
+}
+
+#[test]
+fn test_color_distance_euclidean() {
+    for (a, b) in COLOR_PAIRS {
+        let a: Srgb = Srgb::new(a[0], a[1], a[2]).into_format();
+        let b: Srgb = Srgb::new(b[0], b[1], b[2]).into_format();
+        let result = color_distance(
+            &DistanceMetric::Euclidean,
+            [a.red, a.green, a.blue],
+            [b.red, b.green, b.blue],
+        );
+        let expected = a.distance(b);
+
+        assert!(
+            (result - expected).abs() < 0.001,
+            "Euclidean expected {expected}, was {result}"
+        );
+    }
+}
+
+#[test]
+fn test_convert_oklab() {
+    let colors: Vec<_> = COLOR_PAIRS.iter().flat_map(|&(a, b)| [a, b]).collect();
+    let (converted, reverted) = convert_colors(&ColorSpace::Oklab, &colors);
+
+    for ((color, converted), reverted) in colors.iter().zip(converted).zip(reverted) {
+        let expected: Oklab = Srgb::new(color[0], color[1], color[2])
+            .into_format::<f32>()
+            .into_color();
+        for (value, expected) in converted.iter().zip([expected.l, expected.a, expected.b]) {
+            assert!(
+                (value - expected).abs() < 0.001,
+                "Oklab of {color:?} expected {expected:?}, was {converted:?}"
+            );
+        }
+        assert_eq!([reverted.r, reverted.g, reverted.b], *color);
+    }
+}
+
+#[test]
+fn test_convert_linear_rgb() {
+    let colors: Vec<_> = COLOR_PAIRS.iter().flat_map(|&(a, b)| [a, b]).collect();
+    let (converted, reverted) = convert_colors(&ColorSpace::LinearRgb, &colors);
+
+    for ((color, converted), reverted) in colors.iter().zip(converted).zip(reverted) {
+        let expected = Srgb::new(color[0], color[1], color[2])
+            .into_format::<f32>()
+            .into_linear::<f32>();
+        for (value, expected) in converted
+            .iter()
+            .zip([expected.red, expected.green, expected.blue])
+        {
+            assert!(
+                (value - expected).abs() < 0.001,
+                "Linear RGB of {color:?} expected {expected:?}, was {converted:?}"
+            );
+        }
+        assert_eq!([reverted.r, reverted.g, reverted.b], *color);
+    }
+}
+
+#[test]
+fn test_resize_area() {
+    let image_processor = ImageProcessor::new().block_on().unwrap();
+    let (device, queue) = (&image_processor.device, &image_processor.queue);
+    let resize = |image: &Image<Vec<RGBA8>>, dimensions| {
+        InputTexture::new(device, queue, image)
+            .resized(dimensions, &ResizeFilter::Area, device, queue)
+            .pull_image(device, queue)
+            .block_on()
+            .unwrap()
+    };
+    let close = |a: RGBA8, b: RGBA8| {
+        a.iter()
+            .zip(b.iter())
+            .all(|(a, b)| (a as i32 - b as i32).abs() <= 1)
+    };
+
+    // Transparent pixels lower the alpha without darkening the color.
+    let red = RGBA8::new(255, 0, 0, 255);
+    let green = RGBA8::new(0, 255, 0, 255);
+    let transparent = RGBA8::new(0, 0, 0, 0);
+    let image = Image::new(
+        (4, 2),
+        vec![
+            red,
+            transparent,
+            green,
+            green,
+            transparent,
+            red,
+            green,
+            green,
+        ],
+    );
+    let resized = resize(&image, (2, 1));
+    assert!(close(resized.rgba[0], RGBA8::new(255, 0, 0, 128)));
+    assert_eq!(resized.rgba[1], green);
+
+    // Areas larger than the loop bound are averaged from a grid of their pixels.
+    let black = RGBA8::new(0, 0, 0, 255);
+    let white = RGBA8::new(255, 255, 255, 255);
+    let image = Image::new(
+        (256, 256),
+        (0..256 * 256)
+            .map(|index| if index % 256 < 128 { black } else { white })
+            .collect(),
+    );
+    let resized = resize(&image, (1, 1));
+    assert!(close(resized.rgba[0], RGBA8::new(128, 128, 128, 255)));
+}
+
+#[test]
+fn test_find_indexed_keeps_palette_order() {
+    let image_processor = ImageProcessor::new().block_on().unwrap();
+    let transparent = RGBA8::new(0, 0, 0, 0);
+    let red = RGBA8::new(255, 0, 0, 255);
+    // The palette holds the exact colors given, not their round trip through Lab.
+    let blue = RGBA8::new(0x12, 0x34, 0x57, 255);
+    let image = Image::new((3, 1), vec![blue, red, transparent]);
+    let colors = [transparent, red, blue];
+
+    let indexed = image_processor
+        .find_indexed(&image, &colors, &Options::default())
+        .block_on()
+        .unwrap();
+
+    assert_eq!(indexed.palette(), &colors[..]);
+    assert_eq!(
+        (0..3).map(|x| indexed.get_index(x, 0)).collect::<Vec<_>>(),
+        vec![2, 1, 0]
+    );
+
+    let entries = image_processor
+        .palette_coverage(&image, &colors, &Options::default())
+        .block_on()
+        .unwrap();
+    assert_eq!(
+        entries
+            .iter()
+            .map(|entry| entry.pixel_count)
+            .collect::<Vec<_>>(),
+        vec![1, 1, 1]
+    );
+}
+
+#[test]
+fn test_reduce_tiles_with_mask() {
+    let image_processor = ImageProcessor::new().block_on().unwrap();
+    let red = RGBA8::new(255, 0, 0, 255);
+    let blue = RGBA8::new(0, 0, 255, 255);
+    let white = RGBA8::new(255, 255, 255, 255);
+    let black = RGBA8::new(0, 0, 0, 255);
+    // Two 8x8 tiles, only the red one is in the region of interest.
+    let pixels = (0..16 * 8).map(|position| if position % 16 < 8 { red } else { blue });
+    let image = Image::new((16, 8), pixels.collect::<Vec<_>>());
+    let mask = Image::new(
+        (16, 8),
+        (0..16 * 8)
+            .map(|position| if position % 16 < 8 { white } else { black })
+            .collect::<Vec<_>>(),
+    );
+    let tile_options = TileOptions {
+        tile_size: TileSize::Eight,
+        colors_per_tile: 1,
+        sub_palette_count: 1,
+    };
+    let options = |masked_pixels| Options {
+        mask: Some(MaskOptions {
+            mask: mask.clone(),
+            masked_pixels,
+        }),
+        ..Default::default()
+    };
+
+    let tiled = image_processor
+        .reduce_tiles(1, &image, &tile_options, &options(MaskedPixels::Remap))
+        .block_on()
+        .unwrap();
+    assert!(tiled
+        .to_image()
+        .rgba
+        .iter()
+        .all(|pixel| pixel.r > 200 && pixel.b < 50));
+
+    assert!(image_processor
+        .reduce_tiles(1, &image, &tile_options, &options(MaskedPixels::Keep))
+        .block_on()
+        .is_err());
+
+    let translucent = Image::new((1, 1), vec![RGBA8::new(255, 0, 0, 128)]);
+    assert!(image_processor
+        .reduce_tiles(1, &translucent, &tile_options, &Options::default())
+        .block_on()
+        .is_err());
+}
+
+#[test]
+fn test_same_seed_same_palette() {
+    let image_processor = ImageProcessor::new().block_on().unwrap();
+    let colors = (0..=255)
+        .step_by(3)
+        .map(|shade: u8| RGBA8::new(shade, 255 - shade, shade.wrapping_mul(7), 255))
+        .collect::<Vec<_>>();
+    let image = striped_image(&colors, 128);
+
+    for batch_size in [None, Some(1024)] {
+        let palette = |seed| {
+            let kmeans = KmeansOptions {
+                seed,
+                max_iterations: 32,
+                batch_size,
+                ..Default::default()
+            };
+            kmeans_palette(&image_processor, 8, &image, kmeans)
+        };
+
+        assert_eq!(palette(42), palette(42), "{batch_size:?}");
+        assert_ne!(palette(42), palette(43), "{batch_size:?}");
+    }
+}
+
+#[test]
+fn test_initial_colors_seed_centroids() {
+    let image_processor = ImageProcessor::new().block_on().unwrap();
+    let black = RGBA8::new(0, 0, 0, 255);
+    let gray = RGBA8::new(128, 128, 128, 255);
+    let white = RGBA8::new(255, 255, 255, 255);
+    let image = striped_image(&[black, gray, white], 48);
+    let palette = |initial_colors| {
+        let kmeans = KmeansOptions {
+            initial_colors,
+            ..Default::default()
+        };
+        kmeans_palette(&image_processor, 2, &image, kmeans)
+    };
+
+    // Gray joins the color it starts next to, and both splits are stable.
+    let colors = palette(vec![black, gray]);
+    assert!(colors.contains(&black));
+    assert!(!colors.contains(&white));
+    let colors = palette(vec![gray, white]);
+    assert!(colors.contains(&white));
+    assert!(!colors.contains(&black));
+}
+
+#[test]
+fn test_weights_change_palette() {
+    let image_processor = ImageProcessor::new().block_on().unwrap();
+    let black = RGBA8::new(0, 0, 0, 255);
+    let red = RGBA8::new(255, 0, 0, 255);
+    let white = RGBA8::new(255, 255, 255, 255);
+    let image = striped_image(&[black, red, white], 48);
+    // The black pixels weigh nothing, the others fully.
+    let weights = striped_image(&[black, white, white], 48);
+
+    let unweighted = kmeans_palette(&image_processor, 2, &image, KmeansOptions::default());
+    let weighted = kmeans_palette(
+        &image_processor,
+        2,
+        &image,
+        KmeansOptions {
+            weights: Some(weights),
+            ..Default::default()
+        },
+    );
+    assert_eq!(weighted, vec![red, white]);
+    assert_ne!(unweighted, weighted);
+}
+
+#[test]
+fn test_zero_weight_clusters_converge() {
+    let image_processor = ImageProcessor::new().block_on().unwrap();
+    let black = RGBA8::new(0, 0, 0, 255);
+    let red = RGBA8::new(255, 0, 0, 255);
+    let white = RGBA8::new(255, 255, 255, 255);
+    let image = striped_image(&[black, red, white], 48);
+    let weights = striped_image(&[black, white, white], 48);
+
+    // More centroids than weighted colors leaves one without any weighted pixel.
+    for seed in 0..8 {
+        let options = Options {
+            kmeans: KmeansOptions {
+                seed,
+                weights: Some(weights.clone()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let (colors, report) = image_processor
+            .palette_kmeans(3, &image, &options)
+            .block_on()
+            .unwrap();
+        assert!(report.converged, "seed {seed} didn't converge");
+        assert!(
+            colors.iter().all(|color| [red, white].contains(color)),
+            "seed {seed} picked an ignored color: {colors:?}"
+        );
+    }
+}
+
+#[test]
+fn test_fixed_colors_stay() {
+    let image_processor = ImageProcessor::new().block_on().unwrap();
+    let fixed = RGBA8::new(200, 30, 40, 255);
+    // Pure reds closest to the fixed color, that would pull it towards them if it could move.
+    let pixels = (0..64 * 64)
+        .map(|position| {
+            let shade = (position % 64) as u8;
+            match position % 3 {
+                0 => RGBA8::new(0, 64 + shade, 255 - shade, 255),
+                1 => RGBA8::new(shade, 255 - shade, 64, 255),
+                _ => RGBA8::new(255, shade / 8, 0, 255),
+            }
+        })
+        .collect::<Vec<_>>();
+    let image = Image::new((64, 64), pixels);
+    let close = |a: &RGBA8, b: &RGBA8| {
+        [a.r.abs_diff(b.r), a.g.abs_diff(b.g), a.b.abs_diff(b.b)]
+            .iter()
+            .all(|&difference| difference <= 1)
+    };
+
+    for batch_size in [None, Some(256)] {
+        for algo in [Algorithm::Kmeans, Algorithm::Octree, Algorithm::Wu] {
+            if batch_size.is_some() && !matches!(algo, Algorithm::Kmeans) {
+                continue;
+            }
+            let options = Options {
+                algo,
+                kmeans: KmeansOptions {
+                    fixed_colors: vec![fixed],
+                    batch_size,
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            let colors = image_processor
+                .palette(4, &image, &options)
+                .block_on()
+                .unwrap();
+
+            assert_eq!(colors.len(), 4);
+            assert!(
+                colors.iter().any(|color| close(color, &fixed)),
+                "{algo} {batch_size:?} lost the fixed color: {colors:?}"
+            );
+        }
+    }
+
+    let options = Options {
+        algo: Algorithm::Octree,
+        kmeans: KmeansOptions {
+            batch_size: Some(256),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    assert!(image_processor
+        .palette(4, &image, &options)
+        .block_on()
+        .is_err());
+}
+
+#[test]
+fn test_wu_initialization() {
+    let image_processor = ImageProcessor::new().block_on().unwrap();
+    let colors = (0..192)
+        .map(|position| {
+            let shade = (position % 64) as u8;
+            match position % 3 {
+                0 => RGBA8::new(0, 64 + shade, 255 - shade, 255),
+                1 => RGBA8::new(shade, 255 - shade, 64, 255),
+                _ => RGBA8::new(255, shade * 2, shade, 255),
+            }
+        })
+        .collect::<Vec<_>>();
+    let image = striped_image(&colors, 64);
+    let palettes = |initialization| {
+        (0..4)
+            .map(|seed| {
+                let kmeans = KmeansOptions {
+                    seed,
+                    initialization,
+                    max_iterations: 2,
+                    ..Default::default()
+                };
+                kmeans_palette(&image_processor, 6, &image, kmeans)
+            })
+            .collect::<Vec<_>>()
+    };
+
+    // K-means++ depends on the seed, while the Wu palette doesn't.
+    let plus_plus = palettes(Initialization::KmeansPlusPlus);
+    assert!(plus_plus.iter().any(|palette| palette != &plus_plus[0]));
+    let wu = palettes(Initialization::Wu);
+    assert!(wu.iter().all(|palette| palette == &wu[0]));
+}
+
+#[test]
+fn test_mini_batch_convergence() {
+    let image_processor = ImageProcessor::new().block_on().unwrap();
+    let colors = [
+        RGBA8::new(230, 20, 20, 255),
+        RGBA8::new(20, 200, 40, 255),
+        RGBA8::new(30, 40, 220, 255),
+        RGBA8::new(240, 230, 60, 255),
+    ];
+    // Four clusters of slightly noisy colors.
+    let pixels = (0..512 * 512u32)
+        .map(|position| {
+            let color = colors[(position % 4) as usize];
+            let noise = (position.wrapping_mul(2654435761) >> 28) as u8;
+            RGBA8::new(
+                color.r.saturating_add(noise),
+                color.g.saturating_add(noise),
+                color.b,
+                255,
+            )
+        })
+        .collect::<Vec<_>>();
+    let image = Image::new((512, 512), pixels);
+    let options = Options {
+        kmeans: KmeansOptions {
+            batch_size: Some(1024),
+            max_iterations: 1000,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    let (palette, report) = image_processor
+        .palette_kmeans(4, &image, &options)
+        .block_on()
+        .unwrap();
+
+    // Stops on its own once the batches stop improving, but not before learning the clusters.
+    assert!(report.converged);
+    assert!(report.iterations < 1000);
+    for color in colors {
+        assert!(
+            palette.iter().any(|other| [
+                color.r.abs_diff(other.r),
+                color.g.abs_diff(other.g),
+                color.b.abs_diff(other.b)
+            ]
+            .iter()
+            .all(|&difference| difference <= 16)),
+            "{color:?} missing from {palette:?}"
+        );
+    }
+}
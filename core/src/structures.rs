@@ -1,5 +1,5 @@
-use anyhow::Result;
-use palette::{rgb::Rgba, IntoColor, Lab, Srgb, Srgba};
+use anyhow::{anyhow, Result};
+use palette::{rgb::Rgba, IntoColor, Lab, LinSrgba, Oklab, Srgb, Srgba};
 use rgb::RGBA8;
 use std::{ops::Deref, sync::Arc, vec};
 use wgpu::{
@@ -17,11 +17,9 @@ use crate::{
     image::{copied_pixel, Container, Image},
     modules::include_shader,
     utils::{compute_work_group_count, padded_bytes_per_row},
-    ColorSpace,
+    ColorSpace, DitherOptions, ResizeFilter, Sampling, SamplingOptions,
 };
 
-const MAX_IMAGE_DIMENSION: u32 = 256;
-
 pub(crate) struct InputTexture {
     pub texture: Texture,
     pub dimensions: (u32, u32),
@@ -64,30 +62,71 @@ impl InputTexture {
         }
     }
 
-    pub fn shrunk(&self, device: &Device, queue: &Queue) -> Option<InputTexture> {
-        let (width, height) = self.dimensions;
-        if width > MAX_IMAGE_DIMENSION || height > MAX_IMAGE_DIMENSION {
-            Some(self.resized(MAX_IMAGE_DIMENSION, device, queue))
-        } else {
-            None
+    /// An empty texture, for shaders to fill.
+    pub fn empty(device: &Device, (width, height): (u32, u32)) -> Self {
+        let texture = device.create_texture(&TextureDescriptor {
+            label: None,
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Rgba8Unorm,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::STORAGE_BINDING,
+            view_formats: &[],
+        });
+
+        Self {
+            texture,
+            dimensions: (width, height),
         }
     }
 
-    pub fn resized(&self, max_size: u32, device: &Device, queue: &Queue) -> InputTexture {
-        let (width, height) = self.dimensions;
+    pub fn texture_2d_layout(binding: u32) -> BindGroupLayoutEntry {
+        BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Texture {
+                sample_type: TextureSampleType::Float { filterable: true },
+                view_dimension: TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        }
+    }
 
-        let (new_width, new_height) = if width > height {
-            (
-                max_size,
-                ((height as f32 * max_size as f32 / width as f32) as u32).max(1),
-            )
-        } else {
-            (
-                ((width as f32 * max_size as f32 / height as f32) as u32).max(1),
-                max_size,
-            )
-        };
+    /// Downsamples the texture according to the sampling, or `None` if it's already small enough.
+    /// [Sampling::Auto] stands for [Sampling::MaxDimension] of `auto_max_dimension`. The sample
+    /// keeps at least `min_pixels` pixels when the image has them, one for each color to find.
+    pub fn sampled(
+        &self,
+        sampling: &SamplingOptions,
+        auto_max_dimension: u32,
+        min_pixels: u32,
+        device: &Device,
+        queue: &Queue,
+    ) -> Result<Option<InputTexture>> {
+        let new_dimensions = sample_dimensions(
+            self.dimensions,
+            &sampling.sampling,
+            auto_max_dimension,
+            min_pixels,
+        )?;
+
+        Ok(new_dimensions
+            .map(|new_dimensions| self.resized(new_dimensions, &sampling.filter, device, queue)))
+    }
 
+    pub fn resized(
+        &self,
+        (new_width, new_height): (u32, u32),
+        filter: &ResizeFilter,
+        device: &Device,
+        queue: &Queue,
+    ) -> InputTexture {
         let texture_size = wgpu::Extent3d {
             width: new_width,
             height: new_height,
@@ -107,71 +146,114 @@ impl InputTexture {
             view_formats: &[],
         });
 
-        let resize_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("Resize shader"),
-            source: ShaderSource::Wgsl(include_shader!("shaders/resize.wgsl").into()),
-        });
-
-        let pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
-            label: Some("Resize pipeline"),
-            layout: None,
-            module: &resize_shader,
-            entry_point: "main",
-        });
-
-        let filter_mode = FilterMode::Linear;
-
-        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            label: None,
-            address_mode_u: AddressMode::ClampToEdge,
-            address_mode_v: AddressMode::ClampToEdge,
-            address_mode_w: AddressMode::ClampToEdge,
-            mag_filter: filter_mode,
-            min_filter: filter_mode,
-            mipmap_filter: filter_mode,
-            ..Default::default()
-        });
-
-        let compute_constants = device.create_bind_group(&BindGroupDescriptor {
-            label: Some("Compute constants"),
-            layout: &pipeline.get_bind_group_layout(0),
-            entries: &[BindGroupEntry {
-                binding: 0,
-                resource: BindingResource::Sampler(&sampler),
-            }],
-        });
-
-        let texture_bind_group = device.create_bind_group(&BindGroupDescriptor {
-            label: Some("Texture bind group"),
-            layout: &pipeline.get_bind_group_layout(1),
-            entries: &[
-                BindGroupEntry {
-                    binding: 0,
-                    resource: BindingResource::TextureView(
-                        &self.texture.create_view(&TextureViewDescriptor::default()),
-                    ),
-                },
-                BindGroupEntry {
-                    binding: 1,
-                    resource: BindingResource::TextureView(
-                        &updated_texture.create_view(&TextureViewDescriptor::default()),
-                    ),
-                },
-            ],
-        });
-
+        let textures = [
+            BindingResource::TextureView(
+                &self.texture.create_view(&TextureViewDescriptor::default()),
+            ),
+            BindingResource::TextureView(
+                &updated_texture.create_view(&TextureViewDescriptor::default()),
+            ),
+        ];
         let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor { label: None });
-
-        {
-            let (dispatch_with, dispatch_height) =
-                compute_work_group_count((texture_size.width, texture_size.height), (16, 16));
-            let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
-                label: Some("Resize pass"),
-            });
-            compute_pass.set_pipeline(&pipeline);
-            compute_pass.set_bind_group(0, &compute_constants, &[]);
-            compute_pass.set_bind_group(1, &texture_bind_group, &[]);
-            compute_pass.dispatch_workgroups(dispatch_with, dispatch_height, 1);
+        let (dispatch_with, dispatch_height) =
+            compute_work_group_count((texture_size.width, texture_size.height), (16, 16));
+
+        match filter {
+            ResizeFilter::Bilinear => {
+                let resize_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                    label: Some("Resize shader"),
+                    source: ShaderSource::Wgsl(include_shader!("shaders/resize.wgsl").into()),
+                });
+
+                let pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+                    label: Some("Resize pipeline"),
+                    layout: None,
+                    module: &resize_shader,
+                    entry_point: "main",
+                });
+
+                let filter_mode = FilterMode::Linear;
+
+                let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+                    label: None,
+                    address_mode_u: AddressMode::ClampToEdge,
+                    address_mode_v: AddressMode::ClampToEdge,
+                    address_mode_w: AddressMode::ClampToEdge,
+                    mag_filter: filter_mode,
+                    min_filter: filter_mode,
+                    mipmap_filter: filter_mode,
+                    ..Default::default()
+                });
+
+                let compute_constants = device.create_bind_group(&BindGroupDescriptor {
+                    label: Some("Compute constants"),
+                    layout: &pipeline.get_bind_group_layout(0),
+                    entries: &[BindGroupEntry {
+                        binding: 0,
+                        resource: BindingResource::Sampler(&sampler),
+                    }],
+                });
+
+                let [input, output] = textures;
+                let texture_bind_group = device.create_bind_group(&BindGroupDescriptor {
+                    label: Some("Texture bind group"),
+                    layout: &pipeline.get_bind_group_layout(1),
+                    entries: &[
+                        BindGroupEntry {
+                            binding: 0,
+                            resource: input,
+                        },
+                        BindGroupEntry {
+                            binding: 1,
+                            resource: output,
+                        },
+                    ],
+                });
+
+                let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+                    label: Some("Resize pass"),
+                });
+                compute_pass.set_pipeline(&pipeline);
+                compute_pass.set_bind_group(0, &compute_constants, &[]);
+                compute_pass.set_bind_group(1, &texture_bind_group, &[]);
+                compute_pass.dispatch_workgroups(dispatch_with, dispatch_height, 1);
+            }
+            ResizeFilter::Area => {
+                let resize_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                    label: Some("Area resize shader"),
+                    source: ShaderSource::Wgsl(include_shader!("shaders/resize_area.wgsl").into()),
+                });
+
+                let pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+                    label: Some("Area resize pipeline"),
+                    layout: None,
+                    module: &resize_shader,
+                    entry_point: "main",
+                });
+
+                let [input, output] = textures;
+                let texture_bind_group = device.create_bind_group(&BindGroupDescriptor {
+                    label: Some("Texture bind group"),
+                    layout: &pipeline.get_bind_group_layout(0),
+                    entries: &[
+                        BindGroupEntry {
+                            binding: 0,
+                            resource: input,
+                        },
+                        BindGroupEntry {
+                            binding: 1,
+                            resource: output,
+                        },
+                    ],
+                });
+
+                let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+                    label: Some("Area resize pass"),
+                });
+                compute_pass.set_pipeline(&pipeline);
+                compute_pass.set_bind_group(0, &texture_bind_group, &[]);
+                compute_pass.dispatch_workgroups(dispatch_with, dispatch_height, 1);
+            }
         }
 
         queue.submit(Some(encoder.finish()));
@@ -260,6 +342,69 @@ impl Deref for InputTexture {
     }
 }
 
+/// Dimensions of the sample of an image of `(width, height)`, or `None` to use the image as is. See
+/// [InputTexture::sampled].
+fn sample_dimensions(
+    (width, height): (u32, u32),
+    sampling: &Sampling,
+    auto_max_dimension: u32,
+    min_pixels: u32,
+) -> Result<Option<(u32, u32)>> {
+    let max_dimension = |max_dimension: u32| {
+        if width <= max_dimension && height <= max_dimension {
+            None
+        } else if width > height {
+            Some((
+                max_dimension,
+                ((height as f32 * max_dimension as f32 / width as f32) as u32).max(1),
+            ))
+        } else {
+            Some((
+                ((width as f32 * max_dimension as f32 / height as f32) as u32).max(1),
+                max_dimension,
+            ))
+        }
+    };
+    let pixel_budget = |budget: u32| {
+        let pixel_count = width as u64 * height as u64;
+        if pixel_count <= budget as u64 {
+            None
+        } else {
+            let scale = (budget as f64 / pixel_count as f64).sqrt();
+            Some((
+                ((width as f64 * scale) as u32).max(1),
+                ((height as f64 * scale) as u32).max(1),
+            ))
+        }
+    };
+
+    let new_dimensions = match sampling {
+        Sampling::Auto => max_dimension(auto_max_dimension),
+        Sampling::Full => None,
+        Sampling::MaxDimension(0) | Sampling::PixelBudget(0) => {
+            return Err(anyhow!("The sampling size can't be 0"))
+        }
+        Sampling::MaxDimension(size) => max_dimension(*size),
+        Sampling::PixelBudget(budget) => pixel_budget(*budget),
+    };
+
+    Ok(match new_dimensions {
+        Some((new_width, new_height))
+            if (new_width as u64 * new_height as u64) < min_pixels as u64 =>
+        {
+            // Rounding the sides up keeps at least `min_pixels`, as long as the image has them.
+            let scale = (min_pixels as f64 / (width as f64 * height as f64)).sqrt();
+            (scale < 1.0).then(|| {
+                (
+                    ((width as f64 * scale).ceil() as u32).min(width),
+                    ((height as f64 * scale).ceil() as u32).min(height),
+                )
+            })
+        }
+        new_dimensions => new_dimensions,
+    })
+}
+
 pub struct WorkTexture(Texture);
 
 impl WorkTexture {
@@ -277,13 +422,35 @@ impl WorkTexture {
             sample_count: 1,
             dimension: TextureDimension::D2,
             format: TextureFormat::Rgba32Float,
-            usage: TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING,
+            usage: TextureUsages::STORAGE_BINDING
+                | TextureUsages::TEXTURE_BINDING
+                | TextureUsages::COPY_SRC
+                | TextureUsages::COPY_DST,
             view_formats: &[],
         });
 
         Self(texture)
     }
 
+    pub async fn pull_values(&self, device: &Arc<Device>, queue: &Queue) -> Result<Vec<[f32; 4]>> {
+        let data = pull_texture(self, 16, device, queue).await?;
+        Ok(bytemuck::pod_collect_to_vec(&data))
+    }
+
+    pub fn write_values(&self, queue: &Queue, values: &[[f32; 4]]) {
+        let size = self.size();
+        queue.write_texture(
+            self.as_image_copy(),
+            bytemuck::cast_slice(values),
+            ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(16 * size.width),
+                rows_per_image: None,
+            },
+            size,
+        );
+    }
+
     pub fn texture_2d_layout(binding: u32) -> BindGroupLayoutEntry {
         BindGroupLayoutEntry {
             binding,
@@ -319,6 +486,9 @@ impl Deref for WorkTexture {
     }
 }
 
+/// Index given to transparent pixels when there is no transparent entry in the centroids.
+pub(crate) const TRANSPARENT_INDEX: u32 = u32::MAX;
+
 pub(crate) struct ColorIndexTexture(Texture);
 
 impl ColorIndexTexture {
@@ -336,21 +506,43 @@ impl ColorIndexTexture {
             sample_count: 1,
             dimension: TextureDimension::D2,
             format: TextureFormat::R32Uint,
-            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::STORAGE_BINDING,
+            usage: TextureUsages::TEXTURE_BINDING
+                | TextureUsages::STORAGE_BINDING
+                | TextureUsages::COPY_SRC
+                | TextureUsages::COPY_DST,
             view_formats: &[],
         });
 
         Self(texture)
     }
 
-    pub fn texture_2d_layout(binding: u32) -> BindGroupLayoutEntry {
+    pub async fn pull_indices(&self, device: &Arc<Device>, queue: &Queue) -> Result<Vec<u32>> {
+        let data = pull_texture(self, 4, device, queue).await?;
+        Ok(bytemuck::pod_collect_to_vec(&data))
+    }
+
+    pub fn write_indices(&self, queue: &Queue, indices: &[u32]) {
+        let size = self.size();
+        queue.write_texture(
+            self.as_image_copy(),
+            bytemuck::cast_slice(indices),
+            ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * size.width),
+                rows_per_image: None,
+            },
+            size,
+        );
+    }
+
+    pub fn texture_storage_layout(binding: u32) -> BindGroupLayoutEntry {
         BindGroupLayoutEntry {
             binding,
             visibility: ShaderStages::COMPUTE,
-            ty: BindingType::Texture {
-                sample_type: TextureSampleType::Uint,
+            ty: BindingType::StorageTexture {
+                access: StorageTextureAccess::WriteOnly,
+                format: TextureFormat::R32Uint,
                 view_dimension: TextureViewDimension::D2,
-                multisampled: false,
             },
             count: None,
         }
@@ -365,6 +557,49 @@ impl Deref for ColorIndexTexture {
     }
 }
 
+/// Copies the texture back to the CPU, without the padding added to its rows.
+async fn pull_texture(
+    texture: &Texture,
+    bytes_per_pixel: usize,
+    device: &Arc<Device>,
+    queue: &Queue,
+) -> Result<Vec<u8>> {
+    let Extent3d { width, height, .. } = texture.size();
+    let unpadded_bytes_per_row = width as usize * bytes_per_pixel;
+    let padded_bytes_per_row = padded_bytes_per_row(unpadded_bytes_per_row as u64) as usize;
+
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: None,
+        size: padded_bytes_per_row as u64 * height as u64,
+        usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor { label: None });
+    encoder.copy_texture_to_buffer(
+        texture.as_image_copy(),
+        wgpu::ImageCopyBuffer {
+            buffer: &buffer,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(padded_bytes_per_row as u32),
+                rows_per_image: None,
+            },
+        },
+        texture.size(),
+    );
+    queue.submit(Some(encoder.finish()));
+
+    let padded_data = AsyncBufferView::new(buffer.slice(..), device).await?;
+
+    let mut data = Vec::with_capacity(unpadded_bytes_per_row * height as usize);
+    for padded in padded_data.chunks_exact(padded_bytes_per_row) {
+        data.extend_from_slice(&padded[..unpadded_bytes_per_row]);
+    }
+
+    Ok(data)
+}
+
 pub(crate) struct OutputTexture {
     texture: Texture,
     texture_size: wgpu::Extent3d,
@@ -498,47 +733,62 @@ pub(crate) struct CentroidsBuffer {
 }
 
 impl CentroidsBuffer {
-    pub fn empty_centroids(k: u32, device: &Device) -> Self {
-        let mut centroids: Vec<u8> = Vec::with_capacity((k as usize + 1) * 4);
+    /// Centroids matching the given colors. A fully transparent color is kept as the reserved
+    /// transparent entry, with an alpha of 0: the shaders expect it last, and only once, so the
+    /// centroids follow [CentroidsBuffer::fixed_order].
+    pub fn fixed_centroids(colors: &[RGBA8], color_space: &ColorSpace, device: &Device) -> Self {
+        let colors: Vec<_> = Self::fixed_order(colors)
+            .into_iter()
+            .map(|index| &colors[index])
+            .collect();
+        let mut centroids: Vec<u8> = Vec::with_capacity(16 * (colors.len() + 1));
+
         // Aligned 16, see https://www.w3.org/TR/WGSL/#address-space-layout-constraints
-        centroids.extend_from_slice(bytemuck::cast_slice(&[k, 0, 0, 0]));
+        centroids.extend_from_slice(bytemuck::cast_slice(&[colors.len() as u32, 0, 0, 0]));
 
-        centroids.extend_from_slice(
-            &(0..k)
-                .flat_map(|_| [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0])
-                .collect::<Vec<u8>>(),
-        );
+        centroids.extend_from_slice(bytemuck::cast_slice(
+            &colors
+                .iter()
+                .map(|color| centroid(color, color_space))
+                .collect::<Vec<[f32; 4]>>(),
+        ));
 
         let buffer = device.create_buffer_init(&BufferInitDescriptor {
             label: None,
             contents: &centroids,
             usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC,
         });
-
         let copy_size = centroids.len() as u64;
 
         Self { copy_size, buffer }
     }
 
-    pub fn fixed_centroids(colors: &[RGBA8], color_space: &ColorSpace, device: &Device) -> Self {
-        let mut centroids: Vec<u8> = Vec::with_capacity(16 * (colors.len() + 1));
+    /// Index in `colors` of each of the [CentroidsBuffer::fixed_centroids]: the opaque colors, then
+    /// the first fully transparent one.
+    pub fn fixed_order(colors: &[RGBA8]) -> Vec<usize> {
+        (0..colors.len())
+            .filter(|&index| colors[index].a != 0)
+            .chain(colors.iter().position(|color| color.a == 0))
+            .collect()
+    }
 
+    /// `k` centroids, starting with the given opaque colors, the others left empty.
+    pub fn initial_centroids(
+        k: u32,
+        colors: &[RGBA8],
+        color_space: &ColorSpace,
+        device: &Device,
+    ) -> Self {
+        let mut centroids: Vec<u8> = Vec::with_capacity((k as usize + 1) * 16);
         // Aligned 16, see https://www.w3.org/TR/WGSL/#address-space-layout-constraints
-        centroids.extend_from_slice(bytemuck::cast_slice(&[colors.len() as u32, 0, 0, 0]));
+        centroids.extend_from_slice(bytemuck::cast_slice(&[k, 0, 0, 0]));
 
         centroids.extend_from_slice(bytemuck::cast_slice(
             &colors
                 .iter()
-                .map(|c| match color_space {
-                    ColorSpace::Lab => {
-                        let lab: Lab = Srgb::new(c.r, c.g, c.b).into_format().into_color();
-                        [lab.l, lab.a, lab.b, 1.0]
-                    }
-                    ColorSpace::Rgb => {
-                        let srgb: Srgb = Srgb::new(c.r, c.g, c.b).into_format();
-                        [srgb.red, srgb.green, srgb.blue, 1.0]
-                    }
-                })
+                .map(|color| centroid(color, color_space))
+                .chain(std::iter::repeat([0.0; 4]))
+                .take(k as usize)
                 .collect::<Vec<[f32; 4]>>(),
         ));
 
@@ -578,12 +828,12 @@ impl CentroidsBuffer {
         }
     }
 
-    pub async fn pull_values(
+    /// Pulls the centroids as they are stored on the GPU, in the working color space.
+    pub async fn pull_components(
         &self,
         device: &Arc<Device>,
         queue: &Queue,
-        color_space: &ColorSpace,
-    ) -> Result<Vec<RGBA8>> {
+    ) -> Result<Vec<[f32; 4]>> {
         let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor { label: None });
 
         let staging_buffer = self.staging_buffer(device, &mut encoder);
@@ -595,8 +845,19 @@ impl CentroidsBuffer {
         let async_data = AsyncBufferView::new(cent_buffer_slice, device);
         let data = async_data.await?;
 
-        let colors: Vec<_> = bytemuck::cast_slice::<u8, f32>(&data[16..])
-            .chunks_exact(4)
+        Ok(bytemuck::pod_collect_to_vec(&data[16..]))
+    }
+
+    pub async fn pull_values(
+        &self,
+        device: &Arc<Device>,
+        queue: &Queue,
+        color_space: &ColorSpace,
+    ) -> Result<Vec<RGBA8>> {
+        let colors: Vec<_> = self
+            .pull_components(device, queue)
+            .await?
+            .iter()
             .map(|color| {
                 let raw: Rgba<_, u8> = match color_space {
                     ColorSpace::Lab => {
@@ -604,12 +865,19 @@ impl CentroidsBuffer {
                             .into_format()
                     }
                     ColorSpace::Rgb => Srgba::new(color[0], color[1], color[2], 1.0).into_format(),
+                    ColorSpace::Oklab => {
+                        IntoColor::<Srgba>::into_color(Oklab::new(color[0], color[1], color[2]))
+                            .into_format()
+                    }
+                    ColorSpace::LinearRgb => {
+                        Srgba::from_linear(LinSrgba::new(color[0], color[1], color[2], 1.0))
+                    }
                 };
                 RGBA8 {
                     r: raw.red,
                     g: raw.green,
                     b: raw.blue,
-                    a: raw.alpha,
+                    a: if color[3] == 0.0 { 0 } else { raw.alpha },
                 }
             })
             .collect();
@@ -617,6 +885,35 @@ impl CentroidsBuffer {
     }
 }
 
+/// The color in the working color space, with an alpha of 0 for the transparent color, 1 otherwise.
+fn centroid(color: &RGBA8, color_space: &ColorSpace) -> [f32; 4] {
+    let alpha = if color.a == 0 { 0.0 } else { 1.0 };
+    match color_space {
+        ColorSpace::Lab => {
+            let lab: Lab = Srgb::new(color.r, color.g, color.b)
+                .into_format()
+                .into_color();
+            [lab.l, lab.a, lab.b, alpha]
+        }
+        ColorSpace::Rgb => {
+            let srgb: Srgb = Srgb::new(color.r, color.g, color.b).into_format();
+            [srgb.red, srgb.green, srgb.blue, alpha]
+        }
+        ColorSpace::Oklab => {
+            let oklab: Oklab = Srgb::new(color.r, color.g, color.b)
+                .into_format()
+                .into_color();
+            [oklab.l, oklab.a, oklab.b, alpha]
+        }
+        ColorSpace::LinearRgb => {
+            let linear = Srgb::new(color.r, color.g, color.b)
+                .into_format::<f32>()
+                .into_linear();
+            [linear.red, linear.green, linear.blue, alpha]
+        }
+    }
+}
+
 impl Deref for CentroidsBuffer {
     type Target = Buffer;
 
@@ -624,3 +921,194 @@ impl Deref for CentroidsBuffer {
         &self.buffer
     }
 }
+
+/// Threshold map used by ordered dithering, stored as its width and height, the strength
+/// and the threshold (negative when it should be computed from the palette), followed by
+/// the thresholds, row by row.
+pub(crate) struct ThresholdMapBuffer {
+    buffer: Buffer,
+}
+
+impl ThresholdMapBuffer {
+    pub fn new(device: &Device, dither_options: &DitherOptions) -> Self {
+        let ((width, height), thresholds) = dither_options.matrix.thresholds();
+
+        let mut contents: Vec<u8> = Vec::with_capacity(16 + thresholds.len() * 4);
+        contents.extend_from_slice(bytemuck::cast_slice(&[width, height]));
+        contents.extend_from_slice(bytemuck::cast_slice(&[
+            dither_options.strength,
+            dither_options.threshold.unwrap_or(-1.0),
+        ]));
+        contents.extend_from_slice(bytemuck::cast_slice(&thresholds));
+
+        let buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Threshold map buffer"),
+            contents: &contents,
+            usage: BufferUsages::STORAGE,
+        });
+
+        Self { buffer }
+    }
+
+    pub fn layout(binding: u32) -> BindGroupLayoutEntry {
+        BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }
+    }
+}
+
+impl Deref for ThresholdMapBuffer {
+    type Target = Buffer;
+
+    fn deref(&self) -> &Self::Target {
+        &self.buffer
+    }
+}
+
+/// Bins of 5 bits per channel, as filled by `histogram.wgsl`.
+pub(crate) const HISTOGRAM_BIN_COUNT: usize = 32 * 32 * 32;
+
+pub(crate) struct HistogramBuffer {
+    buffer: Buffer,
+}
+
+impl HistogramBuffer {
+    pub fn new(device: &Device) -> Self {
+        let buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Histogram buffer"),
+            contents: bytemuck::cast_slice(&vec![0u32; HISTOGRAM_BIN_COUNT * 4]),
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC,
+        });
+
+        Self { buffer }
+    }
+
+    /// For each bin, the pixel count followed by the sum of the low bits of each channel.
+    pub async fn pull_bins(&self, device: &Arc<Device>, queue: &Queue) -> Result<Vec<[u32; 4]>> {
+        let size = self.buffer.size();
+        let staging_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor { label: None });
+        encoder.copy_buffer_to_buffer(&self.buffer, 0, &staging_buffer, 0, size);
+        queue.submit(Some(encoder.finish()));
+
+        let data = AsyncBufferView::new(staging_buffer.slice(..), device).await?;
+
+        Ok(bytemuck::pod_collect_to_vec(&data))
+    }
+
+    pub fn layout(binding: u32) -> BindGroupLayoutEntry {
+        BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only: false },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }
+    }
+}
+
+impl Deref for HistogramBuffer {
+    type Target = Buffer;
+
+    fn deref(&self) -> &Self::Target {
+        &self.buffer
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Histogram of the colors, each appearing as many times as its count, binned like
+    /// `histogram.wgsl` does.
+    pub(crate) fn histogram(colors: &[([u8; 3], u32)]) -> Vec<[u32; 4]> {
+        let mut histogram = vec![[0; 4]; HISTOGRAM_BIN_COUNT];
+        for &([r, g, b], count) in colors {
+            let index = ((r as usize >> 3) << 10) | ((g as usize >> 3) << 5) | (b as usize >> 3);
+            let bin = &mut histogram[index];
+            bin[0] += count;
+            bin[1] += (r as u32 & 7) * count;
+            bin[2] += (g as u32 & 7) * count;
+            bin[3] += (b as u32 & 7) * count;
+        }
+        histogram
+    }
+
+    #[test]
+    fn test_sample_dimensions() {
+        let sample = |dimensions, sampling| sample_dimensions(dimensions, &sampling, 256, 1);
+
+        assert_eq!(
+            sample((1000, 500), Sampling::Auto).unwrap(),
+            Some((256, 128))
+        );
+        assert_eq!(
+            sample((500, 1000), Sampling::Auto).unwrap(),
+            Some((128, 256))
+        );
+        assert_eq!(sample((200, 100), Sampling::Auto).unwrap(), None);
+        assert_eq!(sample((1000, 500), Sampling::Full).unwrap(), None);
+        assert_eq!(
+            sample((1000, 500), Sampling::MaxDimension(100)).unwrap(),
+            Some((100, 50))
+        );
+        // The short side never drops to 0.
+        assert_eq!(
+            sample((10000, 10), Sampling::MaxDimension(100)).unwrap(),
+            Some((100, 1))
+        );
+        assert!(sample((1000, 500), Sampling::MaxDimension(0)).is_err());
+        assert!(sample((1000, 500), Sampling::PixelBudget(0)).is_err());
+    }
+
+    #[test]
+    fn test_sample_dimensions_pixel_budget() {
+        let sample = |dimensions, budget| {
+            sample_dimensions(dimensions, &Sampling::PixelBudget(budget), 256, 1).unwrap()
+        };
+
+        assert_eq!(sample((1000, 500), 500 * 250), Some((500, 250)));
+        assert_eq!(sample((1000, 500), 1000 * 500), None);
+        let (width, height) = sample((1920, 1080), 65536).unwrap();
+        assert!(width * height <= 65536);
+        assert!(width * height > 60000);
+        assert!((width as f32 / height as f32 - 16.0 / 9.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_sample_dimensions_min_pixels() {
+        // One pixel can't hold 4 colors, the sample grows back to at least 4 pixels.
+        let (width, height) = sample_dimensions((1000, 500), &Sampling::PixelBudget(1), 256, 4)
+            .unwrap()
+            .unwrap();
+        assert!(width * height >= 4);
+        assert!(width * height <= 8);
+
+        let (width, height) = sample_dimensions((1000, 500), &Sampling::MaxDimension(1), 256, 16)
+            .unwrap()
+            .unwrap();
+        assert!(width * height >= 16);
+
+        // An image with fewer pixels than asked for is used as is.
+        assert_eq!(
+            sample_dimensions((4, 2), &Sampling::PixelBudget(1), 256, 16).unwrap(),
+            None
+        );
+    }
+}
@@ -0,0 +1,714 @@
+//! Loads and saves palettes as the swatch files of common image editors.
+
+use std::{fs, path::Path};
+
+use anyhow::{anyhow, Result};
+use palette::{IntoColor, Lab, Srgb};
+use rgb::RGBA8;
+use serde_json::Value;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SwatchFormat {
+    /// GIMP palette, `.gpl`
+    Gpl,
+    /// Adobe Swatch Exchange, `.ase`
+    Ase,
+    /// Adobe Color Table, `.act`
+    Act,
+    /// JASC palette, `.pal`
+    Pal,
+    /// Paint.NET palette, `.txt`
+    PaintNet,
+    /// Hex colors, one per line, `.hex`
+    Hex,
+    /// CSS custom properties, `.css`
+    Css,
+    /// Either a list of colors, or an object with a `colors` list, like Lospec's. Colors are
+    /// hex strings, or objects with a `hex` string or `r`, `g`, `b` and optional `a` values.
+    Json,
+}
+
+impl SwatchFormat {
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension.to_ascii_lowercase().as_str() {
+            "gpl" => Some(SwatchFormat::Gpl),
+            "ase" => Some(SwatchFormat::Ase),
+            "act" => Some(SwatchFormat::Act),
+            "pal" => Some(SwatchFormat::Pal),
+            "txt" => Some(SwatchFormat::PaintNet),
+            "hex" => Some(SwatchFormat::Hex),
+            "css" => Some(SwatchFormat::Css),
+            "json" => Some(SwatchFormat::Json),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            SwatchFormat::Gpl => "gpl",
+            SwatchFormat::Ase => "ase",
+            SwatchFormat::Act => "act",
+            SwatchFormat::Pal => "pal",
+            SwatchFormat::PaintNet => "txt",
+            SwatchFormat::Hex => "hex",
+            SwatchFormat::Css => "css",
+            SwatchFormat::Json => "json",
+        }
+    }
+}
+
+/// Loads the colors of a swatch file, guessing its format from the extension.
+pub fn load_swatch<P>(path: P) -> Result<Vec<RGBA8>>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let format = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .and_then(SwatchFormat::from_extension)
+        .ok_or_else(|| anyhow!("Unsupported palette file {}", path.display()))?;
+
+    parse_swatch(&fs::read(path)?, &format)
+}
+
+pub fn parse_swatch(content: &[u8], format: &SwatchFormat) -> Result<Vec<RGBA8>> {
+    let colors = match format {
+        SwatchFormat::Gpl => parse_gpl(std::str::from_utf8(content)?),
+        SwatchFormat::Ase => parse_ase(content),
+        SwatchFormat::Act => parse_act(content),
+        SwatchFormat::Pal => parse_pal(std::str::from_utf8(content)?),
+        SwatchFormat::PaintNet => parse_paint_net(std::str::from_utf8(content)?),
+        SwatchFormat::Hex => parse_hex_lines(std::str::from_utf8(content)?),
+        SwatchFormat::Css => parse_css(std::str::from_utf8(content)?),
+        SwatchFormat::Json => parse_json(std::str::from_utf8(content)?),
+    }?;
+
+    if colors.is_empty() {
+        Err(anyhow!("The palette file doesn't contain any color"))
+    } else {
+        Ok(colors)
+    }
+}
+
+/// Saves the palette as a swatch file, that can be imported in other tools. The name is only
+/// kept by the formats that have one.
+pub fn save_swatch<P>(path: P, palette: &[RGBA8], name: &str, format: &SwatchFormat) -> Result<()>
+where
+    P: AsRef<Path>,
+{
+    fs::write(path, format_swatch(palette, name, format)?)?;
+
+    Ok(())
+}
+
+/// Content of the swatch file of the palette, see [save_swatch].
+pub fn format_swatch(palette: &[RGBA8], name: &str, format: &SwatchFormat) -> Result<Vec<u8>> {
+    Ok(match format {
+        SwatchFormat::Gpl => format_gpl(palette, name).into_bytes(),
+        SwatchFormat::Ase => format_ase(palette),
+        SwatchFormat::Act => format_act(palette)?,
+        SwatchFormat::Pal => format_pal(palette).into_bytes(),
+        SwatchFormat::PaintNet => format_paint_net(palette).into_bytes(),
+        SwatchFormat::Hex => format_hex_lines(palette).into_bytes(),
+        SwatchFormat::Css => format_css(palette).into_bytes(),
+        SwatchFormat::Json => format_json(palette).into_bytes(),
+    })
+}
+
+/// `#RRGGBB`, or `#RRGGBBAA` for translucent colors.
+pub fn hex_color(color: &RGBA8) -> String {
+    if color.a == 255 {
+        format!("#{:02X}{:02X}{:02X}", color.r, color.g, color.b)
+    } else {
+        format!(
+            "#{:02X}{:02X}{:02X}{:02X}",
+            color.r, color.g, color.b, color.a
+        )
+    }
+}
+
+/// Parses `RRGGBB` or `RRGGBBAA`, with an optional leading `#`.
+pub fn parse_hex_color(color: &str) -> Result<RGBA8> {
+    let hex = color.strip_prefix('#').unwrap_or(color);
+    if !(hex.len() == 6 || hex.len() == 8) || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(anyhow!("Invalid hex color {color}"));
+    }
+
+    let channel = |index: usize| u8::from_str_radix(&hex[index..index + 2], 16);
+    Ok(RGBA8 {
+        r: channel(0)?,
+        g: channel(2)?,
+        b: channel(4)?,
+        a: if hex.len() == 8 { channel(6)? } else { 255 },
+    })
+}
+
+fn parse_rgb_line(line: &str) -> Result<RGBA8> {
+    let channels = line
+        .split_whitespace()
+        .take(3)
+        .map(|channel| channel.parse::<u8>())
+        .collect::<Result<Vec<_>, _>>()?;
+
+    match channels[..] {
+        [r, g, b] => Ok(RGBA8 { r, g, b, a: 255 }),
+        _ => Err(anyhow!("Invalid color {line}")),
+    }
+}
+
+fn parse_gpl(content: &str) -> Result<Vec<RGBA8>> {
+    let mut lines = content.lines();
+    if !lines
+        .next()
+        .is_some_and(|line| line.trim().starts_with("GIMP Palette"))
+    {
+        return Err(anyhow!("Not a GIMP palette"));
+    }
+
+    lines
+        .map(str::trim)
+        .filter(|line| {
+            !(line.is_empty()
+                || line.starts_with('#')
+                || line.starts_with("Name:")
+                || line.starts_with("Columns:"))
+        })
+        .map(parse_rgb_line)
+        .collect()
+}
+
+fn parse_pal(content: &str) -> Result<Vec<RGBA8>> {
+    let mut lines = content.lines().map(str::trim);
+    if lines.next() != Some("JASC-PAL") {
+        return Err(anyhow!("Not a JASC palette"));
+    }
+    let _version = lines.next();
+    let count: usize = lines
+        .next()
+        .ok_or_else(|| anyhow!("Missing color count"))?
+        .parse()?;
+
+    let colors = lines
+        .filter(|line| !line.is_empty())
+        .take(count)
+        .map(parse_rgb_line)
+        .collect::<Result<Vec<_>>>()?;
+
+    if colors.len() == count {
+        Ok(colors)
+    } else {
+        Err(anyhow!("Expected {count} colors, found {}", colors.len()))
+    }
+}
+
+fn parse_paint_net(content: &str) -> Result<Vec<RGBA8>> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !(line.is_empty() || line.starts_with(';')))
+        .map(|line| {
+            let argb = parse_hex_color(line)?;
+            if line.len() != 8 {
+                return Err(anyhow!("Invalid Paint.NET color {line}"));
+            }
+            Ok(RGBA8 {
+                r: argb.g,
+                g: argb.b,
+                b: argb.a,
+                a: argb.r,
+            })
+        })
+        .collect()
+}
+
+fn parse_hex_lines(content: &str) -> Result<Vec<RGBA8>> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(parse_hex_color)
+        .collect()
+}
+
+fn parse_css(content: &str) -> Result<Vec<RGBA8>> {
+    content
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("--"))
+        .map(|declaration| {
+            let (_, value) = declaration
+                .split_once(':')
+                .ok_or_else(|| anyhow!("Invalid declaration {declaration}"))?;
+            parse_hex_color(value.trim().trim_end_matches(';').trim())
+        })
+        .collect()
+}
+
+fn parse_json(content: &str) -> Result<Vec<RGBA8>> {
+    let json: Value = serde_json::from_str(content)?;
+    let colors = match &json {
+        Value::Array(colors) => colors,
+        Value::Object(object) => object
+            .get("colors")
+            .and_then(Value::as_array)
+            .ok_or_else(|| anyhow!("Missing colors list"))?,
+        _ => return Err(anyhow!("Expected a list of colors")),
+    };
+
+    colors
+        .iter()
+        .map(|color| match color {
+            Value::String(hex) => parse_hex_color(hex),
+            Value::Object(object) => {
+                if let Some(hex) = object.get("hex").and_then(Value::as_str) {
+                    return parse_hex_color(hex);
+                }
+                let channel = |name: &str| {
+                    object
+                        .get(name)
+                        .and_then(Value::as_u64)
+                        .and_then(|channel| u8::try_from(channel).ok())
+                };
+                match (channel("r"), channel("g"), channel("b")) {
+                    (Some(r), Some(g), Some(b)) => Ok(RGBA8 {
+                        r,
+                        g,
+                        b,
+                        a: channel("a").unwrap_or(255),
+                    }),
+                    _ => Err(anyhow!("Invalid color {color}")),
+                }
+            }
+            _ => Err(anyhow!("Invalid color {color}")),
+        })
+        .collect()
+}
+
+fn parse_act(content: &[u8]) -> Result<Vec<RGBA8>> {
+    let (count, transparent_index) = match content.len() {
+        768 => (256, None),
+        772 => (
+            u16::from_be_bytes([content[768], content[769]]) as usize,
+            Some(u16::from_be_bytes([content[770], content[771]]) as usize),
+        ),
+        _ => return Err(anyhow!("Not an Adobe Color Table")),
+    };
+
+    Ok(content[..768]
+        .chunks_exact(3)
+        .take(count.min(256))
+        .enumerate()
+        .map(|(index, rgb)| RGBA8 {
+            r: rgb[0],
+            g: rgb[1],
+            b: rgb[2],
+            a: if Some(index) == transparent_index {
+                0
+            } else {
+                255
+            },
+        })
+        .collect())
+}
+
+/// See http://www.selapa.net/swatches/colors/fileformats.php#adobe_ase
+fn parse_ase(content: &[u8]) -> Result<Vec<RGBA8>> {
+    let mut reader = Reader { content, offset: 0 };
+    if reader.bytes(4)? != b"ASEF" {
+        return Err(anyhow!("Not an Adobe Swatch Exchange file"));
+    }
+    let _version = reader.bytes(4)?;
+    let block_count = reader.u32()?;
+
+    let mut colors = vec![];
+    for _ in 0..block_count {
+        let block_type = reader.u16()?;
+        let block_length = reader.u32()? as usize;
+        let mut block = Reader {
+            content: reader.bytes(block_length)?,
+            offset: 0,
+        };
+        // Group start and end blocks only hold a name.
+        if block_type != 0x0001 {
+            continue;
+        }
+
+        let name_length = block.u16()? as usize;
+        let _name = block.bytes(name_length * 2)?;
+        let model = block.bytes(4)?;
+        let rgb = match model {
+            b"RGB " => [block.f32()?, block.f32()?, block.f32()?],
+            b"CMYK" => {
+                let [c, m, y, k] = [block.f32()?, block.f32()?, block.f32()?, block.f32()?];
+                [
+                    (1.0 - c) * (1.0 - k),
+                    (1.0 - m) * (1.0 - k),
+                    (1.0 - y) * (1.0 - k),
+                ]
+            }
+            b"Gray" => [block.f32()?; 3],
+            b"LAB " => {
+                let lab = Lab::new(block.f32()? * 100.0, block.f32()?, block.f32()?);
+                let rgb: Srgb = lab.into_color();
+                [rgb.red, rgb.green, rgb.blue]
+            }
+            _ => return Err(anyhow!("Unsupported color model {model:?}")),
+        };
+        let [r, g, b] = rgb.map(|channel| (channel.clamp(0.0, 1.0) * 255.0).round() as u8);
+        colors.push(RGBA8 { r, g, b, a: 255 });
+    }
+
+    Ok(colors)
+}
+
+/// GIMP palette.
+fn format_gpl(palette: &[RGBA8], name: &str) -> String {
+    let mut content = format!("GIMP Palette\nName: {name}\n#\n");
+    for color in palette {
+        content.push_str(&format!(
+            "{:3} {:3} {:3}\t{}\n",
+            color.r,
+            color.g,
+            color.b,
+            hex_color(color)
+        ));
+    }
+    content
+}
+
+/// Adobe Swatch Exchange, see http://www.selapa.net/swatches/colors/fileformats.php#adobe_ase
+fn format_ase(palette: &[RGBA8]) -> Vec<u8> {
+    let mut content = vec![];
+    content.extend_from_slice(b"ASEF");
+    content.extend_from_slice(&1u16.to_be_bytes());
+    content.extend_from_slice(&0u16.to_be_bytes());
+    content.extend_from_slice(&(palette.len() as u32).to_be_bytes());
+
+    for color in palette {
+        // Null terminated UTF-16 name.
+        let name: Vec<u16> = hex_color(color).encode_utf16().chain([0]).collect();
+
+        let mut block = vec![];
+        block.extend_from_slice(&(name.len() as u16).to_be_bytes());
+        for character in name {
+            block.extend_from_slice(&character.to_be_bytes());
+        }
+        block.extend_from_slice(b"RGB ");
+        for channel in [color.r, color.g, color.b] {
+            block.extend_from_slice(&(channel as f32 / 255.0).to_be_bytes());
+        }
+        // Normal color, as opposed to global or spot.
+        block.extend_from_slice(&2u16.to_be_bytes());
+
+        content.extend_from_slice(&1u16.to_be_bytes());
+        content.extend_from_slice(&(block.len() as u32).to_be_bytes());
+        content.extend_from_slice(&block);
+    }
+
+    content
+}
+
+/// Adobe Color Table: 256 RGB entries, followed by the color count and the transparent index.
+fn format_act(palette: &[RGBA8]) -> Result<Vec<u8>> {
+    if palette.len() > 256 {
+        return Err(anyhow!(
+            "An act palette is limited to 256 colors, got {}",
+            palette.len()
+        ));
+    }
+
+    let mut content = vec![0; 768];
+    for (entry, color) in content.chunks_exact_mut(3).zip(palette) {
+        entry.copy_from_slice(&[color.r, color.g, color.b]);
+    }
+    let transparent_index = palette
+        .iter()
+        .position(|color| color.a == 0)
+        .map_or(0xFFFF, |index| index as u16);
+    content.extend_from_slice(&(palette.len() as u16).to_be_bytes());
+    content.extend_from_slice(&transparent_index.to_be_bytes());
+
+    Ok(content)
+}
+
+/// JASC palette, as used by Paint Shop Pro.
+fn format_pal(palette: &[RGBA8]) -> String {
+    let mut content = format!("JASC-PAL\r\n0100\r\n{}\r\n", palette.len());
+    for color in palette {
+        content.push_str(&format!("{} {} {}\r\n", color.r, color.g, color.b));
+    }
+    content
+}
+
+/// Paint.NET palette, with colors as `AARRGGBB`.
+fn format_paint_net(palette: &[RGBA8]) -> String {
+    let mut content = format!("; paint.net Palette File\n; Colors: {}\n", palette.len());
+    for color in palette {
+        content.push_str(&format!(
+            "{:02X}{:02X}{:02X}{:02X}\n",
+            color.a, color.r, color.g, color.b
+        ));
+    }
+    content
+}
+
+/// One color per line, without the leading `#`.
+fn format_hex_lines(palette: &[RGBA8]) -> String {
+    palette
+        .iter()
+        .map(|color| format!("{}\n", &hex_color(color)[1..]))
+        .collect()
+}
+
+fn format_css(palette: &[RGBA8]) -> String {
+    let mut content = String::from(":root {\n");
+    for (index, color) in palette.iter().enumerate() {
+        content.push_str(&format!("  --color-{index}: {};\n", hex_color(color)));
+    }
+    content.push_str("}\n");
+    content
+}
+
+fn format_json(palette: &[RGBA8]) -> String {
+    let colors = palette
+        .iter()
+        .map(|color| {
+            format!(
+                "    {{ \"hex\": \"{}\", \"r\": {}, \"g\": {}, \"b\": {}, \"a\": {} }}",
+                hex_color(color),
+                color.r,
+                color.g,
+                color.b,
+                color.a
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(",\n");
+    format!("{{\n  \"colors\": [\n{colors}\n  ]\n}}\n")
+}
+
+struct Reader<'a> {
+    content: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, length: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .content
+            .get(self.offset..self.offset + length)
+            .ok_or_else(|| anyhow!("Unexpected end of file"))?;
+        self.offset += length;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_be_bytes(self.bytes(2)?.try_into()?))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.bytes(4)?.try_into()?))
+    }
+
+    fn f32(&mut self) -> Result<f32> {
+        Ok(f32::from_be_bytes(self.bytes(4)?.try_into()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rgb::RGBA8;
+
+    use super::{
+        format_act, format_ase, format_css, format_gpl, format_swatch, parse_swatch, SwatchFormat,
+    };
+
+    const ORANGE: RGBA8 = RGBA8 {
+        r: 255,
+        g: 128,
+        b: 0,
+        a: 255,
+    };
+    const BLACK: RGBA8 = RGBA8 {
+        r: 0,
+        g: 0,
+        b: 0,
+        a: 255,
+    };
+
+    const PALETTE: [RGBA8; 2] = [
+        ORANGE,
+        RGBA8 {
+            r: 0,
+            g: 0,
+            b: 0,
+            a: 0,
+        },
+    ];
+
+    fn parse(content: &[u8], format: SwatchFormat) -> Vec<RGBA8> {
+        parse_swatch(content, &format).unwrap()
+    }
+
+    #[test]
+    fn test_parse_gpl() {
+        let content =
+            b"GIMP Palette\nName: Test\nColumns: 4\n#\n255 128   0\tOrange\n  0   0   0\n";
+
+        assert_eq!(parse(content, SwatchFormat::Gpl), [ORANGE, BLACK]);
+        assert!(parse_swatch(b"255 128 0", &SwatchFormat::Gpl).is_err());
+    }
+
+    #[test]
+    fn test_parse_pal() {
+        let content = b"JASC-PAL\r\n0100\r\n2\r\n255 128 0\r\n0 0 0\r\n";
+
+        assert_eq!(parse(content, SwatchFormat::Pal), [ORANGE, BLACK]);
+    }
+
+    #[test]
+    fn test_parse_paint_net() {
+        let content = b"; paint.net Palette File\n; Colors: 2\nFFFF8000\n00000000\n";
+
+        assert_eq!(
+            parse(content, SwatchFormat::PaintNet),
+            [ORANGE, RGBA8::new(0, 0, 0, 0)]
+        );
+    }
+
+    #[test]
+    fn test_parse_hex() {
+        assert_eq!(
+            parse(b"ff8000\n#000000\n\n", SwatchFormat::Hex),
+            [ORANGE, BLACK]
+        );
+        assert!(parse_swatch(b"ff80", &SwatchFormat::Hex).is_err());
+    }
+
+    #[test]
+    fn test_parse_json() {
+        assert_eq!(
+            parse(
+                br#"{"name": "Lospec", "colors": ["ff8000", "000000"]}"#,
+                SwatchFormat::Json
+            ),
+            [ORANGE, BLACK]
+        );
+        assert_eq!(
+            parse(
+                br##"[{"hex": "#FF8000"}, {"r": 0, "g": 0, "b": 0}]"##,
+                SwatchFormat::Json
+            ),
+            [ORANGE, BLACK]
+        );
+    }
+
+    #[test]
+    fn test_parse_act() {
+        let mut content = vec![0; 772];
+        content[..3].copy_from_slice(&[255, 128, 0]);
+        content[768..].copy_from_slice(&[0, 2, 0, 1]);
+
+        assert_eq!(
+            parse(&content, SwatchFormat::Act),
+            [ORANGE, RGBA8::new(0, 0, 0, 0)]
+        );
+        assert_eq!(parse(&content[..768], SwatchFormat::Act).len(), 256);
+    }
+
+    #[test]
+    fn test_parse_ase() {
+        let mut content = b"ASEF\x00\x01\x00\x00\x00\x00\x00\x01".to_vec();
+        content.extend_from_slice(&[0, 1, 0, 0, 0, 24]);
+        // Name "o", null terminated.
+        content.extend_from_slice(&[0, 2, 0, b'o', 0, 0]);
+        content.extend_from_slice(b"RGB ");
+        for channel in [1.0f32, 128.0 / 255.0, 0.0] {
+            content.extend_from_slice(&channel.to_be_bytes());
+        }
+        content.extend_from_slice(&[0, 2]);
+
+        assert_eq!(parse(&content, SwatchFormat::Ase), [ORANGE]);
+    }
+
+    #[test]
+    fn test_format_gpl() {
+        assert_eq!(
+            format_gpl(&PALETTE, "tokyo"),
+            "GIMP Palette\nName: tokyo\n#\n255 128   0\t#FF8000\n  0   0   0\t#00000000\n"
+        );
+    }
+
+    #[test]
+    fn test_format_ase() {
+        let content = format_ase(&PALETTE);
+
+        assert_eq!(&content[0..12], b"ASEF\x00\x01\x00\x00\x00\x00\x00\x02");
+        // Block type, then a block length of 2 + 8 * 2 + 4 + 3 * 4 + 2 bytes.
+        assert_eq!(&content[12..18], &[0, 1, 0, 0, 0, 36]);
+        assert_eq!(content.len(), 12 + 6 + 36 + 6 + 40);
+    }
+
+    #[test]
+    fn test_format_act() {
+        let content = format_act(&PALETTE).unwrap();
+
+        assert_eq!(content.len(), 772);
+        assert_eq!(&content[0..6], &[255, 128, 0, 0, 0, 0]);
+        assert_eq!(&content[768..], &[0, 2, 0, 1]);
+        assert!(format_act(&[PALETTE[0]; 257]).is_err());
+    }
+
+    #[test]
+    fn test_format_css() {
+        assert_eq!(
+            format_css(&PALETTE),
+            ":root {\n  --color-0: #FF8000;\n  --color-1: #00000000;\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_parse_css() {
+        assert_eq!(
+            parse(
+                b":root {\n  --color-0: #FF8000;\n  --color-1:#000000\n}\n",
+                SwatchFormat::Css
+            ),
+            [ORANGE, BLACK]
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let opaque = [ORANGE, BLACK, RGBA8::new(18, 52, 86, 255)];
+        let translucent = [ORANGE, RGBA8::new(18, 52, 86, 128), RGBA8::new(0, 0, 0, 0)];
+
+        for format in [
+            SwatchFormat::Gpl,
+            SwatchFormat::Ase,
+            SwatchFormat::Act,
+            SwatchFormat::Pal,
+            SwatchFormat::PaintNet,
+            SwatchFormat::Hex,
+            SwatchFormat::Css,
+            SwatchFormat::Json,
+        ] {
+            let content = format_swatch(&opaque, "test", &format).unwrap();
+            assert_eq!(parse(&content, format), opaque, "{format:?}");
+            assert_eq!(
+                SwatchFormat::from_extension(format.extension()),
+                Some(format)
+            );
+        }
+        // Only some formats have an alpha channel.
+        for format in [
+            SwatchFormat::PaintNet,
+            SwatchFormat::Hex,
+            SwatchFormat::Css,
+            SwatchFormat::Json,
+        ] {
+            let content = format_swatch(&translucent, "test", &format).unwrap();
+            assert_eq!(parse(&content, format), translucent, "{format:?}");
+        }
+    }
+}
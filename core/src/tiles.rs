@@ -0,0 +1,196 @@
+/// Rounds of refinement, tiles rarely keep switching sub-palettes for long.
+const MAX_ROUNDS: usize = 32;
+
+/// Splits a palette into at most `sub_palette_count` sub-palettes of `colors_per_tile` colors, and
+/// picks one for each tile, so that the pixels of each tile stay as close as possible to the color
+/// they got from the whole palette.
+///
+/// Each tile is described by its histogram, how many of its pixels got each color of the palette,
+/// and `distances` holds the distance between every two colors of the palette. Like k-means, the
+/// tiles go to the sub-palette that fits them best, then each sub-palette is rebuilt from its
+/// tiles, until no tile moves. Returns the colors of each sub-palette, as indices in the palette,
+/// and the sub-palette of each tile.
+pub(crate) fn group_tiles(
+    histograms: &[Vec<u32>],
+    distances: &[Vec<f32>],
+    colors_per_tile: usize,
+    sub_palette_count: usize,
+) -> (Vec<Vec<usize>>, Vec<usize>) {
+    let mut sub_palettes =
+        initial_sub_palettes(histograms, distances, colors_per_tile, sub_palette_count);
+    let mut assignments = assign(histograms, distances, &sub_palettes);
+
+    for _ in 0..MAX_ROUNDS {
+        for (index, sub_palette) in sub_palettes.iter_mut().enumerate() {
+            let mut merged = vec![0; distances.len()];
+            for (histogram, _) in histograms
+                .iter()
+                .zip(&assignments)
+                .filter(|(_, &assignment)| assignment == index)
+            {
+                for (total, count) in merged.iter_mut().zip(histogram) {
+                    *total += count;
+                }
+            }
+            // A sub-palette left without tiles keeps its colors, tiles may come back to it.
+            if merged.iter().any(|&count| count > 0) {
+                *sub_palette = pick_colors(&merged, distances, colors_per_tile);
+            }
+        }
+
+        let next = assign(histograms, distances, &sub_palettes);
+        if next == assignments {
+            break;
+        }
+        assignments = next;
+    }
+
+    (sub_palettes, assignments)
+}
+
+/// The first sub-palette fits the whole image, then each new one fits the tile the others fit the
+/// worst, until there are enough of them or every tile fits perfectly.
+fn initial_sub_palettes(
+    histograms: &[Vec<u32>],
+    distances: &[Vec<f32>],
+    colors_per_tile: usize,
+    sub_palette_count: usize,
+) -> Vec<Vec<usize>> {
+    let mut total = vec![0; distances.len()];
+    for histogram in histograms {
+        for (total, count) in total.iter_mut().zip(histogram) {
+            *total += count;
+        }
+    }
+
+    let mut sub_palettes = vec![pick_colors(&total, distances, colors_per_tile)];
+    while sub_palettes.len() < sub_palette_count {
+        let worst = histograms
+            .iter()
+            .map(|histogram| {
+                sub_palettes
+                    .iter()
+                    .map(|sub_palette| error(histogram, distances, sub_palette))
+                    .fold(f64::MAX, f64::min)
+            })
+            .enumerate()
+            .max_by(|(_, a), (_, b)| a.total_cmp(b));
+        match worst {
+            Some((tile, error)) if error > 0.0 => {
+                sub_palettes.push(pick_colors(&histograms[tile], distances, colors_per_tile))
+            }
+            _ => break,
+        }
+    }
+    sub_palettes
+}
+
+/// The sub-palette fitting each tile best.
+fn assign(
+    histograms: &[Vec<u32>],
+    distances: &[Vec<f32>],
+    sub_palettes: &[Vec<usize>],
+) -> Vec<usize> {
+    histograms
+        .iter()
+        .map(|histogram| {
+            sub_palettes
+                .iter()
+                .map(|sub_palette| error(histogram, distances, sub_palette))
+                .enumerate()
+                .min_by(|(_, a), (_, b)| a.total_cmp(b))
+                .map_or(0, |(index, _)| index)
+        })
+        .collect()
+}
+
+/// Sum of the distances between each pixel and the closest color of the sub-palette.
+fn error(histogram: &[u32], distances: &[Vec<f32>], sub_palette: &[usize]) -> f64 {
+    histogram
+        .iter()
+        .enumerate()
+        .filter(|(_, &count)| count > 0)
+        .map(|(color, &count)| {
+            let distance = sub_palette
+                .iter()
+                .map(|&other| distances[color][other])
+                .fold(f32::MAX, f32::min);
+            count as f64 * distance as f64
+        })
+        .sum()
+}
+
+/// Greedily picks the colors bringing the pixels of the histogram the closest to their color,
+/// sorted by index.
+fn pick_colors(histogram: &[u32], distances: &[Vec<f32>], color_count: usize) -> Vec<usize> {
+    let used: Vec<_> = histogram
+        .iter()
+        .enumerate()
+        .filter(|(_, &count)| count > 0)
+        .map(|(color, &count)| (color, count as f64))
+        .collect();
+    let mut nearest = vec![f32::MAX; histogram.len()];
+    let mut picked = vec![];
+
+    while picked.len() < color_count.min(histogram.len()) {
+        let cost = |candidate: usize| -> f64 {
+            used.iter()
+                .map(|&(color, count)| {
+                    count * nearest[color].min(distances[color][candidate]) as f64
+                })
+                .sum()
+        };
+        let Some(best) = (0..histogram.len())
+            .filter(|candidate| !picked.contains(candidate))
+            .map(|candidate| (candidate, cost(candidate)))
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(candidate, _)| candidate)
+        else {
+            break;
+        };
+
+        for (color, nearest) in nearest.iter_mut().enumerate() {
+            *nearest = nearest.min(distances[color][best]);
+        }
+        picked.push(best);
+    }
+
+    picked.sort_unstable();
+    picked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_group_tiles() {
+        let values = [0.0f32, 10.0, 100.0, 110.0];
+        let distances: Vec<Vec<f32>> = values
+            .iter()
+            .map(|a| values.iter().map(|b| (a - b).abs()).collect())
+            .collect();
+        let histograms = vec![
+            vec![8, 8, 0, 0],
+            vec![0, 0, 8, 8],
+            vec![16, 0, 0, 0],
+            vec![0, 0, 4, 12],
+        ];
+
+        let (sub_palettes, assignments) = group_tiles(&histograms, &distances, 2, 2);
+        assert_eq!(sub_palettes.len(), 2);
+        assert_eq!(sub_palettes[assignments[0]], vec![0, 1]);
+        assert_eq!(sub_palettes[assignments[1]], vec![2, 3]);
+        assert_eq!(assignments[2], assignments[0]);
+        assert_eq!(assignments[3], assignments[1]);
+
+        // A single sub-palette keeps one color on each side.
+        let (sub_palettes, assignments) = group_tiles(&histograms, &distances, 2, 1);
+        assert_eq!(sub_palettes, vec![vec![1, 3]]);
+        assert_eq!(assignments, vec![0; 4]);
+
+        // One sub-palette is enough when every tile fits in it.
+        let (sub_palettes, _) = group_tiles(&histograms[..1], &distances, 2, 4);
+        assert_eq!(sub_palettes, vec![vec![0, 1]]);
+    }
+}
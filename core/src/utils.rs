@@ -2,8 +2,8 @@ pub(crate) fn compute_work_group_count(
     (width, height): (u32, u32),
     (workgroup_width, workgroup_height): (u32, u32),
 ) -> (u32, u32) {
-    let x = (width + workgroup_width - 1) / workgroup_width;
-    let y = (height + workgroup_height - 1) / workgroup_height;
+    let x = width.div_ceil(workgroup_width);
+    let y = height.div_ceil(workgroup_height);
 
     (x, y)
 }
@@ -13,3 +13,12 @@ pub(crate) fn padded_bytes_per_row(bytes_per_row: u64) -> u64 {
     let padding = (256 - bytes_per_row % 256) % 256;
     bytes_per_row + padding
 }
+
+/// Spreads a seed over the whole u32 range, using the SplitMix64 finalizer. Integer only, so it
+/// gives the same result on every platform.
+pub(crate) fn hash_seed(seed: u64) -> u32 {
+    let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    (z ^ (z >> 31)) as u32
+}
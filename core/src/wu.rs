@@ -0,0 +1,221 @@
+use rgb::RGBA8;
+
+/// Histogram bins per channel, plus a leading empty slice so that cumulative moments can be
+/// looked up at index 0.
+const SIDE: usize = 33;
+
+/// Pixel count, sum of each channel, and sum of the squared channels.
+type Moment = [f64; 5];
+
+/// A box of bins, from `lower` excluded to `upper` included, on each axis.
+#[derive(Clone, Copy)]
+struct Cube {
+    lower: [usize; 3],
+    upper: [usize; 3],
+}
+
+impl Cube {
+    fn volume(&self) -> usize {
+        (0..3)
+            .map(|axis| self.upper[axis] - self.lower[axis])
+            .product()
+    }
+}
+
+/// Xiaolin Wu's quantizer, from "Efficient Statistical Computations for Optimal Color
+/// Quantization". Boxes of the histogram are cut in two, picking the cut that minimizes the sum
+/// of the variances, and the box with the largest variance is cut next.
+///
+/// The histogram is the one filled by `histogram.wgsl`, 5 bits per channel. It doesn't keep the
+/// squared colors of each pixel, so the variance within a bin is left out: it only affects which
+/// box is cut next, as cuts are picked from the color sums.
+pub(crate) fn wu(histogram: &[[u32; 4]], color_count: u32) -> Vec<RGBA8> {
+    let moments = cumulative_moments(histogram);
+
+    let mut cubes = vec![Cube {
+        lower: [0; 3],
+        upper: [SIDE - 1; 3],
+    }];
+    let mut variances = vec![variance(&moments, &cubes[0])];
+
+    while cubes.len() < color_count as usize {
+        let (next, &next_variance) = variances
+            .iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .expect("There is always one cube");
+        if next_variance <= 0.0 {
+            break;
+        }
+
+        match cut(&moments, &cubes[next]) {
+            Some((first, second)) => {
+                cubes[next] = first;
+                variances[next] = variance(&moments, &first);
+                cubes.push(second);
+                variances.push(variance(&moments, &second));
+            }
+            None => variances[next] = 0.0,
+        }
+    }
+
+    cubes
+        .iter()
+        .filter_map(|cube| {
+            let [weight, r, g, b, _] = volume(&moments, cube);
+            (weight > 0.0).then(|| RGBA8 {
+                r: (r / weight).round() as u8,
+                g: (g / weight).round() as u8,
+                b: (b / weight).round() as u8,
+                a: 255,
+            })
+        })
+        .collect()
+}
+
+fn index(r: usize, g: usize, b: usize) -> usize {
+    (r * SIDE + g) * SIDE + b
+}
+
+/// Moments of the boxes going from the origin to each bin.
+fn cumulative_moments(histogram: &[[u32; 4]]) -> Vec<Moment> {
+    let mut moments = vec![[0.0; 5]; SIDE * SIDE * SIDE];
+    for (bin_index, &[count, r, g, b]) in histogram.iter().enumerate() {
+        if count == 0 {
+            continue;
+        }
+        let bin = [
+            (bin_index >> 10) & 31,
+            (bin_index >> 5) & 31,
+            bin_index & 31,
+        ];
+        let count = count as f64;
+        let low_bits = [r, g, b];
+        let sums =
+            [0, 1, 2].map(|channel| (bin[channel] << 3) as f64 * count + low_bits[channel] as f64);
+        let squares = sums.iter().map(|sum| sum * sum / count).sum::<f64>();
+
+        moments[index(bin[0] + 1, bin[1] + 1, bin[2] + 1)] =
+            [count, sums[0], sums[1], sums[2], squares];
+    }
+
+    for r in 1..SIDE {
+        let mut area = [[0.0; 5]; SIDE];
+        for g in 1..SIDE {
+            let mut line = [0.0; 5];
+            for b in 1..SIDE {
+                let current = index(r, g, b);
+                for moment in 0..5 {
+                    line[moment] += moments[current][moment];
+                    area[b][moment] += line[moment];
+                    moments[current][moment] =
+                        moments[index(r - 1, g, b)][moment] + area[b][moment];
+                }
+            }
+        }
+    }
+
+    moments
+}
+
+fn volume(moments: &[Moment], cube: &Cube) -> Moment {
+    let [r0, g0, b0] = cube.lower;
+    let [r1, g1, b1] = cube.upper;
+    let corners = [
+        (index(r1, g1, b1), 1.0),
+        (index(r1, g1, b0), -1.0),
+        (index(r1, g0, b1), -1.0),
+        (index(r1, g0, b0), 1.0),
+        (index(r0, g1, b1), -1.0),
+        (index(r0, g1, b0), 1.0),
+        (index(r0, g0, b1), 1.0),
+        (index(r0, g0, b0), -1.0),
+    ];
+
+    let mut volume = [0.0; 5];
+    for (corner, sign) in corners {
+        for (value, moment) in volume.iter_mut().zip(moments[corner]) {
+            *value += sign * moment;
+        }
+    }
+    volume
+}
+
+/// Sum of the squared distances between the pixels of the cube and their mean.
+fn variance(moments: &[Moment], cube: &Cube) -> f64 {
+    if cube.volume() <= 1 {
+        return 0.0;
+    }
+
+    let [weight, r, g, b, squares] = volume(moments, cube);
+    if weight == 0.0 {
+        return 0.0;
+    }
+    squares - (r * r + g * g + b * b) / weight
+}
+
+/// Cuts the cube where the two halves are the most homogeneous, or `None` if it can't be cut.
+fn cut(moments: &[Moment], cube: &Cube) -> Option<(Cube, Cube)> {
+    let whole = volume(moments, cube);
+
+    let mut best: Option<(f64, usize, usize)> = None;
+    for axis in 0..3 {
+        for position in cube.lower[axis] + 1..cube.upper[axis] {
+            let mut half = *cube;
+            half.upper[axis] = position;
+            let [weight, r, g, b, _] = volume(moments, &half);
+            let other_weight = whole[0] - weight;
+            if weight == 0.0 || other_weight == 0.0 {
+                continue;
+            }
+            let (other_r, other_g, other_b) = (whole[1] - r, whole[2] - g, whole[3] - b);
+
+            // Maximizing this minimizes the summed variance of both halves.
+            let score = (r * r + g * g + b * b) / weight
+                + (other_r * other_r + other_g * other_g + other_b * other_b) / other_weight;
+            if best.is_none_or(|(best_score, _, _)| score > best_score) {
+                best = Some((score, axis, position));
+            }
+        }
+    }
+
+    best.map(|(_, axis, position)| {
+        let mut first = *cube;
+        let mut second = *cube;
+        first.upper[axis] = position;
+        second.lower[axis] = position;
+        (first, second)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structures::{tests::histogram, HISTOGRAM_BIN_COUNT};
+
+    #[test]
+    fn test_wu() {
+        let histogram = histogram(&[
+            ([0, 0, 0], 10),
+            ([10, 0, 0], 10),
+            ([255, 255, 255], 5),
+            ([250, 0, 0], 5),
+        ]);
+
+        let mut colors = wu(&histogram, 3);
+        colors.sort_by_key(|color| (color.r, color.g));
+        assert_eq!(
+            colors,
+            vec![
+                RGBA8::new(5, 0, 0, 255),
+                RGBA8::new(250, 0, 0, 255),
+                RGBA8::new(255, 255, 255, 255),
+            ]
+        );
+
+        assert_eq!(wu(&histogram, 4).len(), 4);
+        // There are only 4 colors to split.
+        assert_eq!(wu(&histogram, 8).len(), 4);
+        assert!(wu(&vec![[0; 4]; HISTOGRAM_BIN_COUNT], 8).is_empty());
+    }
+}